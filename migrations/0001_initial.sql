@@ -1,9 +1,9 @@
--- This file is almost the same as `scrapy.sql` but contains only the necessary to initialize the database once.
--- also, it doesn't hardcode the database name or user so the values are free to be defined in `docker-compose.yml`
+-- Schema of the databases created before the migrations. Every statement is
+-- skipped on them, the changes made since are in the following migrations.
 
-CREATE EXTENSION unaccent;
+CREATE EXTENSION IF NOT EXISTS unaccent;
 
-CREATE TABLE "seller" (
+CREATE TABLE IF NOT EXISTS "seller" (
   "seller_id" integer PRIMARY KEY,
   "seller_name" text,
   "seller_url" text,
@@ -11,7 +11,7 @@ CREATE TABLE "seller" (
   "seller_is_pro" boolean
 );
 
-CREATE TABLE "okkazeo_announce" (
+CREATE TABLE IF NOT EXISTS "okkazeo_announce" (
   "oa_id" integer UNIQUE NOT NULL,
   "oa_last_modification_date" timestamptz NOT NULL,
   "oa_name" text NOT NULL,
@@ -25,7 +25,7 @@ CREATE TABLE "okkazeo_announce" (
   "oa_nbr_player" integer
 );
 
-CREATE TABLE "deal" (
+CREATE TABLE IF NOT EXISTS "deal" (
   "deal_id" SERIAL PRIMARY KEY,
   "deal_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
   "deal_price" integer,
@@ -33,7 +33,7 @@ CREATE TABLE "deal" (
 );
 
 
-CREATE TABLE "reference" (
+CREATE TABLE IF NOT EXISTS "reference" (
   "ref_id" SERIAL PRIMARY KEY,
   "ref_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
   "ref_name" text,
@@ -42,7 +42,7 @@ CREATE TABLE "reference" (
   "ref_available" boolean
 );
 
-CREATE TABLE "reviewer" (
+CREATE TABLE IF NOT EXISTS "reviewer" (
   "reviewer_id" SERIAL PRIMARY KEY,
   "reviewer_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
   "reviewer_name" text,
@@ -51,7 +51,7 @@ CREATE TABLE "reviewer" (
   "reviewer_number" integer
 );
 
-CREATE TABLE "shipping" (
+CREATE TABLE IF NOT EXISTS "shipping" (
   "ship_id" SERIAL PRIMARY KEY,
   "ship_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
   "ship_shipper" text,
@@ -59,9 +59,9 @@ CREATE TABLE "shipping" (
 );
 
 
-CREATE INDEX idx_deal_oa_id ON deal (deal_oa_id);
-CREATE INDEX idx_oa_id ON okkazeo_announce (oa_id);
-CREATE INDEX idx_reference_oa_id ON reference (ref_oa_id);
-CREATE INDEX idx_reviewer_oa_id ON reviewer (reviewer_oa_id);
-CREATE INDEX idx_ship_oa_id ON shipping (ship_oa_id);
-CREATE INDEX idx_seller ON seller (seller_id);
+CREATE INDEX IF NOT EXISTS idx_deal_oa_id ON deal (deal_oa_id);
+CREATE INDEX IF NOT EXISTS idx_oa_id ON okkazeo_announce (oa_id);
+CREATE INDEX IF NOT EXISTS idx_reference_oa_id ON reference (ref_oa_id);
+CREATE INDEX IF NOT EXISTS idx_reviewer_oa_id ON reviewer (reviewer_oa_id);
+CREATE INDEX IF NOT EXISTS idx_ship_oa_id ON shipping (ship_oa_id);
+CREATE INDEX IF NOT EXISTS idx_seller ON seller (seller_id);
@@ -0,0 +1,2 @@
+-- Resellers which did not answer before their deadline, retried at the next csv
+ALTER TABLE okkazeo_announce ADD COLUMN IF NOT EXISTS "oa_timed_out_resellers" text[];
@@ -0,0 +1,2 @@
+-- Price of a reference with the cheapest delivery of its shop
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_landed_price" real;
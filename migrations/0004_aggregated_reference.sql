@@ -0,0 +1,2 @@
+-- References found through a price comparator rather than on the shop
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_aggregated" boolean;
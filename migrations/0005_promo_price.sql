@@ -0,0 +1,5 @@
+-- Sale prices of the resellers and the reference a deal is computed with
+ALTER TABLE deal ADD COLUMN IF NOT EXISTS "deal_reference" text;
+ALTER TABLE deal ADD COLUMN IF NOT EXISTS "deal_promo" boolean;
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_promo_price" real;
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_promo_end" date;
@@ -0,0 +1,2 @@
+-- How well the product title of a reference matches the announce
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_match_score" real;
@@ -0,0 +1,17 @@
+-- Catalogue of the games sold new, announces of the same game share its references and reviews
+CREATE TABLE IF NOT EXISTS "game" (
+  "game_id" SERIAL PRIMARY KEY,
+  "game_title" text NOT NULL,
+  "game_key" text NOT NULL,
+  "game_barcode" bigint UNIQUE,
+  "game_bgg_id" integer UNIQUE,
+  "game_updated" timestamptz NOT NULL
+);
+
+ALTER TABLE okkazeo_announce ADD COLUMN IF NOT EXISTS "oa_game_id" integer REFERENCES game("game_id") ON DELETE SET NULL;
+ALTER TABLE reference ADD COLUMN IF NOT EXISTS "ref_game_id" integer REFERENCES game("game_id") ON DELETE CASCADE;
+ALTER TABLE reviewer ADD COLUMN IF NOT EXISTS "reviewer_game_id" integer REFERENCES game("game_id") ON DELETE CASCADE;
+
+CREATE INDEX IF NOT EXISTS idx_game_key ON game (game_key);
+CREATE INDEX IF NOT EXISTS idx_reference_game_id ON reference (ref_game_id);
+CREATE INDEX IF NOT EXISTS idx_reviewer_game_id ON reviewer (reviewer_game_id);
@@ -0,0 +1,12 @@
+-- Description of the games from the bgg XML API, editions of a game share its bgg id
+ALTER TABLE game DROP CONSTRAINT IF EXISTS game_game_bgg_id_key;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_year" integer;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_min_players" integer;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_max_players" integer;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_playing_time" integer;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_min_age" integer;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_weight" real;
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_categories" text[];
+ALTER TABLE game ADD COLUMN IF NOT EXISTS "game_mechanics" text[];
+
+CREATE INDEX IF NOT EXISTS idx_game_bgg_id ON game (game_bgg_id);
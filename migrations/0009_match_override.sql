@@ -0,0 +1,13 @@
+-- Manual corrections of the product matched by a reseller, a NULL url means no match
+CREATE TABLE IF NOT EXISTS "match_override" (
+  "mo_id" SERIAL PRIMARY KEY,
+  "mo_key" text,
+  "mo_barcode" bigint,
+  "mo_reseller" text NOT NULL,
+  "mo_url" text,
+  "mo_created" timestamptz NOT NULL DEFAULT now(),
+  CHECK ("mo_key" IS NOT NULL OR "mo_barcode" IS NOT NULL)
+);
+
+CREATE INDEX IF NOT EXISTS idx_match_override_key ON match_override (mo_key);
+CREATE INDEX IF NOT EXISTS idx_match_override_barcode ON match_override (mo_barcode);
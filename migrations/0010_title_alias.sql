@@ -0,0 +1,9 @@
+-- Other names of a game learned from barcode lookups, the seeded ones are in aliases.json
+CREATE TABLE IF NOT EXISTS "title_alias" (
+  "alias_id" SERIAL PRIMARY KEY,
+  "alias_key" text NOT NULL,
+  "alias_name" text NOT NULL,
+  "alias_source" text NOT NULL,
+  "alias_created" timestamptz NOT NULL DEFAULT now(),
+  UNIQUE ("alias_key", "alias_name")
+);
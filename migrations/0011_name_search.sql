@@ -0,0 +1,10 @@
+-- Name filter matching trigrams and french stems of the titles without accents
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+-- unaccent is only stable, indexes need an immutable function
+CREATE OR REPLACE FUNCTION f_unaccent(text) RETURNS text AS
+$$ SELECT public.unaccent('public.unaccent', $1) $$
+LANGUAGE sql IMMUTABLE PARALLEL SAFE STRICT;
+
+CREATE INDEX IF NOT EXISTS idx_oa_name_trgm ON okkazeo_announce USING gin (f_unaccent(oa_name) gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS idx_oa_name_fts ON okkazeo_announce USING gin (to_tsvector('french', f_unaccent(oa_name)));
@@ -0,0 +1,22 @@
+-- Announces refer to their seller by name, as written by the backend, and the
+-- sellers are inserted without an id.
+ALTER TABLE okkazeo_announce DROP CONSTRAINT IF EXISTS okkazeo_announce_oa_seller_fkey;
+ALTER TABLE okkazeo_announce ALTER COLUMN oa_seller TYPE text USING oa_seller::text;
+
+CREATE SEQUENCE IF NOT EXISTS seller_seller_id_seq OWNED BY seller.seller_id;
+SELECT setval('seller_seller_id_seq', COALESCE(MAX(seller_id), 0) + 1, false) FROM seller;
+ALTER TABLE seller ALTER COLUMN seller_id SET DEFAULT nextval('seller_seller_id_seq');
+
+-- keep the first seller of each name, the others were never read
+DELETE FROM seller s USING seller kept
+  WHERE s.seller_name = kept.seller_name AND s.seller_id > kept.seller_id;
+DELETE FROM seller WHERE seller_name IS NULL;
+ALTER TABLE seller ALTER COLUMN seller_name SET NOT NULL;
+-- the schema of scrapy.sql already has it
+ALTER TABLE seller DROP CONSTRAINT IF EXISTS seller_seller_name_key;
+ALTER TABLE seller ADD CONSTRAINT seller_seller_name_key UNIQUE (seller_name);
+
+UPDATE okkazeo_announce SET oa_seller = NULL
+  WHERE oa_seller NOT IN (SELECT seller_name FROM seller);
+ALTER TABLE okkazeo_announce ADD CONSTRAINT okkazeo_announce_oa_seller_fkey
+  FOREIGN KEY (oa_seller) REFERENCES seller (seller_name) ON UPDATE CASCADE;
@@ -0,0 +1,33 @@
+-- Prices seen for the announces, a row is appended when the price changes.
+-- Rows are kept when the announce is removed.
+CREATE TABLE "announce_price_history" (
+  "aph_id" SERIAL PRIMARY KEY,
+  "aph_oa_id" integer NOT NULL,
+  "aph_price" real NOT NULL,
+  "aph_date" timestamptz NOT NULL DEFAULT now()
+);
+
+-- Prices seen at the resellers for an announce or a catalogue game, a row is
+-- appended when the price or the sale price changes
+CREATE TABLE "reference_price_history" (
+  "rph_id" SERIAL PRIMARY KEY,
+  "rph_oa_id" integer,
+  "rph_game_id" integer,
+  "rph_reseller" text NOT NULL,
+  "rph_price" real NOT NULL,
+  "rph_promo_price" real,
+  "rph_date" timestamptz NOT NULL DEFAULT now(),
+  CHECK ("rph_oa_id" IS NOT NULL OR "rph_game_id" IS NOT NULL)
+);
+
+CREATE INDEX idx_aph_oa_id ON announce_price_history (aph_oa_id, aph_id);
+CREATE INDEX idx_rph_oa_id ON reference_price_history (rph_oa_id, rph_reseller, rph_id);
+CREATE INDEX idx_rph_game_id ON reference_price_history (rph_game_id, rph_reseller, rph_id);
+
+-- the current prices start the history
+INSERT INTO announce_price_history (aph_oa_id, aph_price, aph_date)
+  SELECT oa_id, oa_price, oa_last_modification_date FROM okkazeo_announce;
+INSERT INTO reference_price_history (rph_oa_id, rph_game_id, rph_reseller, rph_price, rph_promo_price)
+  SELECT ref_oa_id, ref_game_id, ref_name, ref_price, ref_promo_price FROM reference
+  WHERE ref_name IS NOT NULL AND ref_price IS NOT NULL
+    AND (ref_oa_id IS NOT NULL OR ref_game_id IS NOT NULL);
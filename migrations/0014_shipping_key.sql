@@ -0,0 +1,5 @@
+-- The shipping fees of the announces are upserted from the okkazeo csv
+DELETE FROM shipping s USING shipping kept
+  WHERE s.ship_oa_id = kept.ship_oa_id AND s.ship_shipper = kept.ship_shipper
+    AND s.ship_id > kept.ship_id;
+ALTER TABLE shipping ADD CONSTRAINT shipping_oa_id_shipper_key UNIQUE (ship_oa_id, ship_shipper);
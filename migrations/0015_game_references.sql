@@ -0,0 +1,11 @@
+-- The announces linked to a catalogue game read its references and reviews,
+-- their own copies are dropped. The others, whose lookup was not cached, keep theirs.
+DELETE FROM reference r USING okkazeo_announce oa
+  WHERE r.ref_oa_id = oa.oa_id AND oa.oa_game_id IS NOT NULL;
+DELETE FROM reviewer r USING okkazeo_announce oa
+  WHERE r.reviewer_oa_id = oa.oa_id AND oa.oa_game_id IS NOT NULL;
+
+ALTER TABLE reference ADD CONSTRAINT reference_single_owner
+  CHECK (ref_oa_id IS NULL OR ref_game_id IS NULL);
+ALTER TABLE reviewer ADD CONSTRAINT reviewer_single_owner
+  CHECK (reviewer_oa_id IS NULL OR reviewer_game_id IS NULL);
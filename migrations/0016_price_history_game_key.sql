@@ -0,0 +1,6 @@
+-- Catalogue entries are refreshed in place, the reseller prices of a removed
+-- entry would be attached to no game
+DELETE FROM reference_price_history
+  WHERE rph_game_id IS NOT NULL AND rph_game_id NOT IN (SELECT game_id FROM game);
+ALTER TABLE reference_price_history ADD CONSTRAINT reference_price_history_rph_game_id_fkey
+  FOREIGN KEY (rph_game_id) REFERENCES game ("game_id") ON DELETE CASCADE;
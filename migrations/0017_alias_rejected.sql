@@ -0,0 +1,2 @@
+-- Aliases removed by an admin are kept so that they are not learned again
+ALTER TABLE title_alias ADD COLUMN IF NOT EXISTS "alias_rejected" boolean NOT NULL DEFAULT false;
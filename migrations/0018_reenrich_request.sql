@@ -0,0 +1,9 @@
+-- Games to look up again after an override change, queued by the frontend and
+-- processed by the backend which owns the reseller rate limiters and breakers
+CREATE TABLE IF NOT EXISTS "reenrich_request" (
+  "rr_id" SERIAL PRIMARY KEY,
+  "rr_key" text,
+  "rr_barcode" bigint,
+  "rr_created" timestamptz NOT NULL DEFAULT now(),
+  CHECK ("rr_key" IS NOT NULL OR "rr_barcode" IS NOT NULL)
+);
@@ -0,0 +1,219 @@
+use std::{collections::HashMap, env, fs};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::{Client, Error};
+
+use crate::{
+    catalogue::catalogue_key,
+    db::{insert_alias_into_db, reject_alias_in_db, select_alias_from_db, select_aliases_from_db},
+    game::{Game, GameKind},
+    website::helper::match_game,
+};
+
+/// Names of the same game in several languages, "Les Aventuriers du Rail" and "Ticket to Ride"
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(transparent)]
+pub struct AliasGroup(pub Vec<String>);
+
+/// Alias learned from a barcode lookup, stored both ways
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TitleAlias {
+    pub id: i32,
+    /// Key of the title `name` is an alias of
+    pub key: String,
+    pub name: String,
+    /// Reseller whose barcode lookup taught the alias
+    pub source: String,
+    pub created: DateTime<Utc>,
+    /// Removed by an admin, not used nor learned again
+    pub rejected: bool,
+}
+
+/// Key of the title an alias is looked up with, whatever the kind of the announce
+pub fn alias_key(title: &str) -> String {
+    catalogue_key(title, GameKind::Unknown)
+}
+
+/// Other names to search `title` with, from the seed file then the learned ones
+pub async fn load_aliases(db_client: &Client, title: &str) -> Vec<String> {
+    let key = alias_key(title);
+    let mut aliases = SEED_ALIASES.get(&key).cloned().unwrap_or_default();
+    match select_aliases_from_db(db_client, &key).await {
+        Ok(learned) => aliases.extend(learned),
+        Err(e) => log::error!("error db, cannot select aliases of {} : {}", title, e),
+    }
+
+    let mut keys = vec![key];
+    aliases.retain(|alias| {
+        let key = alias_key(alias);
+        if keys.contains(&key) {
+            return false;
+        }
+        keys.push(key);
+        true
+    });
+    aliases
+}
+
+/// Products found by barcode whose title the announce name does not match,
+/// a name lookup would have missed them
+fn aliases_to_learn(game: &Game) -> Vec<(String, String)> {
+    let name = game.okkazeo_announce.name.trim();
+    let mut aliases: Vec<(String, String)> = vec![];
+    for reference in game.references.values() {
+        let title = match &reference.product_title {
+            Some(title) => title.trim(),
+            None => continue,
+        };
+        if title.is_empty()
+            || match_game(name, game.okkazeo_announce.kind(), title).is_match()
+            || aliases.iter().any(|(_, alias)| alias == title)
+        {
+            continue;
+        }
+        aliases.push((reference.name.clone(), title.to_string()));
+    }
+    aliases
+}
+
+/// Store both ways the names learned from the barcode lookups of `game`
+pub async fn learn_aliases(db_client: &Client, game: &Game) {
+    let name = game.okkazeo_announce.name.trim();
+    for (reseller, title) in aliases_to_learn(game) {
+        log::info!("learned alias {} for {} from {}", title, name, reseller);
+        for (key, alias) in [(alias_key(name), title.as_str()), (alias_key(&title), name)] {
+            if let Err(e) = insert_alias_into_db(db_client, &key, alias, &reseller).await {
+                log::error!("error db, cannot insert alias {} of {} : {}", alias, key, e);
+                return;
+            }
+        }
+        ALIAS_LEARNED.with_label_values(&[&reseller]).inc();
+    }
+}
+
+/// Reject the alias with id `id` and its reverse, the title it is an alias of
+/// as an alias of `name`. Returns the rejected alias, None if there was none.
+pub async fn reject_alias(db_client: &Client, id: i32) -> Result<Option<TitleAlias>, Error> {
+    let Some(alias) = select_alias_from_db(db_client, id).await? else {
+        return Ok(None);
+    };
+    reject_alias_in_db(db_client, &alias.key, &alias.name).await?;
+    for reverse in select_aliases_from_db(db_client, &alias_key(&alias.name)).await? {
+        if alias_key(&reverse) == alias.key {
+            reject_alias_in_db(db_client, &alias_key(&alias.name), &reverse).await?;
+        }
+    }
+    Ok(Some(TitleAlias {
+        rejected: true,
+        ..alias
+    }))
+}
+
+/// Each name of a group is an alias of the others
+fn parse_alias_seed(content: &str) -> Result<HashMap<String, Vec<String>>, serde_json::Error> {
+    let groups: Vec<AliasGroup> = serde_json::from_str(content)?;
+    let mut aliases: HashMap<String, Vec<String>> = HashMap::new();
+    for AliasGroup(names) in groups {
+        for name in &names {
+            aliases.entry(alias_key(name)).or_default().extend(
+                names
+                    .iter()
+                    .filter(|other| alias_key(other) != alias_key(name))
+                    .cloned(),
+            );
+        }
+    }
+    Ok(aliases)
+}
+
+fn load_alias_seed() -> HashMap<String, Vec<String>> {
+    let filename = env::var("ALIASES_FILE").unwrap_or("aliases.json".to_string());
+    match fs::read_to_string(&filename)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse_alias_seed(&content).map_err(|e| e.to_string()))
+    {
+        Ok(aliases) => {
+            log::debug!(
+                "loaded aliases of {} titles from {}",
+                aliases.len(),
+                filename
+            );
+            aliases
+        }
+        Err(e) => {
+            log::error!(
+                "Cannot read aliases from {}, only learned ones are used: {}",
+                filename,
+                e
+            );
+            HashMap::new()
+        }
+    }
+}
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    static ref SEED_ALIASES: HashMap<String, Vec<String>> = load_alias_seed();
+    static ref ALIAS_LEARNED: IntCounterVec = register_int_counter_vec!(
+        "alias_learned",
+        "Number of aliases learned from a barcode lookup, by reseller",
+        &["reseller"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::{alias_key, aliases_to_learn, parse_alias_seed};
+    use crate::game::{Game, OkkazeoAnnounce, Reference};
+
+    #[test]
+    fn test_parse_seed() {
+        let content =
+            fs::read_to_string("aliases.json").expect("Should have been able to read the file");
+        let aliases = parse_alias_seed(&content).unwrap();
+        assert_eq!(
+            aliases.get(&alias_key("Les Aventuriers du Rail")),
+            Some(&vec!["Ticket to Ride".to_string()])
+        );
+        assert_eq!(
+            aliases.get(&alias_key("ticket to ride")),
+            Some(&vec!["Les Aventuriers du Rail".to_string()])
+        );
+    }
+
+    #[test]
+    fn test_aliases_to_learn() {
+        let mut game = Game {
+            okkazeo_announce: OkkazeoAnnounce {
+                name: "Les Colons de Catane".to_string(),
+                extension: "Jeu".to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        for (reseller, title) in [
+            ("philibert", Some("Catan")),
+            ("ultrajeux", Some("Catan")),
+            ("espritjeu", Some("Les Colons de Catane - Jeu de base")),
+            ("knapix", None),
+        ] {
+            game.references.insert(
+                reseller.to_string(),
+                Reference {
+                    name: reseller.to_string(),
+                    product_title: title.map(str::to_string),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let aliases = aliases_to_learn(&game);
+        assert_eq!(aliases.len(), 1);
+        assert_eq!(aliases[0].1, "Catan");
+    }
+}
@@ -1,13 +1,16 @@
+use boardgame_finder::alias::load_aliases;
+use boardgame_finder::catalogue::{load_match_overrides, process_reenrich_requests};
+use boardgame_finder::game::get_game_infos;
 use boardgame_finder::metrics;
 use boardgame_finder::website::okkazeo::get_okkazeo_csv;
-use boardgame_finder::{db::select_all_ids_from_oa_table_from_db, game::get_game_infos};
+use std::collections::HashSet;
 use std::time::{Duration, Instant};
 use tokio::time::{self};
-use tokio_postgres::row;
 
 use boardgame_finder::db::{
-    connect_db, delete_from_all_table_with_id, insert_announce_into_db,
-    select_game_with_id_from_db, update_game_from_db, update_sellers_nb_announces_from_db,
+    create_pool, get_db_client, insert_announce_into_db, select_game_with_id_from_db,
+    sync_okkazeo_rows_into_db, update_enriched_game_from_db, update_game_from_db,
+    update_sellers_nb_announces_from_db,
 };
 
 #[tokio::main]
@@ -17,10 +20,10 @@ async fn main() {
     let backend_metrics_bind_addr =
         std::env::var("BACKEND_METRICS_ADDR").unwrap_or("127.0.0.1:3003".to_string());
 
-    let client = connect_db().await.expect("cannot connect to DB");
+    let pool = create_pool().await.expect("cannot connect to DB");
 
     log::info!("starting program");
-    let csv_fetch_interval = Duration::from_secs(60 * 60 * 3); // every 6 hours
+    let csv_fetch_interval = Duration::from_secs(60 * 60 * 3); // every 3 hours
     log::info!(
         "parsing game csv every {} seconds",
         csv_fetch_interval.as_secs()
@@ -28,52 +31,89 @@ async fn main() {
 
     tokio::spawn(async { metrics::run_metrics(backend_metrics_bind_addr).await });
 
+    // overrides changed on the frontend are looked up here, through the same
+    // rate limiters and breakers as the csv
+    let reenrich_pool = pool.clone();
+    let reenrich_poll_interval = Duration::from_secs(60);
+    tokio::spawn(async move {
+        loop {
+            if let Ok(mut client) = get_db_client(&reenrich_pool).await {
+                process_reenrich_requests(&mut client).await;
+            }
+            time::sleep(reenrich_poll_interval).await;
+        }
+    });
+
     loop {
         let start = Instant::now();
         log::debug!("fetching time : {:?}", start);
 
         // fetch csv
-        let rows = get_okkazeo_csv("https://www.okkazeo.com/aubonmeeple.csv".to_string())
-            .await
-            .unwrap();
+        let rows =
+            match get_okkazeo_csv("https://www.okkazeo.com/aubonmeeple.csv".to_string()).await {
+                Ok(rows) => rows,
+                Err(e) => {
+                    log::error!("cannot fetch the okkazeo csv : {}", e);
+                    let duration = start.elapsed();
+                    if duration < csv_fetch_interval {
+                        time::sleep(csv_fetch_interval - duration).await;
+                    }
+                    continue;
+                }
+            };
 
         if rows.len() < 10 {
             let duration = start.elapsed();
             log::error!("CSV is empty !");
-            time::sleep(csv_fetch_interval - duration).await;
+            if duration < csv_fetch_interval {
+                time::sleep(csv_fetch_interval - duration).await;
+            }
+            continue;
         }
 
         log::info!("csv containing {} row", rows.len());
-        let mut csv_ids = vec![];
-        for row in &rows {
-            csv_ids.push(row.id as i32);
+        // the sync and each announce take their own connection from the pool,
+        // a broken one is replaced for the next announce
+        let Ok(mut client) = get_db_client(&pool).await else {
+            let duration = start.elapsed();
+            if duration < csv_fetch_interval {
+                time::sleep(csv_fetch_interval - duration).await;
+            }
+            continue;
+        };
+        let sync = sync_okkazeo_rows_into_db(&mut client, &rows).await;
+        drop(client);
+        let sync = match sync {
+            Ok(sync) => sync,
+            Err(e) => {
+                log::error!("error db, cannot sync the csv : {}", e);
+                let duration = start.elapsed();
+                if duration < csv_fetch_interval {
+                    time::sleep(csv_fetch_interval - duration).await;
+                }
+                continue;
+            }
+        };
+        log::info!(
+            "{} new announces, {} changed, {} removed",
+            sync.new_ids.len(),
+            sync.changed_ids.len(),
+            sync.deleted
+        );
+
+        let new_ids: HashSet<u32> = sync.new_ids.into_iter().collect();
+        for row in rows.iter().filter(|row| new_ids.contains(&row.id)) {
             log::debug!("treating record : {:?}", row);
-            let fetched_game = select_game_with_id_from_db(&client, row.id).await;
-            match fetched_game {
-                None => match get_game_infos(row.clone()).await {
-                    Err(e) => log::error!("error getting game info {}", e),
-                    Ok(g) => {
-                        if fetched_game.is_none() {
-                            if let Err(e) = insert_announce_into_db(&client, &g).await {
-                                log::error!(
-                                    "error db, cannot insert game {} : {}",
-                                    g.okkazeo_announce.name,
-                                    e
-                                );
-                            }
-                        }
-                    }
-                },
-                Some(mut game) => {
-                    log::debug!(
-                        "game {} already in DB, updating it",
-                        game.okkazeo_announce.id
-                    );
-                    game.update_game(row.clone());
-                    if let Err(e) = update_game_from_db(&client, &game).await {
+            let Ok(mut client) = get_db_client(&pool).await else {
+                continue;
+            };
+            match get_game_infos(&mut client, row.clone()).await {
+                Err(e) => log::error!("error getting game info {}", e),
+                Ok(g) => {
+                    if let Err(e) = insert_announce_into_db(&mut client, &g).await {
                         log::error!(
-                            "error db, cannot update game {} : {}",
-                            game.okkazeo_announce.name,
+                            "error db, cannot insert game {} : {}",
+                            g.okkazeo_announce.name,
                             e
                         );
                     }
@@ -81,36 +121,42 @@ async fn main() {
             }
         }
 
-        let db_ids = match select_all_ids_from_oa_table_from_db(&client).await {
-            Ok(ids) => {
-                log::debug!("fetched {} ids", ids.len());
-                ids
-            }
-            Err(e) => {
-                log::error!("error fetching ids from db : {}", e);
+        for id in sync.changed_ids {
+            let Ok(mut client) = get_db_client(&pool).await else {
+                continue;
+            };
+            let Some(mut game) = select_game_with_id_from_db(&client, id).await else {
                 continue;
+            };
+            log::debug!("game {} changed, updating it", id);
+            let retry_resellers = !game.timed_out_resellers.is_empty();
+            if retry_resellers {
+                let overrides = load_match_overrides(&client, &game.okkazeo_announce).await;
+                let aliases = load_aliases(&client, &game.okkazeo_announce.name).await;
+                game.retry_timed_out_resellers(&overrides, &aliases).await;
             }
-        };
-        let csv_set: std::collections::HashSet<i32> =
-            rows.iter().cloned().map(|r| r.id as i32).collect();
-        let ids_to_remove: Vec<i32> = db_ids
-            .iter()
-            .filter(|&x| !csv_set.contains(x))
-            .cloned()
-            .collect();
-
-        log::debug!("removing {:?} games", ids_to_remove.len());
-        for id in ids_to_remove {
-            log::debug!("removing {} from db", id);
-            if let Err(e) = delete_from_all_table_with_id(&client, id).await {
-                log::error!("error deleting from db : {}", e);
+            // shipping fees are part of the landed price
+            game.get_deal_advantage();
+            let res = if retry_resellers {
+                update_enriched_game_from_db(&mut client, &game).await
+            } else {
+                update_game_from_db(&mut client, &game).await
+            };
+            if let Err(e) = res {
+                log::error!(
+                    "error db, cannot update game {} : {}",
+                    game.okkazeo_announce.name,
+                    e
+                );
             }
         }
 
-        log::debug!(
-            "updated {} sellers",
-            update_sellers_nb_announces_from_db(&client).await
-        );
+        if let Ok(client) = get_db_client(&pool).await {
+            log::debug!(
+                "updated {} sellers",
+                update_sellers_nb_announces_from_db(&client).await
+            );
+        }
 
         let duration = start.elapsed();
         log::info!("treated CSV in {:?} ", duration);
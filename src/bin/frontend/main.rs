@@ -1,3 +1,4 @@
+use boardgame_finder::db::create_pool;
 use boardgame_finder::frontlib::server;
 use boardgame_finder::metrics;
 use tokio::task::JoinSet;
@@ -12,11 +13,14 @@ async fn main() {
         std::env::var("FRONTEND_METRICS_ADDR").unwrap_or("127.0.0.1:3002".to_string());
 
     log::info!("[MAIN] starting program");
+    let pool = create_pool().await.expect("cannot connect to DB");
+    log::info!("[MAIN] connected with DB");
+
     let mut set = JoinSet::new();
-    set.spawn(async move { server::run_server(frontend_bind_addr).await });
+    set.spawn(async move { server::run_server(frontend_bind_addr, pool).await });
     set.spawn(async move { metrics::run_metrics(frontend_metrics_bind_addr).await });
 
-    while let Some(_) = set.join_next().await {
+    while set.join_next().await.is_some() {
         log::info!("Main task over");
     }
 }
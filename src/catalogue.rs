@@ -0,0 +1,408 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use tokio_postgres::Client;
+use unidecode::unidecode;
+
+use crate::{
+    alias::{learn_aliases, load_aliases},
+    db::{
+        delete_reenrich_request_from_db, select_announces_to_reenrich_from_db,
+        select_catalogue_entry_from_db, select_catalogue_entry_with_id_from_db,
+        select_game_with_id_from_db, select_match_overrides_from_db,
+        select_reenrich_requests_from_db, update_enriched_game_from_db,
+        upsert_catalogue_entry_into_db,
+    },
+    game::{BggInfos, Game, GameKind, OkkazeoAnnounce, Reference, Review},
+    gtin::Gtin,
+    website::{
+        helper::{clean_name, TOKENS_UNWANTED},
+        MatchOverrides,
+    },
+};
+
+/// A game sold new, shared by all the announces of this game.
+/// Its references and reviews are reused while they are fresh.
+#[derive(Debug, Default, Clone)]
+pub struct CatalogueEntry {
+    /// None until the entry is stored
+    pub id: Option<i32>,
+    pub title: String,
+    /// Title stripped of accents, punctuation and meaningless words
+    pub key: String,
+    pub barcode: Option<Gtin>,
+    pub bgg: Option<BggInfos>,
+    pub references: HashMap<String, Reference>,
+    pub review: Review,
+    pub updated: DateTime<Utc>,
+}
+
+impl CatalogueEntry {
+    /// True while the cached references and reviews can be reused
+    pub fn is_fresh(&self) -> bool {
+        is_fresh_at(self.updated, Utc::now(), *CATALOGUE_TTL)
+    }
+}
+
+fn is_fresh_at(updated: DateTime<Utc>, now: DateTime<Utc>, ttl: Duration) -> bool {
+    match chrono::Duration::from_std(ttl) {
+        Ok(ttl) => now - updated < ttl,
+        Err(_) => true,
+    }
+}
+
+/// Key identifying a game without barcode, "Azul : le jeu" and "AZUL" share it.
+/// An expansion titled like its base game gets another key.
+pub fn catalogue_key(title: &str, kind: GameKind) -> String {
+    let key = clean_name(title)
+        .split_whitespace()
+        .map(|word| unidecode(word).to_lowercase())
+        .filter(|word| !TOKENS_UNWANTED.contains(&word.as_str()))
+        .collect::<Vec<_>>()
+        .join(" ");
+    match kind {
+        GameKind::Expansion => format!("{} [extension]", key),
+        GameKind::BaseWithExpansion => format!("{} [jeu + extension]", key),
+        GameKind::Base | GameKind::Unknown => key,
+    }
+}
+
+/// Manual correction of the product matched by a reseller for a game,
+/// identified by its barcode or its catalogue key
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MatchOverride {
+    pub id: i32,
+    pub key: Option<String>,
+    pub barcode: Option<Gtin>,
+    /// Reseller name as in `Game.references`, or "bgg"
+    pub reseller: String,
+    /// Product to use, None when the reseller does not sell the game
+    pub url: Option<String>,
+}
+
+/// Game whose announces must be looked up again, queued by the frontend when
+/// an override changes
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReenrichRequest {
+    pub id: i32,
+    pub key: Option<String>,
+    pub barcode: Option<Gtin>,
+}
+
+/// Override of each reseller, the barcode ones are more precise than the key ones
+pub fn overrides_by_reseller(overrides: Vec<MatchOverride>) -> MatchOverrides {
+    let (by_barcode, by_key): (Vec<_>, Vec<_>) =
+        overrides.into_iter().partition(|o| o.barcode.is_some());
+    by_key
+        .into_iter()
+        .chain(by_barcode)
+        .map(|o| (o.reseller, o.url))
+        .collect()
+}
+
+/// Overrides applying to the game of an announce, none when they cannot be loaded
+pub async fn load_match_overrides(
+    db_client: &Client,
+    announce: &OkkazeoAnnounce,
+) -> MatchOverrides {
+    let key = catalogue_key(&announce.name, announce.kind());
+    match select_match_overrides_from_db(db_client, announce.barcode, &key).await {
+        Ok(overrides) => overrides_by_reseller(overrides),
+        Err(e) => {
+            log::error!(
+                "error db, cannot select match overrides of {} : {}",
+                announce.name,
+                e
+            );
+            MatchOverrides::new()
+        }
+    }
+}
+
+/// Process the queued re-enrichment requests, oldest first. A request is
+/// removed once processed, it is processed again after a restart otherwise.
+pub async fn process_reenrich_requests(db_client: &mut Client) {
+    let requests = match select_reenrich_requests_from_db(db_client).await {
+        Ok(requests) => requests,
+        Err(e) => {
+            log::error!("error db, cannot select re-enrichment requests : {}", e);
+            return;
+        }
+    };
+    // identical requests queued before the lookup are served by the same one,
+    // the latest of them stands for the others
+    let mut distinct: Vec<ReenrichRequest> = vec![];
+    for request in requests {
+        match distinct
+            .iter_mut()
+            .find(|d| d.key == request.key && d.barcode == request.barcode)
+        {
+            Some(known) => known.id = request.id,
+            None => distinct.push(request),
+        }
+    }
+    for request in distinct {
+        reenrich_announces(db_client, request.key.as_deref(), request.barcode).await;
+        if let Err(e) = delete_reenrich_request_from_db(db_client, &request).await {
+            log::error!(
+                "error db, cannot delete re-enrichment request {} : {}",
+                request.id,
+                e
+            );
+        }
+    }
+}
+
+/// Look up again the references and reviews of the announces of a game whose
+/// overrides changed. Its catalogue entries are refreshed in place and keep
+/// their previous references and reviews when a lookup times out.
+pub async fn reenrich_announces(db_client: &mut Client, key: Option<&str>, barcode: Option<Gtin>) {
+    let ids = match select_announces_to_reenrich_from_db(db_client, barcode, key).await {
+        Ok(ids) => ids,
+        Err(e) => {
+            log::error!("error db, cannot select announces to re-enrich : {}", e);
+            return;
+        }
+    };
+
+    for id in ids {
+        let mut game = match select_game_with_id_from_db(db_client, id as u32).await {
+            Some(game) => game,
+            None => continue,
+        };
+        let entry = match game.catalogue_id {
+            Some(game_id) => match select_catalogue_entry_with_id_from_db(db_client, game_id).await
+            {
+                Ok(entry) => entry,
+                Err(e) => {
+                    log::error!(
+                        "error db, cannot select catalogue entry {} : {}",
+                        game_id,
+                        e
+                    );
+                    continue;
+                }
+            },
+            None => None,
+        };
+        log::info!(
+            "re-enriching {} after an override change",
+            game.okkazeo_announce.name
+        );
+        game.references.clear();
+        game.review = Review::default();
+        game.bgg = None;
+        game.timed_out_resellers.clear();
+
+        let key = catalogue_key(&game.okkazeo_announce.name, game.okkazeo_announce.kind());
+        lookup_game(db_client, &mut game, key, entry).await;
+        if game.catalogue_id.is_some() && !game.timed_out_resellers.is_empty() {
+            // the announces of the entry keep showing its previous references
+            log::warn!(
+                "keeping the catalogue entry of {}, {:?} timed out",
+                game.okkazeo_announce.name,
+                game.timed_out_resellers
+            );
+            continue;
+        }
+        game.get_deal_advantage();
+        if let Err(e) = update_enriched_game_from_db(db_client, &game).await {
+            log::error!(
+                "error db, cannot update {} : {}",
+                game.okkazeo_announce.name,
+                e
+            );
+        }
+    }
+}
+
+/// Fill the references, reviews and bgg description of a new announce from its catalogue
+/// entry, looking them up only when the entry is missing or stale
+pub async fn enrich_game(db_client: &mut Client, game: &mut Game) {
+    let key = catalogue_key(&game.okkazeo_announce.name, game.okkazeo_announce.kind());
+    let barcode = game.okkazeo_announce.barcode;
+
+    let entry = match select_catalogue_entry_from_db(db_client, barcode, &key).await {
+        Ok(entry) => entry,
+        Err(e) => {
+            log::error!(
+                "error db, cannot select catalogue entry of {} : {}",
+                game.okkazeo_announce.name,
+                e
+            );
+            None
+        }
+    };
+
+    if let Some(entry) = &entry {
+        if entry.is_fresh() {
+            log::debug!(
+                "reusing catalogue entry {} for {}",
+                entry.title,
+                game.okkazeo_announce.name
+            );
+            CATALOGUE_LOOKUP.with_label_values(&["hit"]).inc();
+            game.references = entry.references.clone();
+            game.review = entry.review.clone();
+            game.bgg = entry.bgg.clone();
+            game.catalogue_id = entry.id;
+            return;
+        }
+    }
+    CATALOGUE_LOOKUP
+        .with_label_values(&[if entry.is_some() { "stale" } else { "miss" }])
+        .inc();
+    lookup_game(db_client, game, key, entry).await;
+}
+
+/// Look up the references, reviews and bgg description of the game and store
+/// them in its catalogue entry, updated in place when there is one
+async fn lookup_game(
+    db_client: &mut Client,
+    game: &mut Game,
+    key: String,
+    entry: Option<CatalogueEntry>,
+) {
+    let barcode = game.okkazeo_announce.barcode;
+    let overrides = load_match_overrides(db_client, &game.okkazeo_announce).await;
+    let aliases = load_aliases(db_client, &game.okkazeo_announce.name).await;
+    game.get_references_and_reviews(&overrides, &aliases).await;
+    learn_aliases(db_client, game).await;
+    if !game.timed_out_resellers.is_empty() {
+        // an incomplete lookup would hide the missing resellers to the next announces
+        log::debug!(
+            "not caching {}, {:?} timed out",
+            game.okkazeo_announce.name,
+            game.timed_out_resellers
+        );
+        return;
+    }
+
+    let entry = CatalogueEntry {
+        id: entry.as_ref().and_then(|e| e.id),
+        title: game.okkazeo_announce.name.trim().to_string(),
+        key,
+        barcode: barcode.or(entry.as_ref().and_then(|e| e.barcode)),
+        bgg: game.bgg.clone().or(entry.and_then(|e| e.bgg)),
+        references: game.references.clone(),
+        review: game.review.clone(),
+        updated: Utc::now(),
+    };
+    match upsert_catalogue_entry_into_db(db_client, &entry).await {
+        Ok(id) => game.catalogue_id = Some(id),
+        Err(e) => log::error!(
+            "error db, cannot store catalogue entry of {} : {}",
+            entry.title,
+            e
+        ),
+    }
+}
+
+fn create_catalogue_ttl() -> Duration {
+    let hours = std::env::var("CATALOGUE_TTL_HOURS")
+        .map_err(|e| e.to_string())
+        .and_then(|v| v.parse::<u64>().map_err(|e| e.to_string()))
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Cannot initialize catalogue ttl from environment, fallback to default: {}",
+                err
+            );
+            DEFAULT_CATALOGUE_TTL_HOURS
+        });
+    Duration::from_secs(hours * 3600)
+}
+
+/// DEFAULT_CATALOGUE_TTL_HOURS is the default age after which a catalogue entry is looked up again
+const DEFAULT_CATALOGUE_TTL_HOURS: u64 = 24;
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    static ref CATALOGUE_TTL: Duration = create_catalogue_ttl();
+    static ref CATALOGUE_LOOKUP: IntCounterVec = register_int_counter_vec!(
+        "catalogue_lookup",
+        "Number of announces enriched from a fresh, stale or missing catalogue entry",
+        &["result"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{catalogue_key, is_fresh_at, overrides_by_reseller, MatchOverride};
+    use crate::{game::GameKind, gtin::Gtin};
+
+    #[test]
+    fn test_overrides_by_reseller() {
+        let overrides = overrides_by_reseller(vec![
+            MatchOverride {
+                barcode: Gtin::new(3760269590120),
+                reseller: "philibert".to_string(),
+                url: Some("https://www.philibert.net/fr/azul/1".to_string()),
+                ..Default::default()
+            },
+            MatchOverride {
+                key: Some("azul".to_string()),
+                reseller: "philibert".to_string(),
+                url: Some("https://www.philibert.net/fr/azul/2".to_string()),
+                ..Default::default()
+            },
+            MatchOverride {
+                key: Some("azul".to_string()),
+                reseller: "ultrajeux".to_string(),
+                url: None,
+                ..Default::default()
+            },
+        ]);
+        assert_eq!(overrides.len(), 2);
+        assert_eq!(
+            overrides.get("philibert"),
+            Some(&Some("https://www.philibert.net/fr/azul/1".to_string()))
+        );
+        assert_eq!(overrides.get("ultrajeux"), Some(&None));
+    }
+
+    #[test]
+    fn test_catalogue_key() {
+        assert_eq!(catalogue_key("Azul", GameKind::Base), "azul");
+        assert_eq!(catalogue_key("AZUL : le jeu", GameKind::Base), "azul");
+        assert_eq!(
+            catalogue_key("Death Note - le jeu d'enquête", GameKind::Base),
+            "death note enquete"
+        );
+        assert_eq!(
+            catalogue_key("Les Flammes d’Adlerstein", GameKind::Base),
+            catalogue_key("Les Flammes D'adlerstein", GameKind::Base)
+        );
+        assert_ne!(
+            catalogue_key("Dixit", GameKind::Base),
+            catalogue_key("Dixit 2", GameKind::Base)
+        );
+        assert_ne!(
+            catalogue_key("Catan", GameKind::Base),
+            catalogue_key("Catan", GameKind::Expansion)
+        );
+    }
+
+    #[test]
+    fn test_is_fresh() {
+        let updated = Utc.with_ymd_and_hms(2026, 10, 1, 12, 0, 0).unwrap();
+        let ttl = Duration::from_secs(24 * 3600);
+        assert!(is_fresh_at(
+            updated,
+            Utc.with_ymd_and_hms(2026, 10, 2, 11, 0, 0).unwrap(),
+            ttl
+        ));
+        assert!(!is_fresh_at(
+            updated,
+            Utc.with_ymd_and_hms(2026, 10, 2, 13, 0, 0).unwrap(),
+            ttl
+        ));
+    }
+}
@@ -1,34 +1,188 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::pin::pin;
+use std::time::Duration;
 
 use chrono::{DateTime, Utc};
-use tokio_postgres::{Client, Error, NoTls, Row};
+use deadpool_postgres::{
+    Manager, ManagerConfig, Object, Pool, PoolError, RecyclingMethod, Runtime,
+};
+use tokio_postgres::{
+    binary_copy::BinaryCopyInWriter,
+    error::SqlState,
+    types::{ToSql, Type},
+    Client, Error, GenericClient, NoTls, Row, Transaction,
+};
 
 use crate::frontlib::server::State;
 use crate::{
+    alias::TitleAlias,
+    catalogue::{CatalogueEntry, MatchOverride, ReenrichRequest},
     frontlib::Filters,
-    game::{Deal, Game, Games, OkkazeoAnnounce, Reference, Review, Reviewer, Seller},
+    game::{
+        BggInfos, Deal, Game, Games, OkkazeoAnnounce, PriceHistory, PricePoint, Reference, Review,
+        Reviewer, Seller,
+    },
+    gtin::{Gtin, GtinError},
+    migration::migrate,
+    rating::RATING_POLICY,
+    website::{okkazeo::Row as OkkazeoRow, shipping},
 };
 
 use lazy_static::lazy_static;
-use prometheus::{register_int_counter_vec, IntCounterVec};
+use prometheus::{
+    register_int_counter_vec, register_int_gauge, register_int_gauge_vec, IntCounterVec, IntGauge,
+    IntGaugeVec,
+};
 
-pub async fn connect_db() -> Result<Client, Error> {
+/// Pool of connections to DB_URL and migrate its schema, fails against a database this build cannot use.
+/// Connections are checked with a query before being handed out again and replaced when broken.
+pub async fn create_pool() -> Result<Pool, anyhow::Error> {
     let db_url = std::env::var("DB_URL").expect("DB_URL is not defined");
+    let mut pg_config: tokio_postgres::Config = db_url.parse()?;
+
+    // the server cancels a query running longer, its connection goes back to the pool
+    let statement_timeout = env_or_default(
+        "DB_STATEMENT_TIMEOUT_SECS",
+        "statement timeout",
+        DEFAULT_STATEMENT_TIMEOUT_SECS,
+    );
+    let options = pg_config
+        .get_options()
+        .map_or(String::new(), |options| format!("{} ", options));
+    pg_config.options(format!(
+        "{}-c statement_timeout={}",
+        options,
+        statement_timeout * 1000
+    ));
 
-    log::info!("connecting to DB");
-    let (client, connection) = tokio_postgres::connect(&db_url, NoTls).await?;
+    let size = env_or_default("DB_POOL_SIZE", "pool size", DEFAULT_POOL_SIZE);
+    let timeout = Duration::from_secs(env_or_default(
+        "DB_POOL_TIMEOUT_SECS",
+        "pool timeout",
+        DEFAULT_POOL_TIMEOUT_SECS,
+    ));
+    let manager = Manager::from_config(
+        pg_config,
+        NoTls,
+        ManagerConfig {
+            recycling_method: RecyclingMethod::Verified,
+        },
+    );
+    let pool = Pool::builder(manager)
+        .max_size(size)
+        .wait_timeout(Some(timeout))
+        .create_timeout(Some(timeout))
+        .recycle_timeout(Some(timeout))
+        .runtime(Runtime::Tokio1)
+        .build()?;
+
+    log::info!("connecting to DB with a pool of {} connections", size);
+    let mut client = get_db_client(&pool).await?;
     log::info!("connected to DB");
+    migrate(&mut client).await?;
+
+    tokio::spawn(report_pool_status(pool.clone()));
+    Ok(pool)
+}
+
+/// Connection taken from `pool`, a new one is opened when none is idle and the pool is not full
+pub async fn get_db_client(pool: &Pool) -> Result<Object, PoolError> {
+    let result = pool.get().await;
+    if let Err(e) = &result {
+        if let PoolError::Timeout(_) = e {
+            DB_TIMEOUTS.with_label_values(&["pool"]).inc();
+        }
+        log::error!("error db, cannot get a connection from the pool : {}", e);
+    }
+    result
+}
+
+/// Count the queries cancelled by the statement timeout
+pub fn observe_db_error(e: &Error) {
+    if e.code() == Some(&SqlState::QUERY_CANCELED) {
+        DB_TIMEOUTS.with_label_values(&["statement"]).inc();
+    }
+}
+
+async fn report_pool_status(pool: Pool) {
+    let mut interval = tokio::time::interval(Duration::from_secs(1));
+    loop {
+        interval.tick().await;
+        let status = pool.status();
+        DB_POOL_CONNECTIONS
+            .with_label_values(&["in_use"])
+            .set((status.size - status.available) as i64);
+        DB_POOL_CONNECTIONS
+            .with_label_values(&["idle"])
+            .set(status.available as i64);
+        DB_POOL_WAITERS.set(status.waiting as i64);
+    }
+}
+
+fn env_or_default<T>(name: &str, what: &str, default: T) -> T
+where
+    T: std::str::FromStr,
+    T::Err: std::fmt::Display,
+{
+    std::env::var(name)
+        .map_err(|e| e.to_string())
+        .and_then(|v| v.parse::<T>().map_err(|e| e.to_string()))
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Cannot initialize DB {} from environment, fallback to default: {}",
+                what,
+                err
+            );
+            default
+        })
+}
 
-    tokio::spawn(async move {
-        if let Err(e) = connection.await {
-            log::error!("connection error : {}", e);
+/// DEFAULT_POOL_SIZE is the default maximum number of connections to the DB
+const DEFAULT_POOL_SIZE: usize = 8;
+/// DEFAULT_POOL_TIMEOUT_SECS is the default time to wait for, open or check a connection
+const DEFAULT_POOL_TIMEOUT_SECS: u64 = 5;
+/// DEFAULT_STATEMENT_TIMEOUT_SECS is the default time a query may run
+const DEFAULT_STATEMENT_TIMEOUT_SECS: u64 = 30;
+
+/// Commit when `result` is Ok, else roll back so that no table is half written
+async fn finish_transaction<T>(
+    transaction: Transaction<'_>,
+    name: &str,
+    result: Result<T, Error>,
+) -> Result<T, Error> {
+    if let Err(e) = &result {
+        observe_db_error(e);
+    }
+    match result {
+        Ok(value) => match transaction.commit().await {
+            Ok(()) => {
+                DB_TRANSACTION.with_label_values(&[name, "commit"]).inc();
+                Ok(value)
+            }
+            Err(e) => {
+                DB_TRANSACTION.with_label_values(&[name, "error"]).inc();
+                Err(e)
+            }
+        },
+        Err(e) => {
+            log::debug!("rolling back {} : {}", name, e);
+            if let Err(rollback_error) = transaction.rollback().await {
+                log::error!("error db, cannot roll back {} : {}", name, rollback_error);
+            }
+            DB_TRANSACTION.with_label_values(&[name, "rollback"]).inc();
+            Err(e)
         }
-    });
+    }
+}
 
-    Ok(client)
+/// Remove an announce with its deal, shipping, references and reviews
+pub async fn delete_from_all_table_with_id(db_client: &mut Client, id: i32) -> Result<(), Error> {
+    let transaction = db_client.transaction().await?;
+    let result = delete_announce(&transaction, id).await;
+    finish_transaction(transaction, "delete_announce", result).await
 }
 
-pub async fn delete_from_all_table_with_id(db_client: &Client, id: i32) -> Result<(), Error> {
+async fn delete_announce(db_client: &impl GenericClient, id: i32) -> Result<(), Error> {
     db_client
         .execute("DELETE FROM deal WHERE deal_oa_id = $1", &[&id])
         .await?;
@@ -65,11 +219,11 @@ pub async fn delete_from_all_table_with_id(db_client: &Client, id: i32) -> Resul
 }
 
 pub async fn insert_into_okkazeo_announce_table(
-    db_client: &Client,
-    game: &Box<Game>,
+    db_client: &impl GenericClient,
+    game: &Game,
 ) -> Result<(), Error> {
     let okkazeo_insert_req = format!(
-        r#"INSERT INTO okkazeo_announce ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+        r#"INSERT INTO okkazeo_announce ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)"#,
         "oa_id",
         "oa_last_modification_date",
         "oa_name",
@@ -80,6 +234,8 @@ pub async fn insert_into_okkazeo_announce_table(
         "oa_seller",
         "oa_barcode",
         "oa_city",
+        "oa_timed_out_resellers",
+        "oa_game_id",
     );
     let _ = db_client
         .query(
@@ -93,12 +249,14 @@ pub async fn insert_into_okkazeo_announce_table(
                 &game.okkazeo_announce.url,
                 &game.okkazeo_announce.extension,
                 &(game.okkazeo_announce.seller.name),
-                &(game.okkazeo_announce.barcode.unwrap_or_default() as i64),
+                &gtin_to_db(game.okkazeo_announce.barcode),
                 &game
                     .okkazeo_announce
                     .city
                     .as_ref()
                     .unwrap_or(&String::from("")),
+                &game.timed_out_resellers,
+                &game.catalogue_id,
             ],
         )
         .await?;
@@ -110,7 +268,7 @@ pub async fn insert_into_okkazeo_announce_table(
 }
 
 pub async fn insert_into_shipping_table(
-    db_client: &Client,
+    db_client: &impl GenericClient,
     id: i32,
     shipping: &HashMap<String, f32>,
 ) -> Result<(), Error> {
@@ -129,7 +287,10 @@ pub async fn insert_into_shipping_table(
     Ok(())
 }
 
-pub async fn insert_into_seller_table(db_client: &Client, seller: &Seller) -> Result<(), Error> {
+pub async fn insert_into_seller_table(
+    db_client: &impl GenericClient,
+    seller: &Seller,
+) -> Result<(), Error> {
     log::debug!("insertin into seller table");
     let seller_insert_req = format!(
         r#"INSERT INTO seller ({}, {}, {}, {}) VALUES ($1, $2, $3, $4)"#,
@@ -150,15 +311,25 @@ pub async fn insert_into_seller_table(db_client: &Client, seller: &Seller) -> Re
 
     Ok(())
 }
-pub async fn insert_into_deal_table(db_client: &Client, id: i32, deal: &Deal) -> Result<(), Error> {
+pub async fn insert_into_deal_table(
+    db_client: &impl GenericClient,
+    id: i32,
+    deal: &Deal,
+) -> Result<(), Error> {
     let deal_insert_req = format!(
-        r#"INSERT INTO deal ({}, {}, {}) VALUES ($1, $2, $3)"#,
-        "deal_oa_id", "deal_price", "deal_percentage",
+        r#"INSERT INTO deal ({}, {}, {}, {}, {}) VALUES ($1, $2, $3, $4, $5)"#,
+        "deal_oa_id", "deal_price", "deal_percentage", "deal_reference", "deal_promo",
     );
     let _ = db_client
         .query(
             &deal_insert_req,
-            &[&id, &deal.deal_price, &deal.deal_percentage],
+            &[
+                &id,
+                &deal.deal_price,
+                &deal.deal_percentage,
+                &deal.deal_reference,
+                &deal.deal_promo,
+            ],
         )
         .await?;
     DB_IO.with_label_values(&["insert", "deal"]).inc();
@@ -167,35 +338,145 @@ pub async fn insert_into_deal_table(db_client: &Client, id: i32, deal: &Deal) ->
 }
 
 pub async fn insert_into_reference_table(
-    db_client: &Client,
+    db_client: &impl GenericClient,
+    id: i32,
+    references: &HashMap<String, Reference>,
+) -> Result<(), Error> {
+    insert_references(db_client, "ref_oa_id", id, references).await
+}
+
+/// `owner_column` tells if the references belong to an announce or a catalogue entry
+async fn insert_references(
+    db_client: &impl GenericClient,
+    owner_column: &str,
     id: i32,
     references: &HashMap<String, Reference>,
 ) -> Result<(), Error> {
     let references_insert_req = format!(
-        r#"INSERT INTO reference ({}, {}, {}, {}) VALUES ($1, $2, $3, $4)"#,
-        "ref_oa_id", "ref_name", "ref_price", "ref_url",
+        r#"INSERT INTO reference ({}, {}, {}, {}, {}, {}, {}, {}, {}, {}) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10)"#,
+        owner_column,
+        "ref_name",
+        "ref_price",
+        "ref_promo_price",
+        "ref_promo_end",
+        "ref_url",
+        "ref_available",
+        "ref_landed_price",
+        "ref_aggregated",
+        "ref_match_score",
     );
 
     for val in references.values() {
         let _ = db_client
             .query(
                 &references_insert_req,
-                &[&id, &val.name, &val.price, &val.url],
+                &[
+                    &id,
+                    &val.name,
+                    &val.price,
+                    &val.promo_price,
+                    &val.promo_end,
+                    &val.url,
+                    &val.available,
+                    &val.landed_price,
+                    &val.aggregated,
+                    &val.match_score,
+                ],
             )
             .await?;
+        insert_reference_price_into_history(db_client, owner_column, id, val).await?;
     }
     DB_IO.with_label_values(&["insert", "reference"]).inc();
     Ok(())
 }
 
+/// Append the price of the reference to its history when it changed since the last one seen
+async fn insert_reference_price_into_history(
+    db_client: &impl GenericClient,
+    owner_column: &str,
+    id: i32,
+    reference: &Reference,
+) -> Result<(), Error> {
+    let history_column = match owner_column {
+        "ref_game_id" => "rph_game_id",
+        _ => "rph_oa_id",
+    };
+    let inserted = db_client
+        .execute(
+            &format!(
+                "INSERT INTO reference_price_history ({0}, rph_reseller, rph_price, rph_promo_price)
+                SELECT $1::integer, $2::text, $3::real, $4::real
+                WHERE NOT EXISTS (
+                    SELECT 1 FROM (
+                        SELECT rph_price, rph_promo_price FROM reference_price_history
+                        WHERE {0} = $1 AND rph_reseller = $2 ORDER BY rph_id DESC LIMIT 1
+                    ) last
+                    WHERE last.rph_price = $3 AND last.rph_promo_price IS NOT DISTINCT FROM $4
+                )",
+                history_column
+            ),
+            &[
+                &id,
+                &reference.name,
+                &reference.price,
+                &reference.promo_price,
+            ],
+        )
+        .await?;
+    if inserted > 0 {
+        DB_IO
+            .with_label_values(&["insert", "reference_price_history"])
+            .inc();
+    }
+    Ok(())
+}
+
+/// Append the price of the announce to its history when it changed since the last one seen
+async fn insert_announce_price_into_history(
+    db_client: &impl GenericClient,
+    id: i32,
+    price: f32,
+) -> Result<(), Error> {
+    let inserted = db_client
+        .execute(
+            "INSERT INTO announce_price_history (aph_oa_id, aph_price)
+            SELECT $1::integer, $2::real
+            WHERE NOT EXISTS (
+                SELECT 1 FROM (
+                    SELECT aph_price FROM announce_price_history
+                    WHERE aph_oa_id = $1 ORDER BY aph_id DESC LIMIT 1
+                ) last
+                WHERE last.aph_price = $2
+            )",
+            &[&id, &price],
+        )
+        .await?;
+    if inserted > 0 {
+        DB_IO
+            .with_label_values(&["insert", "announce_price_history"])
+            .inc();
+    }
+    Ok(())
+}
+
 pub async fn insert_into_reviewer_table(
-    db_client: &Client,
+    db_client: &impl GenericClient,
+    id: i32,
+    reviewers: &HashMap<std::string::String, Reviewer>,
+) -> Result<(), Error> {
+    insert_reviewers(db_client, "reviewer_oa_id", id, reviewers).await
+}
+
+/// `owner_column` tells if the reviewers belong to an announce or a catalogue entry
+async fn insert_reviewers(
+    db_client: &impl GenericClient,
+    owner_column: &str,
     id: i32,
     reviewers: &HashMap<std::string::String, Reviewer>,
 ) -> Result<(), Error> {
     let references_insert_req = format!(
         r#"INSERT INTO reviewer ({}, {}, {}, {}, {}) VALUES ($1, $2, $3, $4, $5)"#,
-        "reviewer_oa_id", "reviewer_name", "reviewer_url", "reviewer_note", "reviewer_number",
+        owner_column, "reviewer_name", "reviewer_url", "reviewer_note", "reviewer_number",
     );
 
     for val in reviewers.values() {
@@ -210,8 +491,15 @@ pub async fn insert_into_reviewer_table(
     Ok(())
 }
 
-pub async fn insert_announce_into_db(db_client: &Client, game: &Box<Game>) -> Result<(), Error> {
+/// Insert an announce with its seller, shipping, deal, references and reviews
+pub async fn insert_announce_into_db(db_client: &mut Client, game: &Game) -> Result<(), Error> {
     log::debug!("inserting {} into DB ", game.okkazeo_announce.name);
+    let transaction = db_client.transaction().await?;
+    let result = insert_announce(&transaction, game).await;
+    finish_transaction(transaction, "insert_announce", result).await
+}
+
+async fn insert_announce(db_client: &impl GenericClient, game: &Game) -> Result<(), Error> {
     //chck if seller already hs announes, if yes update, if not insert
     if check_if_seller_in_db(db_client, game.okkazeo_announce.seller.name.clone()).await? > 0 {
         log::debug!("seller {:?} present in DB", game.okkazeo_announce.seller);
@@ -227,13 +515,20 @@ pub async fn insert_announce_into_db(db_client: &Client, game: &Box<Game>) -> Re
     insert_into_okkazeo_announce_table(db_client, game).await?;
     insert_into_shipping_table(db_client, id, &game.okkazeo_announce.shipping).await?;
     insert_into_deal_table(db_client, id, &game.deal).await?;
-    insert_into_reference_table(db_client, id, &game.references).await?;
-    insert_into_reviewer_table(db_client, id, &game.review.reviews).await?;
+    // an announce of a catalogue game reads the references and reviews of the game
+    if game.catalogue_id.is_none() {
+        insert_into_reference_table(db_client, id, &game.references).await?;
+        insert_into_reviewer_table(db_client, id, &game.review.reviews).await?;
+    }
+    insert_announce_price_into_history(db_client, id, game.okkazeo_announce.price).await?;
 
     Ok(())
 }
 
-pub async fn update_seller_table_from_db(db_client: &Client, seller: &Seller) -> Result<(), Error> {
+pub async fn update_seller_table_from_db(
+    db_client: &impl GenericClient,
+    seller: &Seller,
+) -> Result<(), Error> {
     log::debug!("updating seller table");
     let references_insert_req = format!(
         r#"UPDATE seller SET {} = $1 WHERE {} = $2"#,
@@ -250,12 +545,17 @@ pub async fn update_seller_table_from_db(db_client: &Client, seller: &Seller) ->
     Ok(())
 }
 pub async fn update_okkazeo_announce_table_from_db(
-    db_client: &Client,
+    db_client: &impl GenericClient,
     game: &Game,
 ) -> Result<(), Error> {
     let references_insert_req = format!(
-        r#"UPDATE okkazeo_announce SET {} = $1, {} = $2, {} = $3 WHERE {} = $4"#,
-        "oa_last_modification_date", "oa_price", "oa_seller", "oa_id",
+        r#"UPDATE okkazeo_announce SET {} = $1, {} = $2, {} = $3, {} = $4, {} = $5 WHERE {} = $6"#,
+        "oa_last_modification_date",
+        "oa_price",
+        "oa_seller",
+        "oa_timed_out_resellers",
+        "oa_game_id",
+        "oa_id",
     );
 
     let _ = db_client
@@ -265,6 +565,8 @@ pub async fn update_okkazeo_announce_table_from_db(
                 &game.okkazeo_announce.last_modification_date,
                 &game.okkazeo_announce.price,
                 &game.okkazeo_announce.seller.name,
+                &game.timed_out_resellers,
+                &game.catalogue_id,
                 &(game.okkazeo_announce.id as i32),
             ],
         )
@@ -275,15 +577,25 @@ pub async fn update_okkazeo_announce_table_from_db(
     Ok(())
 }
 
-pub async fn update_deal_table(db_client: &Client, id: i32, deal: &Deal) -> Result<(), Error> {
+pub async fn update_deal_table(
+    db_client: &impl GenericClient,
+    id: i32,
+    deal: &Deal,
+) -> Result<(), Error> {
     let deal_insert_req = format!(
-        r#"UPDATE deal SET {} = $1, {} = $2 WHERE {} = $3"#,
-        "deal_price", "deal_percentage", "deal_oa_id",
+        r#"UPDATE deal SET {} = $1, {} = $2, {} = $3, {} = $4 WHERE {} = $5"#,
+        "deal_price", "deal_percentage", "deal_reference", "deal_promo", "deal_oa_id",
     );
     let _ = db_client
         .query(
             &deal_insert_req,
-            &[&deal.deal_price, &deal.deal_percentage, &id],
+            &[
+                &deal.deal_price,
+                &deal.deal_percentage,
+                &deal.deal_reference,
+                &deal.deal_promo,
+                &id,
+            ],
         )
         .await?;
     DB_IO.with_label_values(&["update", "deal"]).inc();
@@ -291,7 +603,10 @@ pub async fn update_deal_table(db_client: &Client, id: i32, deal: &Deal) -> Resu
     Ok(())
 }
 
-pub async fn delete_from_reference_table(db_client: &Client, id: i32) -> Result<(), Error> {
+pub async fn delete_from_reference_table(
+    db_client: &impl GenericClient,
+    id: i32,
+) -> Result<(), Error> {
     let result = db_client
         .execute("DELETE FROM reference WHERE ref_oa_id = $1", &[&id])
         .await;
@@ -305,7 +620,10 @@ pub async fn delete_from_reference_table(db_client: &Client, id: i32) -> Result<
     }
 }
 
-pub async fn delete_from_reviewer_table(db_client: &Client, id: i32) -> Result<(), Error> {
+pub async fn delete_from_reviewer_table(
+    db_client: &impl GenericClient,
+    id: i32,
+) -> Result<(), Error> {
     let result = db_client
         .execute("DELETE FROM reviewer WHERE reviewer_oa_id = $1", &[&id])
         .await;
@@ -319,8 +637,43 @@ pub async fn delete_from_reviewer_table(db_client: &Client, id: i32) -> Result<(
     }
 }
 
+/// Absent barcodes are stored as NULL
+fn gtin_to_db(gtin: Option<Gtin>) -> Option<i64> {
+    gtin.map(|g| g.value() as i64)
+}
+
+/// NULL and the 0 stored for the announces without barcode are read as None,
+/// other invalid barcodes are logged and ignored
+fn gtin_from_row(row: &Row, column: &str) -> Result<Option<Gtin>, Error> {
+    let code = match row.try_get::<&str, Option<i64>>(column)? {
+        None | Some(0) => return Ok(None),
+        Some(code) => code,
+    };
+    match u64::try_from(code)
+        .map_err(|_| GtinError::NotDigits)
+        .and_then(Gtin::try_from)
+    {
+        Ok(gtin) => Ok(Some(gtin)),
+        Err(e) => {
+            log::warn!("ignoring invalid {} {} : {}", column, code, e);
+            Ok(None)
+        }
+    }
+}
+
+/// Replace the references of an announce
 pub async fn update_reference_table(
-    db_client: &Client,
+    db_client: &mut Client,
+    id: i32,
+    refs: &HashMap<String, Reference>,
+) -> Result<(), Error> {
+    let transaction = db_client.transaction().await?;
+    let result = replace_references(&transaction, id, refs).await;
+    finish_transaction(transaction, "update_references", result).await
+}
+
+async fn replace_references(
+    db_client: &impl GenericClient,
     id: i32,
     refs: &HashMap<String, Reference>,
 ) -> Result<(), Error> {
@@ -328,8 +681,19 @@ pub async fn update_reference_table(
     insert_into_reference_table(db_client, id, refs).await
 }
 
+/// Replace the reviews of an announce
 pub async fn update_reviewer_table(
-    db_client: &Client,
+    db_client: &mut Client,
+    id: i32,
+    reviews: &Review,
+) -> Result<(), Error> {
+    let transaction = db_client.transaction().await?;
+    let result = replace_reviews(&transaction, id, reviews).await;
+    finish_transaction(transaction, "update_reviews", result).await
+}
+
+async fn replace_reviews(
+    db_client: &impl GenericClient,
     id: i32,
     reviews: &Review,
 ) -> Result<(), Error> {
@@ -337,8 +701,260 @@ pub async fn update_reviewer_table(
     insert_into_reviewer_table(db_client, id, &reviews.reviews).await
 }
 
-pub async fn update_game_from_db(db_client: &Client, game: &Game) -> Result<(), Error> {
+/// Update the announce and its deal
+pub async fn update_game_from_db(db_client: &mut Client, game: &Game) -> Result<(), Error> {
+    let transaction = db_client.transaction().await?;
+    let result = update_game(&transaction, game).await;
+    finish_transaction(transaction, "update_game", result).await
+}
+
+/// Update the announce and its deal, and replace its references and reviews
+/// after they were looked up again. An announce linked to a catalogue game
+/// keeps none, the game has them.
+pub async fn update_enriched_game_from_db(
+    db_client: &mut Client,
+    game: &Game,
+) -> Result<(), Error> {
+    let id = game.okkazeo_announce.id as i32;
+    let transaction = db_client.transaction().await?;
+    let result = async {
+        update_game(&transaction, game).await?;
+        if game.catalogue_id.is_some() {
+            delete_from_reference_table(&transaction, id).await?;
+            delete_from_reviewer_table(&transaction, id).await
+        } else {
+            replace_references(&transaction, id, &game.references).await?;
+            replace_reviews(&transaction, id, &game.review).await
+        }
+    }
+    .await;
+    finish_transaction(transaction, "update_enriched_game", result).await
+}
+
+/// Announces of the okkazeo csv left to handle one by one after `sync_okkazeo_rows_into_db`
+#[derive(Debug, Default)]
+pub struct OkkazeoSync {
+    /// In the csv but not in the DB, to look up then insert
+    pub new_ids: Vec<u32>,
+    /// Price, date or shipping fees changed, or resellers timed out during
+    /// the last lookup, their deal is computed again
+    pub changed_ids: Vec<u32>,
+    /// Announces removed from the DB as they left the csv
+    pub deleted: u64,
+}
+
+/// Bring the stored announces up to date with the okkazeo csv in a few
+/// statements: the rows are copied into a staging table, then the announces
+/// which left it are deleted, the prices, dates and shipping fees are updated
+/// and the announces needing a lookup are returned.
+pub async fn sync_okkazeo_rows_into_db(
+    db_client: &mut Client,
+    rows: &[OkkazeoRow],
+) -> Result<OkkazeoSync, Error> {
+    let transaction = db_client.transaction().await?;
+    let result = sync_okkazeo_rows(&transaction, rows).await;
+    finish_transaction(transaction, "sync_okkazeo_rows", result).await
+}
+
+async fn sync_okkazeo_rows(
+    transaction: &Transaction<'_>,
+    rows: &[OkkazeoRow],
+) -> Result<OkkazeoSync, Error> {
+    transaction
+        .batch_execute(
+            "CREATE TEMPORARY TABLE okkazeo_staging (
+                id integer PRIMARY KEY,
+                last_modification_date timestamptz,
+                price real NOT NULL,
+                shop2shop real,
+                colissimo real,
+                mondial_relay real,
+                relais_colis real,
+                hand_delivery boolean NOT NULL
+            ) ON COMMIT DROP",
+        )
+        .await?;
+
+    let sink = transaction
+        .copy_in(
+            "COPY okkazeo_staging (id, last_modification_date, price, shop2shop, colissimo,
+                mondial_relay, relais_colis, hand_delivery) FROM STDIN BINARY",
+        )
+        .await?;
+    let writer = BinaryCopyInWriter::new(
+        sink,
+        &[
+            Type::INT4,
+            Type::TIMESTAMPTZ,
+            Type::FLOAT4,
+            Type::FLOAT4,
+            Type::FLOAT4,
+            Type::FLOAT4,
+            Type::FLOAT4,
+            Type::BOOL,
+        ],
+    );
+    let mut writer = pin!(writer);
+    let mut copied = HashSet::new();
+    for row in rows {
+        // the stored date is kept rather than dropping the announce
+        let date = match row.last_modification_date() {
+            Ok(date) => Some(date),
+            Err(e) => {
+                log::error!("Failed to parse datetime \"{}\" : {}", row.date, e);
+                None
+            }
+        };
+        // the primary key would fail the whole copy
+        if !copied.insert(row.id) {
+            log::warn!("announce {} is twice in the csv", row.id);
+            continue;
+        }
+        writer
+            .as_mut()
+            .write(&[
+                &(row.id as i32),
+                &date,
+                &row.prix_annonce,
+                &row.shop2shop,
+                &row.colissimo,
+                &row.mondial_relay,
+                &row.relais_colis,
+                &row.rmp,
+            ])
+            .await?;
+    }
+    let staged = writer.as_mut().finish().await?;
+    DB_IO.with_label_values(&["copy", "okkazeo_staging"]).inc();
+    log::debug!("staged {} okkazeo rows", staged);
+
+    let deleted = transaction
+        .execute(
+            "DELETE FROM okkazeo_announce oa
+            WHERE NOT EXISTS (SELECT 1 FROM okkazeo_staging s WHERE s.id = oa.oa_id)",
+            &[],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["delete", "okkazeo_announce"])
+        .inc();
+
+    let mut changed_ids: Vec<i32> = vec![];
+    let res = transaction
+        .query(
+            "WITH updated AS (
+                UPDATE okkazeo_announce oa
+                SET oa_price = s.price,
+                    oa_last_modification_date =
+                        COALESCE(s.last_modification_date, oa.oa_last_modification_date)
+                FROM okkazeo_staging s
+                WHERE oa.oa_id = s.id
+                    AND (oa.oa_price, oa.oa_last_modification_date) IS DISTINCT FROM
+                        (s.price, COALESCE(s.last_modification_date, oa.oa_last_modification_date))
+                RETURNING oa.oa_id, oa.oa_price
+            ), history AS (
+                INSERT INTO announce_price_history (aph_oa_id, aph_price)
+                SELECT u.oa_id, u.oa_price FROM updated u
+                WHERE u.oa_price IS DISTINCT FROM (
+                    SELECT aph_price FROM announce_price_history
+                    WHERE aph_oa_id = u.oa_id ORDER BY aph_id DESC LIMIT 1
+                )
+            )
+            SELECT oa_id FROM updated",
+            &[],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["update", "okkazeo_announce"])
+        .inc();
+    for row in res {
+        changed_ids.push(row.try_get("oa_id")?);
+    }
+
+    let res = transaction
+        .query(
+            "WITH staged AS (
+                SELECT s.id, fee.shipper, fee.price
+                FROM okkazeo_staging s
+                JOIN okkazeo_announce oa ON oa.oa_id = s.id
+                CROSS JOIN LATERAL (VALUES
+                    ('shop2shop', s.shop2shop),
+                    ('colissimo', s.colissimo),
+                    ('mondial_relay', s.mondial_relay),
+                    ('relais_colis', s.relais_colis),
+                    ('hand_delivery', CASE WHEN s.hand_delivery THEN 0::real END)
+                ) AS fee (shipper, price)
+                WHERE fee.price IS NOT NULL
+            ), removed AS (
+                DELETE FROM shipping sh
+                USING okkazeo_staging s
+                WHERE sh.ship_oa_id = s.id AND NOT EXISTS (
+                    SELECT 1 FROM staged
+                    WHERE staged.id = sh.ship_oa_id AND staged.shipper = sh.ship_shipper
+                )
+                RETURNING sh.ship_oa_id
+            ), upserted AS (
+                INSERT INTO shipping (ship_oa_id, ship_shipper, ship_price)
+                SELECT id, shipper, price FROM staged
+                ON CONFLICT (ship_oa_id, ship_shipper) DO UPDATE
+                SET ship_price = EXCLUDED.ship_price
+                WHERE shipping.ship_price IS DISTINCT FROM EXCLUDED.ship_price
+                RETURNING ship_oa_id
+            )
+            SELECT ship_oa_id FROM removed UNION SELECT ship_oa_id FROM upserted",
+            &[],
+        )
+        .await?;
+    DB_IO.with_label_values(&["update", "shipping"]).inc();
+    for row in res {
+        changed_ids.push(row.try_get("ship_oa_id")?);
+    }
+
+    let res = transaction
+        .query(
+            "SELECT oa_id FROM okkazeo_announce WHERE cardinality(oa_timed_out_resellers) > 0",
+            &[],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "okkazeo_announce"])
+        .inc();
+    for row in res {
+        changed_ids.push(row.try_get("oa_id")?);
+    }
+    changed_ids.sort_unstable();
+    changed_ids.dedup();
+
+    let res = transaction
+        .query(
+            "SELECT s.id FROM okkazeo_staging s
+            WHERE NOT EXISTS (SELECT 1 FROM okkazeo_announce oa WHERE oa.oa_id = s.id)",
+            &[],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "okkazeo_staging"])
+        .inc();
+    let new_ids = res
+        .iter()
+        .map(|row| row.try_get::<&str, i32>("id").map(|id| id as u32))
+        .collect::<Result<Vec<u32>, Error>>()?;
+
+    Ok(OkkazeoSync {
+        new_ids,
+        changed_ids: changed_ids.into_iter().map(|id| id as u32).collect(),
+        deleted,
+    })
+}
+
+async fn update_game(db_client: &impl GenericClient, game: &Game) -> Result<(), Error> {
     update_okkazeo_announce_table_from_db(db_client, game).await?;
+    insert_announce_price_into_history(
+        db_client,
+        game.okkazeo_announce.id as i32,
+        game.okkazeo_announce.price,
+    )
+    .await?;
     update_deal_table(db_client, game.okkazeo_announce.id as i32, &game.deal).await?;
     /*update_shipping_table(
         db_client,
@@ -354,6 +970,10 @@ pub async fn update_game_from_db(db_client: &Client, game: &Game) -> Result<(),
 pub async fn craft_game_from_row(db_client: &Client, row: Row) -> Result<Game, Error> {
     let id: i32 = row.try_get("oa_id")?;
     let nb_announces: i32 = row.try_get("seller_nb_announces")?;
+    let catalogue_id = row
+        .try_get::<&str, Option<i32>>("oa_game_id")
+        .ok()
+        .flatten();
 
     let game = Game {
         okkazeo_announce: OkkazeoAnnounce {
@@ -370,55 +990,77 @@ pub async fn craft_game_from_row(db_client: &Client, row: Row) -> Result<Game, E
                 nb_announces: nb_announces as u32,
                 is_pro: row.try_get("seller_is_pro")?,
             },
-            barcode: match row.try_get::<&str, i64>("oa_barcode") {
-                Ok(v) => Some(v as u64),
-                Err(_) => None,
-            },
+            barcode: gtin_from_row(&row, "oa_barcode")?,
             city: row.try_get("oa_city")?,
             last_modification_date: row.try_get("oa_last_modification_date")?,
         },
-        references: select_references_from_db(db_client, id).await?,
-        review: select_reviews_from_db(db_client, id).await?,
+        references: match catalogue_id {
+            Some(game_id) => select_references(db_client, "ref_game_id", game_id).await?,
+            None => select_references_from_db(db_client, id).await?,
+        },
+        review: match catalogue_id {
+            Some(game_id) => select_reviews(db_client, "reviewer_game_id", game_id).await?,
+            None => select_reviews_from_db(db_client, id).await?,
+        },
         deal: Deal {
             deal_price: row.try_get("deal_price")?,
             deal_percentage: row.try_get("deal_percentage")?,
+            deal_reference: row.try_get("deal_reference")?,
+            deal_promo: row
+                .try_get::<&str, Option<bool>>("deal_promo")?
+                .unwrap_or_default(),
         },
+        timed_out_resellers: row
+            .try_get::<&str, Option<Vec<String>>>("oa_timed_out_resellers")
+            .ok()
+            .flatten()
+            .unwrap_or_default(),
+        catalogue_id,
+        bgg: match catalogue_id {
+            Some(game_id) => select_bgg_infos_from_db(db_client, game_id).await?,
+            None => None,
+        },
+        price_history: PriceHistory::default(),
     };
 
     Ok(game)
 }
 
+/// Count the announces of every seller, the sellers whose announces were all removed get 0
 pub async fn update_sellers_nb_announces_from_db(db_client: &Client) -> u64 {
     log::debug!("[DB] updating sellers nb_announces from db");
-    let select_req = format!(
-        "UPDATE seller
+    let select_req = "UPDATE seller
          SET seller_nb_announces = subquery.nb_announces
         FROM (
-            SELECT oa_seller, COUNT(*) AS nb_announces
-            FROM okkazeo_announce
-            GROUP BY oa_seller
+            SELECT s.seller_id, COALESCE(c.nb_announces, 0) AS nb_announces
+            FROM seller s
+            LEFT JOIN (
+                SELECT oa_seller, COUNT(*) AS nb_announces
+                FROM okkazeo_announce
+                GROUP BY oa_seller
+            ) c ON c.oa_seller = s.seller_name
         ) AS subquery
-        WHERE seller.seller_name = subquery.oa_seller"
-    );
+        WHERE seller.seller_id = subquery.seller_id
+            AND seller.seller_nb_announces IS DISTINCT FROM subquery.nb_announces"
+        .to_string();
 
     match db_client.execute(&select_req, &[]).await {
         Ok(r) => r,
         Err(e) => {
             log::error!("error updating sellers nb_announces : {}", e);
-            return 0;
+            0
         }
     }
 }
 
 pub async fn select_game_with_id_from_db(db_client: &Client, id: u32) -> Option<Game> {
     log::debug!("[DB] select game with id from db : {}", id);
-    let select_req = format!(
-        "SELECT *
+    let select_req = "SELECT *
                 FROM okkazeo_announce oa
                 JOIN deal d on d.deal_oa_id = oa.oa_id
                 JOIN seller s on s.seller_name = oa.oa_seller
                 WHERE oa.oa_id = $1"
-    );
+        .to_string();
 
     let res = match db_client.query(&select_req, &[&(id as i32)]).await {
         Ok(r) => r,
@@ -442,11 +1084,33 @@ pub async fn select_game_with_id_from_db(db_client: &Client, id: u32) -> Option<
     }
 }
 
+/// Condition on the searched name `$1`. Unless it must be exact, the name
+/// matches when it is a part of the title, when it is close to a part of the
+/// title (typos) or when their french words share the same stems
+fn sql_name_filter(exact_match: bool) -> &'static str {
+    if exact_match {
+        "oa.oa_name = $1"
+    } else {
+        "(unaccent(oa.oa_name) ilike unaccent('%' || $1::text || '%')
+            OR f_unaccent($1) <% f_unaccent(oa.oa_name)
+            OR to_tsvector('french', f_unaccent(oa.oa_name)) @@ plainto_tsquery('french', f_unaccent($1)))"
+    }
+}
+
+/// Titles closest to the searched name `$1` first, the latest announces when nothing is searched
+const SQL_NAME_RELEVANCE: &str = "word_similarity(f_unaccent($1), f_unaccent(oa.oa_name)) DESC,
+    ts_rank(to_tsvector('french', f_unaccent(oa.oa_name)), plainto_tsquery('french', f_unaccent($1))) DESC,
+    oa.oa_last_modification_date DESC";
+
+/// Reviews of an announce, those of its catalogue game when it is linked to one
+const SQL_ANNOUNCE_REVIEWERS: &str =
+    "LEFT JOIN reviewer r on (r.reviewer_game_id = oa.oa_game_id OR r.reviewer_oa_id = oa.oa_id)";
+
+/// Keep the announces whose note, aggregated like `Review::compute_average_note`, is at least `note`
 fn sql_partial_rating_filter(note: Option<f32>) -> String {
-    return note.map_or("".to_string(), |n| format!(
-        "HAVING SUM(CASE WHEN r.reviewer_number > 0 THEN r.reviewer_note * r.reviewer_number ELSE 0 END) / SUM(CASE WHEN r.reviewer_number > 0 THEN r.reviewer_number ELSE 1 END) >= {}",
-        n
-    ));
+    note.map_or("".to_string(), |n| {
+        format!("HAVING {} >= {}", RATING_POLICY.sql_average(), n)
+    })
 }
 
 pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<Games, Error> {
@@ -454,6 +1118,7 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
     let order_by = match state.sort.sort.as_str() {
         "price" => "d.deal_price ASC",
         "percent" => "d.deal_percentage ASC",
+        "relevance" => SQL_NAME_RELEVANCE,
         _ => "oa.oa_last_modification_date DESC",
     };
 
@@ -476,22 +1141,25 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
             oa.oa_price,
             oa.oa_url,
             oa.oa_extension,
+            oa.oa_barcode,
             oa.oa_image,
             oa.oa_city,
+            oa.oa_game_id,
             s.seller_name,
             s.seller_url,
             s.seller_is_pro,
             s.seller_nb_announces,
             d.deal_price,
-            d.deal_percentage
+            d.deal_percentage,
+            d.deal_reference,
+            d.deal_promo
          FROM okkazeo_announce oa
                 JOIN deal d on d.deal_oa_id = oa.oa_id
-                LEFT JOIN reviewer r on r.reviewer_oa_id = oa.oa_id
                 JOIN seller s on s.seller_name = oa.oa_seller
                 WHERE oa.oa_id IN (
                     SELECT oa.oa_id
                     FROM okkazeo_announce oa
-                    LEFT JOIN reviewer r on r.reviewer_oa_id = oa.oa_id
+                    {}
                     JOIN seller s on s.seller_name = oa.oa_seller
                     WHERE {} AND unaccent(oa.oa_city) ilike unaccent($2)
                     AND unaccent(s.seller_name) ilike unaccent($3)
@@ -514,32 +1182,32 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
                     oa.oa_barcode,
                     oa.oa_image,
                     oa.oa_city,
+                    oa.oa_game_id,
                     s.seller_name,
                     s.seller_url,
                     s.seller_is_pro,
                     s.seller_nb_announces,
                     d.deal_price,
-                    d.deal_percentage
+                    d.deal_percentage,
+                    d.deal_reference,
+                    d.deal_promo
                 ORDER BY {} LIMIT $6 OFFSET $7;",
-        if state.filters.exact_match.is_some() {
-            "oa.oa_name = $1"
-        } else {
-            "unaccent(oa.oa_name) ilike unaccent($1)"
-        },
+        SQL_ANNOUNCE_REVIEWERS,
+        sql_name_filter(state.filters.exact_match.is_some()),
         if state.filters.pro.is_some() {
             "AND NOT s.seller_is_pro"
         } else {
             ""
         },
         type_filter,
-        if state.filters.date.is_some() {
-            format!(
+        state
+            .filters
+            .date
+            .as_ref()
+            .map_or(String::new(), |date| format!(
                 "AND oa.oa_last_modification_date >= '{}'",
-                state.filters.date.as_ref().unwrap()
-            )
-        } else {
-            String::new()
-        },
+                date
+            )),
         if state.filters.delivery.is_some() {
             "AND oa.oa_id in ( select distinct ship_oa_id from shipping where ship_shipper != 'hand_delivery')"
         } else {
@@ -560,17 +1228,13 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
         match_start = "(";
     }
 
-    let name_match = if state.filters.exact_match.is_some() {
-        &format!("{}", state.filters.name.clone().unwrap_or_default())
-    } else {
-        &format!("%{}%", state.filters.name.clone().unwrap_or_default())
-    };
+    let name_match = state.filters.name.clone().unwrap_or_default();
 
     let res = db_client
         .query(
             &select_req,
             &[
-                name_match,
+                &name_match,
                 &format!(
                     "%{}{}%",
                     match_start,
@@ -581,7 +1245,7 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
                     state.filters.vendor.as_ref().unwrap_or(&String::new())
                 ),
                 &(state.filters.min_price.unwrap_or_default() as f32),
-                &(state.filters.max_price.unwrap_or_else(|| 10000) as f32),
+                &(state.filters.max_price.unwrap_or(10000) as f32),
                 &(state.pagination.per_page as i64),
                 &((state.pagination.page * state.pagination.per_page) as i64),
             ],
@@ -608,6 +1272,17 @@ pub async fn select_games_from_db(db_client: &Client, state: &State) -> Result<G
         };
         games.games.push(Box::new(game))
     }
+    let announces: Vec<(i32, Option<i32>)> = games
+        .games
+        .iter()
+        .map(|game| (game.okkazeo_announce.id as i32, game.catalogue_id))
+        .collect();
+    let mut histories = select_announces_price_history_from_db(db_client, &announces).await?;
+    for game in games.games.iter_mut() {
+        game.price_history = histories
+            .remove(&(game.okkazeo_announce.id as i32))
+            .unwrap_or_default();
+    }
     DB_IO.with_label_values(&["select", "game"]).inc();
 
     Ok(games)
@@ -630,7 +1305,7 @@ pub async fn select_count_filtered_games_from_db(
                 SELECT oa.oa_id
                 FROM okkazeo_announce oa
                 JOIN deal d on d.deal_oa_id = oa.oa_id
-                LEFT JOIN reviewer r on r.reviewer_oa_id = oa.oa_id
+                {}
                 JOIN seller s on s.seller_name = oa.oa_seller
                 WHERE {} AND unaccent(oa.oa_city) ilike unaccent($2)
                 AND unaccent(s.seller_name) ilike unaccent($3)
@@ -642,25 +1317,18 @@ pub async fn select_count_filtered_games_from_db(
                 GROUP BY oa.oa_id
                 {}
         ) AS c;",
-        if filters.exact_match.is_some() {
-            "oa.oa_name = $1"
-        } else {
-            "unaccent(oa.oa_name) ilike unaccent($1)"
-        },
+        SQL_ANNOUNCE_REVIEWERS,
+        sql_name_filter(filters.exact_match.is_some()),
         if filters.pro.is_some() {
             "AND NOT s.seller_is_pro"
         } else {
             ""
         },
         type_filter,
-        if filters.date.is_some() {
-            format!(
-                "AND oa.oa_last_modification_date >= '{}'",
-                filters.date.as_ref().unwrap()
-            )
-        } else {
-            String::new()
-        },
+        filters.date.as_ref().map_or(String::new(), |date| format!(
+            "AND oa.oa_last_modification_date >= '{}'",
+            date
+        )),
         if filters.delivery.is_some() {
             "AND oa.oa_id in ( select distinct ship_oa_id from shipping where ship_shipper != 'hand_delivery')"
         } else {
@@ -674,38 +1342,36 @@ pub async fn select_count_filtered_games_from_db(
         match_start = "(";
     }
 
-    let name_match = if filters.exact_match.is_some() {
-        &format!("{}", filters.name.unwrap_or_default())
-    } else {
-        &format!("%{}%", filters.name.unwrap_or_default())
-    };
+    let name_match = filters.name.unwrap_or_default();
 
     let res = db_client
         .query(
             &select_req,
             &[
-                name_match,
+                &name_match,
                 &format!("%{}{}%", match_start, filters.city.unwrap_or_default()),
                 &format!("%{}%", filters.vendor.unwrap_or_default()),
                 &(filters.min_price.unwrap_or_default() as f32),
-                &(filters.max_price.unwrap_or_else(|| 10000) as f32),
+                &(filters.max_price.unwrap_or(10000) as f32),
             ],
         )
         .await?;
 
-    let nbr: i64 = res.get(0).unwrap().try_get(0)?;
+    let nbr: i64 = res.first().unwrap().try_get(0)?;
     DB_IO.with_label_values(&["select", "game"]).inc();
 
     Ok(nbr)
 }
 
-pub async fn check_if_seller_in_db(db_client: &Client, name: String) -> Result<i32, Error> {
+pub async fn check_if_seller_in_db(
+    db_client: &impl GenericClient,
+    name: String,
+) -> Result<i32, Error> {
     log::debug!("checkin if seller is in db");
-    let select_req = format!(
-        "SELECT seller_name
+    let select_req = "SELECT seller_name
                 FROM seller
                 WHERE seller_name = $1"
-    );
+        .to_string();
 
     let res = db_client.query(&select_req, &[&name]).await?;
     DB_IO.with_label_values(&["select", "seller"]).inc();
@@ -717,11 +1383,10 @@ pub async fn select_shipping_from_db(
     db_client: &Client,
     id: i32,
 ) -> Result<HashMap<String, f32>, Error> {
-    let select_req = format!(
-        "SELECT *
+    let select_req = "SELECT *
                 FROM shipping
                 WHERE ship_oa_id = $1"
-    );
+        .to_string();
 
     let res = db_client.query(&select_req, &[&id]).await?;
 
@@ -741,10 +1406,8 @@ pub async fn select_intervalled_ids_from_oa_table_from_db(
     start_date: DateTime<Utc>,
     end_date: DateTime<Utc>,
 ) -> Result<Vec<i32>, Error> {
-    let select_req = format!(
-        "SELECT oa_id
-                FROM okkazeo_announce oa WHERE oa_last_modification_date > $1 AND oa_last_modification_date < $2"
-    );
+    let select_req = "SELECT oa_id
+                FROM okkazeo_announce oa WHERE oa_last_modification_date > $1 AND oa_last_modification_date < $2".to_string();
 
     let res = db_client
         .query(&select_req, &[&start_date, &end_date])
@@ -757,10 +1420,9 @@ pub async fn select_intervalled_ids_from_oa_table_from_db(
 }
 
 pub async fn select_all_ids_from_oa_table_from_db(db_client: &Client) -> Result<Vec<i32>, Error> {
-    let select_req = format!(
-        "SELECT oa_id
+    let select_req = "SELECT oa_id
                 FROM okkazeo_announce"
-    );
+        .to_string();
 
     let res = db_client.query(&select_req, &[]).await?;
     DB_IO
@@ -773,11 +1435,20 @@ pub async fn select_all_ids_from_oa_table_from_db(db_client: &Client) -> Result<
 pub async fn select_references_from_db(
     db_client: &Client,
     id: i32,
+) -> Result<HashMap<String, Reference>, Error> {
+    select_references(db_client, "ref_oa_id", id).await
+}
+
+async fn select_references(
+    db_client: &Client,
+    owner_column: &str,
+    id: i32,
 ) -> Result<HashMap<String, Reference>, Error> {
     let select_req = format!(
         "SELECT *
                 FROM reference
-                WHERE ref_oa_id = $1"
+                WHERE {} = $1",
+        owner_column
     );
 
     let res = db_client.query(&select_req, &[&id]).await?;
@@ -787,7 +1458,39 @@ pub async fn select_references_from_db(
         let name: String = row.try_get("ref_name")?;
         let price = row.try_get("ref_price")?;
         let url = row.try_get("ref_url")?;
-        refs.insert(name.clone(), Reference { name, price, url });
+        let promo_price: Option<f32> = row.try_get("ref_promo_price")?;
+        let promo_end = row.try_get("ref_promo_end")?;
+        let available = row.try_get("ref_available")?;
+        // references stored before landed prices existed have none
+        let landed_price = row
+            .try_get::<&str, Option<f32>>("ref_landed_price")
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| shipping::landed_price(&name, promo_price.unwrap_or(price)));
+        let aggregated = row
+            .try_get::<&str, Option<bool>>("ref_aggregated")
+            .ok()
+            .flatten()
+            .unwrap_or_default();
+        let match_score = row
+            .try_get::<&str, Option<f32>>("ref_match_score")
+            .ok()
+            .flatten();
+        refs.insert(
+            name.clone(),
+            Reference {
+                name,
+                price,
+                promo_price,
+                promo_end,
+                url,
+                available,
+                landed_price,
+                aggregated,
+                match_score,
+                product_title: None,
+            },
+        );
     }
 
     DB_IO.with_label_values(&["select", "reference"]).inc();
@@ -795,10 +1498,15 @@ pub async fn select_references_from_db(
 }
 
 pub async fn select_reviews_from_db(db_client: &Client, id: i32) -> Result<Review, Error> {
+    select_reviews(db_client, "reviewer_oa_id", id).await
+}
+
+async fn select_reviews(db_client: &Client, owner_column: &str, id: i32) -> Result<Review, Error> {
     let select_req = format!(
         "SELECT *
                 FROM reviewer
-                WHERE reviewer_oa_id = $1"
+                WHERE {} = $1",
+        owner_column
     );
 
     let res = db_client.query(&select_req, &[&id]).await?;
@@ -822,7 +1530,7 @@ pub async fn select_reviews_from_db(db_client: &Client, id: i32) -> Result<Revie
 
     let mut rev = Review {
         reviews: revs,
-        average_note: 0.0,
+        ..Default::default()
     };
     rev.compute_average_note();
     DB_IO.with_label_values(&["select", "reviews"]).inc();
@@ -830,11 +1538,1163 @@ pub async fn select_reviews_from_db(db_client: &Client, id: i32) -> Result<Revie
     Ok(rev)
 }
 
-lazy_static! {
-    static ref DB_IO: IntCounterVec = register_int_counter_vec!(
-        "db_io",
-        "Number of select/delete/insert on the db",
-        &["operation", "table"]
-    )
-    .unwrap();
+pub async fn select_catalogue_entry_from_db(
+    db_client: &Client,
+    barcode: Option<Gtin>,
+    key: &str,
+) -> Result<Option<CatalogueEntry>, Error> {
+    // an entry with another barcode is another edition of the game
+    let select_req = "SELECT *
+                FROM game
+                WHERE game_barcode = $1
+                OR (game_key = $2 AND ($1::bigint IS NULL OR game_barcode IS NULL))
+                ORDER BY game_barcode IS NULL
+                LIMIT 1";
+
+    let res = db_client
+        .query(select_req, &[&gtin_to_db(barcode), &key])
+        .await?;
+    DB_IO.with_label_values(&["select", "game"]).inc();
+
+    match res.first() {
+        Some(row) => Ok(Some(catalogue_entry_from_row(db_client, row).await?)),
+        None => Ok(None),
+    }
+}
+
+pub async fn select_catalogue_entry_with_id_from_db(
+    db_client: &Client,
+    id: i32,
+) -> Result<Option<CatalogueEntry>, Error> {
+    let res = db_client
+        .query("SELECT * FROM game WHERE game_id = $1", &[&id])
+        .await?;
+    DB_IO.with_label_values(&["select", "game"]).inc();
+    match res.first() {
+        Some(row) => Ok(Some(catalogue_entry_from_row(db_client, row).await?)),
+        None => Ok(None),
+    }
+}
+
+/// Catalogue entry of a game row with its cached references and reviews
+async fn catalogue_entry_from_row(db_client: &Client, row: &Row) -> Result<CatalogueEntry, Error> {
+    let id: i32 = row.try_get("game_id")?;
+    Ok(CatalogueEntry {
+        id: Some(id),
+        title: row.try_get("game_title")?,
+        key: row.try_get("game_key")?,
+        barcode: gtin_from_row(row, "game_barcode")?,
+        bgg: bgg_infos_from_row(row)?,
+        references: select_references(db_client, "ref_game_id", id).await?,
+        review: select_reviews(db_client, "reviewer_game_id", id).await?,
+        updated: row.try_get("game_updated")?,
+    })
+}
+
+/// Insert the entry, or update it when it has an id or when an entry of the
+/// same barcode, bgg id or key exists, and replace its cached references and
+/// reviews. The deals of the announces linked to it are computed again with
+/// the new references. Returns the id of the entry.
+pub async fn upsert_catalogue_entry_into_db(
+    db_client: &mut Client,
+    entry: &CatalogueEntry,
+) -> Result<i32, Error> {
+    let transaction = db_client.transaction().await?;
+    let result = upsert_catalogue_entry(&transaction, entry).await;
+    finish_transaction(transaction, "upsert_catalogue_entry", result).await
+}
+
+async fn upsert_catalogue_entry(
+    db_client: &impl GenericClient,
+    entry: &CatalogueEntry,
+) -> Result<i32, Error> {
+    let barcode = gtin_to_db(entry.barcode);
+    let bgg = entry.bgg.clone().unwrap_or_default();
+    let as_i32 = |v: Option<u32>| v.map(|v| v as i32);
+    let (bgg_id, year, min_players, max_players, playing_time, min_age) = (
+        entry.bgg.as_ref().map(|b| b.id as i32),
+        bgg.year,
+        as_i32(bgg.min_players),
+        as_i32(bgg.max_players),
+        as_i32(bgg.playing_time),
+        as_i32(bgg.min_age),
+    );
+    let existing = match entry.id {
+        Some(id) => Some(id),
+        None => select_catalogue_entry_id(db_client, barcode, bgg_id, &entry.key).await?,
+    };
+    let params: [&(dyn ToSql + Sync); 14] = [
+        &entry.title,
+        &entry.key,
+        &barcode,
+        &bgg_id,
+        &year,
+        &min_players,
+        &max_players,
+        &playing_time,
+        &min_age,
+        &bgg.weight,
+        &bgg.categories,
+        &bgg.mechanics,
+        &entry.updated,
+        &existing,
+    ];
+    let id: i32 = match existing {
+        Some(_) => {
+            let row = db_client
+                .query_one(
+                    "UPDATE game SET game_title = $1, game_key = $2, game_barcode = $3, game_bgg_id = $4,
+                        game_year = $5, game_min_players = $6, game_max_players = $7, game_playing_time = $8,
+                        game_min_age = $9, game_weight = $10, game_categories = $11, game_mechanics = $12,
+                        game_updated = $13
+                    WHERE game_id = $14 RETURNING game_id",
+                    &params,
+                )
+                .await?;
+            DB_IO.with_label_values(&["update", "game"]).inc();
+            row.try_get("game_id")?
+        }
+        None => {
+            let row = db_client
+                .query_one(
+                    "INSERT INTO game (game_title, game_key, game_barcode, game_bgg_id,
+                        game_year, game_min_players, game_max_players, game_playing_time,
+                        game_min_age, game_weight, game_categories, game_mechanics, game_updated)
+                    VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12, $13) RETURNING game_id",
+                    &params[..13],
+                )
+                .await?;
+            DB_IO.with_label_values(&["insert", "game"]).inc();
+            row.try_get("game_id")?
+        }
+    };
+
+    db_client
+        .execute("DELETE FROM reference WHERE ref_game_id = $1", &[&id])
+        .await?;
+    DB_IO.with_label_values(&["delete", "reference"]).inc();
+    insert_references(db_client, "ref_game_id", id, &entry.references).await?;
+
+    db_client
+        .execute("DELETE FROM reviewer WHERE reviewer_game_id = $1", &[&id])
+        .await?;
+    DB_IO.with_label_values(&["delete", "reviewer"]).inc();
+    insert_reviewers(db_client, "reviewer_game_id", id, &entry.review.reviews).await?;
+
+    update_linked_deals(db_client, id, &entry.references).await?;
+    Ok(id)
+}
+
+/// Entry a new lookup belongs to: the one of its barcode, else one of the
+/// same bgg id or key which is not another edition with another barcode
+async fn select_catalogue_entry_id(
+    db_client: &impl GenericClient,
+    barcode: Option<i64>,
+    bgg_id: Option<i32>,
+    key: &str,
+) -> Result<Option<i32>, Error> {
+    let res = db_client
+        .query(
+            "SELECT game_id FROM game
+            WHERE game_barcode = $1
+            OR (($3::integer IS NOT NULL AND game_bgg_id = $3) OR game_key = $2)
+                AND ($1::bigint IS NULL OR game_barcode IS NULL)
+            ORDER BY game_barcode = $1 DESC NULLS LAST, game_bgg_id = $3 DESC NULLS LAST, game_id
+            LIMIT 1",
+            &[&barcode, &key, &bgg_id],
+        )
+        .await?;
+    DB_IO.with_label_values(&["select", "game"]).inc();
+    res.first().map(|row| row.try_get("game_id")).transpose()
+}
+
+/// Compute again the deals of the announces of a catalogue entry from its references
+async fn update_linked_deals(
+    db_client: &impl GenericClient,
+    game_id: i32,
+    references: &HashMap<String, Reference>,
+) -> Result<(), Error> {
+    let announces = db_client
+        .query(
+            "SELECT oa_id, oa_name, oa_price FROM okkazeo_announce WHERE oa_game_id = $1",
+            &[&game_id],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "okkazeo_announce"])
+        .inc();
+    if announces.is_empty() {
+        return Ok(());
+    }
+    let ids = announces
+        .iter()
+        .map(|row| row.try_get("oa_id"))
+        .collect::<Result<Vec<i32>, Error>>()?;
+    let shipping = db_client
+        .query(
+            "SELECT ship_oa_id, ship_shipper, ship_price FROM shipping WHERE ship_oa_id = ANY($1)",
+            &[&ids],
+        )
+        .await?;
+    DB_IO.with_label_values(&["select", "shipping"]).inc();
+
+    for row in announces {
+        let id: i32 = row.try_get("oa_id")?;
+        let mut game = Game {
+            okkazeo_announce: OkkazeoAnnounce {
+                id: id as u32,
+                name: row.try_get("oa_name")?,
+                price: row.try_get("oa_price")?,
+                ..Default::default()
+            },
+            references: references.clone(),
+            ..Default::default()
+        };
+        for ship in shipping.iter() {
+            if ship.try_get::<&str, i32>("ship_oa_id")? == id {
+                game.okkazeo_announce
+                    .shipping
+                    .insert(ship.try_get("ship_shipper")?, ship.try_get("ship_price")?);
+            }
+        }
+        game.get_deal_advantage();
+        update_deal_table(db_client, id, &game.deal).await?;
+    }
+    Ok(())
+}
+
+/// Announces to look up again for the game of `barcode` or `key`: one of
+/// each catalogue entry matching them, refreshing the entry refreshes all its
+/// announces, and the announces of this barcode without entry
+pub async fn select_announces_to_reenrich_from_db(
+    db_client: &Client,
+    barcode: Option<Gtin>,
+    key: Option<&str>,
+) -> Result<Vec<i32>, Error> {
+    let res = db_client
+        .query(
+            "SELECT DISTINCT ON (COALESCE(oa.oa_game_id, -oa.oa_id)) oa.oa_id
+            FROM okkazeo_announce oa
+            LEFT JOIN game g ON g.game_id = oa.oa_game_id
+            WHERE g.game_barcode = $1 OR g.game_key = $2
+                OR (oa.oa_game_id IS NULL AND oa.oa_barcode = $1)
+            ORDER BY COALESCE(oa.oa_game_id, -oa.oa_id), oa.oa_id",
+            &[&gtin_to_db(barcode), &key],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "okkazeo_announce"])
+        .inc();
+    res.iter().map(|row| row.try_get("oa_id")).collect()
+}
+
+fn match_override_from_row(row: &Row) -> Result<MatchOverride, Error> {
+    Ok(MatchOverride {
+        id: row.try_get("mo_id")?,
+        key: row.try_get("mo_key")?,
+        barcode: gtin_from_row(row, "mo_barcode")?,
+        reseller: row.try_get("mo_reseller")?,
+        url: row.try_get("mo_url")?,
+    })
+}
+
+/// Overrides of a game by its barcode or its catalogue key
+pub async fn select_match_overrides_from_db(
+    db_client: &Client,
+    barcode: Option<Gtin>,
+    key: &str,
+) -> Result<Vec<MatchOverride>, Error> {
+    let res = db_client
+        .query(
+            "SELECT * FROM match_override WHERE mo_barcode = $1 OR mo_key = $2",
+            &[&gtin_to_db(barcode), &key],
+        )
+        .await?;
+    DB_IO.with_label_values(&["select", "match_override"]).inc();
+    res.iter().map(match_override_from_row).collect()
+}
+
+pub async fn select_all_match_overrides_from_db(
+    db_client: &Client,
+) -> Result<Vec<MatchOverride>, Error> {
+    let res = db_client
+        .query("SELECT * FROM match_override ORDER BY mo_id", &[])
+        .await?;
+    DB_IO.with_label_values(&["select", "match_override"]).inc();
+    res.iter().map(match_override_from_row).collect()
+}
+
+/// Insert the override, replacing the one of the same reseller for the same game.
+/// Returns its id.
+pub async fn insert_match_override_into_db(
+    db_client: &Client,
+    match_override: &MatchOverride,
+) -> Result<i32, Error> {
+    let barcode = gtin_to_db(match_override.barcode);
+    db_client
+        .execute(
+            "DELETE FROM match_override WHERE mo_reseller = $1 AND mo_key IS NOT DISTINCT FROM $2 AND mo_barcode IS NOT DISTINCT FROM $3",
+            &[&match_override.reseller, &match_override.key, &barcode],
+        )
+        .await?;
+    DB_IO.with_label_values(&["delete", "match_override"]).inc();
+
+    let row = db_client
+        .query_one(
+            "INSERT INTO match_override (mo_key, mo_barcode, mo_reseller, mo_url, mo_created) VALUES ($1, $2, $3, $4, $5) RETURNING mo_id",
+            &[
+                &match_override.key,
+                &barcode,
+                &match_override.reseller,
+                &match_override.url,
+                &chrono::Utc::now(),
+            ],
+        )
+        .await?;
+    DB_IO.with_label_values(&["insert", "match_override"]).inc();
+    row.try_get("mo_id")
+}
+
+/// Returns the deleted override, None if there was none with this id
+pub async fn delete_match_override_from_db(
+    db_client: &Client,
+    id: i32,
+) -> Result<Option<MatchOverride>, Error> {
+    let res = db_client
+        .query(
+            "DELETE FROM match_override WHERE mo_id = $1 RETURNING *",
+            &[&id],
+        )
+        .await?;
+    DB_IO.with_label_values(&["delete", "match_override"]).inc();
+    res.first().map(match_override_from_row).transpose()
+}
+
+/// Queue the lookup of the announces of a game for the backend
+pub async fn insert_reenrich_request_into_db(
+    db_client: &Client,
+    key: Option<&str>,
+    barcode: Option<Gtin>,
+) -> Result<(), Error> {
+    db_client
+        .execute(
+            "INSERT INTO reenrich_request (rr_key, rr_barcode, rr_created) VALUES ($1, $2, $3)",
+            &[&key, &gtin_to_db(barcode), &chrono::Utc::now()],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["insert", "reenrich_request"])
+        .inc();
+    Ok(())
+}
+
+/// Queued requests, oldest first
+pub async fn select_reenrich_requests_from_db(
+    db_client: &Client,
+) -> Result<Vec<ReenrichRequest>, Error> {
+    let res = db_client
+        .query("SELECT * FROM reenrich_request ORDER BY rr_id", &[])
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "reenrich_request"])
+        .inc();
+    res.iter()
+        .map(|row| {
+            Ok(ReenrichRequest {
+                id: row.try_get("rr_id")?,
+                key: row.try_get("rr_key")?,
+                barcode: gtin_from_row(row, "rr_barcode")?,
+            })
+        })
+        .collect()
+}
+
+/// Remove the processed request and the identical ones queued before it
+pub async fn delete_reenrich_request_from_db(
+    db_client: &Client,
+    request: &ReenrichRequest,
+) -> Result<u64, Error> {
+    let deleted = db_client
+        .execute(
+            "DELETE FROM reenrich_request WHERE rr_id <= $1 AND rr_key IS NOT DISTINCT FROM $2 AND rr_barcode IS NOT DISTINCT FROM $3",
+            &[&request.id, &request.key, &gtin_to_db(request.barcode)],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["delete", "reenrich_request"])
+        .inc();
+    Ok(deleted)
+}
+
+/// Learned aliases of the title with key `key`
+pub async fn select_aliases_from_db(db_client: &Client, key: &str) -> Result<Vec<String>, Error> {
+    let res = db_client
+        .query(
+            "SELECT alias_name FROM title_alias WHERE alias_key = $1 AND NOT alias_rejected ORDER BY alias_id",
+            &[&key],
+        )
+        .await?;
+    DB_IO.with_label_values(&["select", "title_alias"]).inc();
+    res.iter().map(|row| row.try_get("alias_name")).collect()
+}
+
+fn title_alias_from_row(row: &Row) -> Result<TitleAlias, Error> {
+    Ok(TitleAlias {
+        id: row.try_get("alias_id")?,
+        key: row.try_get("alias_key")?,
+        name: row.try_get("alias_name")?,
+        source: row.try_get("alias_source")?,
+        created: row.try_get("alias_created")?,
+        rejected: row.try_get("alias_rejected")?,
+    })
+}
+
+pub async fn select_all_aliases_from_db(db_client: &Client) -> Result<Vec<TitleAlias>, Error> {
+    let res = db_client
+        .query("SELECT * FROM title_alias ORDER BY alias_id", &[])
+        .await?;
+    DB_IO.with_label_values(&["select", "title_alias"]).inc();
+    res.iter().map(title_alias_from_row).collect()
+}
+
+/// Reject the alias `alias` of the title with key `key`, the row is kept so
+/// that the alias is not learned again. Returns the rejected alias, None if
+/// there was none.
+pub async fn reject_alias_in_db(
+    db_client: &Client,
+    key: &str,
+    alias: &str,
+) -> Result<Option<TitleAlias>, Error> {
+    let res = db_client
+        .query(
+            "UPDATE title_alias SET alias_rejected = true WHERE alias_key = $1 AND alias_name = $2 RETURNING *",
+            &[&key, &alias],
+        )
+        .await?;
+    DB_IO.with_label_values(&["update", "title_alias"]).inc();
+    res.first().map(title_alias_from_row).transpose()
+}
+
+pub async fn select_alias_from_db(
+    db_client: &Client,
+    id: i32,
+) -> Result<Option<TitleAlias>, Error> {
+    let res = db_client
+        .query("SELECT * FROM title_alias WHERE alias_id = $1", &[&id])
+        .await?;
+    DB_IO.with_label_values(&["select", "title_alias"]).inc();
+    res.first().map(title_alias_from_row).transpose()
+}
+
+/// `source` is the reseller whose barcode lookup taught the alias
+pub async fn insert_alias_into_db(
+    db_client: &Client,
+    key: &str,
+    alias: &str,
+    source: &str,
+) -> Result<(), Error> {
+    db_client
+        .execute(
+            "INSERT INTO title_alias (alias_key, alias_name, alias_source, alias_created) VALUES ($1, $2, $3, $4) ON CONFLICT DO NOTHING",
+            &[&key, &alias, &source, &chrono::Utc::now()],
+        )
+        .await?;
+    DB_IO.with_label_values(&["insert", "title_alias"]).inc();
+    Ok(())
+}
+
+/// Bgg description stored in a catalogue entry row
+fn bgg_infos_from_row(row: &Row) -> Result<Option<BggInfos>, Error> {
+    let id = match row.try_get::<&str, Option<i32>>("game_bgg_id")? {
+        Some(id) => id as u32,
+        None => return Ok(None),
+    };
+    let as_u32 = |column: &str| -> Result<Option<u32>, Error> {
+        Ok(row.try_get::<&str, Option<i32>>(column)?.map(|v| v as u32))
+    };
+    Ok(Some(BggInfos {
+        id,
+        year: row.try_get("game_year")?,
+        min_players: as_u32("game_min_players")?,
+        max_players: as_u32("game_max_players")?,
+        playing_time: as_u32("game_playing_time")?,
+        min_age: as_u32("game_min_age")?,
+        weight: row.try_get("game_weight")?,
+        categories: row
+            .try_get::<&str, Option<Vec<String>>>("game_categories")?
+            .unwrap_or_default(),
+        mechanics: row
+            .try_get::<&str, Option<Vec<String>>>("game_mechanics")?
+            .unwrap_or_default(),
+    }))
+}
+
+pub async fn select_bgg_infos_from_db(
+    db_client: &Client,
+    game_id: i32,
+) -> Result<Option<BggInfos>, Error> {
+    let res = db_client
+        .query("SELECT * FROM game WHERE game_id = $1", &[&game_id])
+        .await?;
+    DB_IO.with_label_values(&["select", "game"]).inc();
+    match res.first() {
+        Some(row) => bgg_infos_from_row(row),
+        None => Ok(None),
+    }
+}
+
+/// Prices of an announce, with the reseller prices of the announce and of its catalogue game
+pub async fn select_announce_price_history_from_db(
+    db_client: &Client,
+    id: i32,
+    game_id: Option<i32>,
+) -> Result<PriceHistory, Error> {
+    let mut histories = select_announces_price_history_from_db(db_client, &[(id, game_id)]).await?;
+    Ok(histories.remove(&id).unwrap_or_default())
+}
+
+/// Prices of the announces given with their catalogue game, by announce id.
+/// One query for the announce prices and one for the reseller prices of them all.
+pub async fn select_announces_price_history_from_db(
+    db_client: &Client,
+    announces: &[(i32, Option<i32>)],
+) -> Result<HashMap<i32, PriceHistory>, Error> {
+    let ids: Vec<i32> = announces.iter().map(|(id, _)| *id).collect();
+    let game_ids: Vec<i32> = announces
+        .iter()
+        .filter_map(|(_, game_id)| *game_id)
+        .collect();
+    let mut histories: HashMap<i32, PriceHistory> = ids
+        .iter()
+        .map(|id| (*id, PriceHistory::default()))
+        .collect();
+
+    let res = db_client
+        .query(
+            "SELECT aph_oa_id, aph_price, aph_date FROM announce_price_history
+            WHERE aph_oa_id = ANY($1) ORDER BY aph_date, aph_id",
+            &[&ids],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "announce_price_history"])
+        .inc();
+    for row in res {
+        if let Some(history) = histories.get_mut(&row.try_get("aph_oa_id")?) {
+            history.push_announce_price(PricePoint {
+                price: row.try_get("aph_price")?,
+                promo_price: None,
+                date: row.try_get("aph_date")?,
+            });
+        }
+    }
+
+    let res = db_client
+        .query(
+            "SELECT rph_oa_id, rph_game_id, rph_reseller, rph_price, rph_promo_price, rph_date
+            FROM reference_price_history
+            WHERE rph_oa_id = ANY($1) OR rph_game_id = ANY($2) ORDER BY rph_date, rph_id",
+            &[&ids, &game_ids],
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "reference_price_history"])
+        .inc();
+    for row in res {
+        let oa_id: Option<i32> = row.try_get("rph_oa_id")?;
+        let game_id: Option<i32> = row.try_get("rph_game_id")?;
+        let reseller: &str = row.try_get("rph_reseller")?;
+        let point = PricePoint {
+            price: row.try_get("rph_price")?,
+            promo_price: row.try_get("rph_promo_price")?,
+            date: row.try_get("rph_date")?,
+        };
+        // a price of a catalogue game belongs to all its announces
+        for (id, announce_game_id) in announces {
+            if oa_id == Some(*id) || (game_id.is_some() && game_id == *announce_game_id) {
+                if let Some(history) = histories.get_mut(id) {
+                    history.push_reference_price(reseller, point.clone());
+                }
+            }
+        }
+    }
+    Ok(histories)
+}
+
+/// Reseller prices of a catalogue game
+pub async fn select_game_price_history_from_db(
+    db_client: &Client,
+    game_id: i32,
+) -> Result<PriceHistory, Error> {
+    let mut history = PriceHistory::default();
+    select_reference_prices(db_client, &mut history, "rph_game_id = $1", &[&game_id]).await?;
+    Ok(history)
+}
+
+async fn select_reference_prices(
+    db_client: &Client,
+    history: &mut PriceHistory,
+    filter: &str,
+    params: &[&(dyn ToSql + Sync)],
+) -> Result<(), Error> {
+    let res = db_client
+        .query(
+            &format!(
+                "SELECT rph_reseller, rph_price, rph_promo_price, rph_date FROM reference_price_history
+                WHERE {} ORDER BY rph_date, rph_id",
+                filter
+            ),
+            params,
+        )
+        .await?;
+    DB_IO
+        .with_label_values(&["select", "reference_price_history"])
+        .inc();
+    for row in res {
+        history.push_reference_price(
+            row.try_get("rph_reseller")?,
+            PricePoint {
+                price: row.try_get("rph_price")?,
+                promo_price: row.try_get("rph_promo_price")?,
+                date: row.try_get("rph_date")?,
+            },
+        );
+    }
+    Ok(())
+}
+
+lazy_static! {
+    static ref DB_TRANSACTION: IntCounterVec = register_int_counter_vec!(
+        "db_transaction",
+        "Number of multi-table writes committed, rolled back or failing to commit",
+        &["transaction", "result"]
+    )
+    .unwrap();
+    static ref DB_IO: IntCounterVec = register_int_counter_vec!(
+        "db_io",
+        "Number of select/delete/insert on the db",
+        &["operation", "table"]
+    )
+    .unwrap();
+    static ref DB_TIMEOUTS: IntCounterVec = register_int_counter_vec!(
+        "db_timeouts",
+        "Number of waits for a pool connection and of queries running out of time",
+        &["timeout"]
+    )
+    .unwrap();
+    static ref DB_POOL_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "db_pool_connections",
+        "Number of connections of the pool in use or idle",
+        &["state"]
+    )
+    .unwrap();
+    static ref DB_POOL_WAITERS: IntGauge = register_int_gauge!(
+        "db_pool_waiters",
+        "Number of tasks waiting for a connection of the pool"
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::{
+        insert_alias_into_db, insert_announce_into_db, insert_reenrich_request_into_db,
+        select_aliases_from_db, select_all_aliases_from_db, select_announce_price_history_from_db,
+        select_announces_to_reenrich_from_db, select_game_with_id_from_db, select_games_from_db,
+        select_reenrich_requests_from_db, sql_name_filter, sync_okkazeo_rows_into_db,
+        update_sellers_nb_announces_from_db, upsert_catalogue_entry_into_db, SQL_NAME_RELEVANCE,
+    };
+    use crate::alias::{alias_key, reject_alias};
+    use crate::catalogue::{process_reenrich_requests, CatalogueEntry};
+    use crate::frontlib::{server::State, Filters, Pagination, Sort};
+    use crate::game::{
+        BggInfos, Game, OkkazeoAnnounce, PricePoint, Reference, Review, Reviewer, Seller,
+    };
+    use crate::gtin::Gtin;
+    use crate::migration::migrate;
+    use crate::website::okkazeo::Row as OkkazeoRow;
+    use tokio_postgres::{Client, Config, NoTls};
+
+    async fn connect(config: &Config, dbname: &str) -> Client {
+        let (client, connection) = config.clone().dbname(dbname).connect(NoTls).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    /// Migrated database dropped by the caller with the returned admin
+    /// client, None when DB_URL is not defined
+    async fn scratch_database(name: &str) -> Option<(Client, Client, String)> {
+        let config: Config = std::env::var("DB_URL").ok()?.parse().unwrap();
+        let admin = connect(&config, config.get_dbname().unwrap_or("postgres")).await;
+        let dbname = format!("{}_{}", name, std::process::id());
+        admin
+            .batch_execute(&format!("CREATE DATABASE {}", dbname))
+            .await
+            .unwrap();
+        let mut client = connect(&config, &dbname).await;
+        migrate(&mut client).await.unwrap();
+        Some((admin, client, dbname))
+    }
+
+    async fn drop_database(admin: Client, client: Client, dbname: String) {
+        drop(client);
+        admin
+            .batch_execute(&format!("DROP DATABASE {} WITH (FORCE)", dbname))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn test_sql_name_filter() {
+        assert_eq!(sql_name_filter(true), "oa.oa_name = $1");
+        let filter = sql_name_filter(false);
+        // substring, typo and stem matches, all without accents
+        assert!(filter.contains("unaccent(oa.oa_name) ilike unaccent('%' || $1::text || '%')"));
+        assert!(filter.contains("f_unaccent($1) <% f_unaccent(oa.oa_name)"));
+        assert!(filter.contains("plainto_tsquery('french', f_unaccent($1))"));
+        // the searched name is the only parameter, given once
+        for sql in [filter, SQL_NAME_RELEVANCE] {
+            assert!(!sql.contains("$2"), "{}", sql);
+            assert!(sql.contains("$1"), "{}", sql);
+        }
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_sql_name_filter_matches() {
+        let Some((admin, client, dbname)) = scratch_database("name_filter").await else {
+            return;
+        };
+        client
+            .batch_execute(
+                "INSERT INTO okkazeo_announce (oa_id, oa_last_modification_date, oa_name, oa_image, oa_price, oa_url)
+                    VALUES (1, '2024-01-04 10:00:00+00', 'Les Aventuriers du Rail', '', 10, ''),
+                           (2, '2024-01-03 10:00:00+00', 'Château Combo', '', 10, ''),
+                           (3, '2024-01-02 10:00:00+00', 'Azul', '', 10, ''),
+                           (4, '2024-01-01 10:00:00+00', 'Catan', '', 10, '');",
+            )
+            .await
+            .unwrap();
+        let names = |exact_match: bool, name: &'static str| {
+            let client = &client;
+            async move {
+                client
+                    .query(
+                        &format!(
+                            "SELECT oa.oa_name FROM okkazeo_announce oa WHERE {} ORDER BY {}",
+                            sql_name_filter(exact_match),
+                            SQL_NAME_RELEVANCE
+                        ),
+                        &[&name],
+                    )
+                    .await
+                    .unwrap()
+                    .iter()
+                    .map(|row| row.get::<_, String>(0))
+                    .collect::<Vec<_>>()
+            }
+        };
+
+        // accents are ignored on both sides
+        assert_eq!(names(false, "chateau").await, vec!["Château Combo"]);
+        assert_eq!(names(false, "AZÛL").await, vec!["Azul"]);
+        // words apart in the title, and plural of a word of the title
+        assert_eq!(
+            names(false, "aventurier rail").await,
+            vec!["Les Aventuriers du Rail"]
+        );
+        // a typo
+        assert_eq!(names(false, "azull").await, vec!["Azul"]);
+        // nothing searched keeps every announce, the latest first
+        assert_eq!(
+            names(false, "").await,
+            vec!["Les Aventuriers du Rail", "Château Combo", "Azul", "Catan"]
+        );
+        assert_eq!(names(true, "Azul").await, vec!["Azul"]);
+        assert!(names(true, "azul").await.is_empty());
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    fn okkazeo_row(id: u32, vendor: &str) -> OkkazeoRow {
+        OkkazeoRow {
+            id,
+            url_announce: format!("https://www.okkazeo.com/{}", id),
+            url_image: String::new(),
+            name: format!("game {}", id),
+            ean: None,
+            kind: "Jeu".to_string(),
+            date: "2024-01-01 10:00:00".to_string(),
+            prix_annonce: 10.0,
+            prix_min: 20.0,
+            vendor: vendor.to_string(),
+            pro: false,
+            url_vendor: String::new(),
+            zipcode: None,
+            city: String::new(),
+            rmp: false,
+            colissimo: None,
+            mondial_relay: None,
+            relais_colis: None,
+            shop2shop: None,
+        }
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_sellers_nb_announces_after_sync() {
+        let Some((admin, mut client, dbname)) = scratch_database("sellers_sync").await else {
+            return;
+        };
+        client
+            .batch_execute(
+                "INSERT INTO seller (seller_name, seller_nb_announces) VALUES ('alice', 2), ('bob', 1);
+                INSERT INTO okkazeo_announce (oa_id, oa_last_modification_date, oa_name, oa_image, oa_price, oa_url, oa_seller)
+                    VALUES (1, '2024-01-01 10:00:00+00', 'game 1', '', 10, '', 'alice'),
+                           (2, '2024-01-01 10:00:00+00', 'game 2', '', 10, '', 'alice'),
+                           (3, '2024-01-01 10:00:00+00', 'game 3', '', 10, '', 'bob');",
+            )
+            .await
+            .unwrap();
+
+        // bob left okkazeo and alice sold one of her games
+        let sync = sync_okkazeo_rows_into_db(&mut client, &[okkazeo_row(1, "alice")])
+            .await
+            .unwrap();
+        assert_eq!(sync.deleted, 2);
+        assert_eq!(update_sellers_nb_announces_from_db(&client).await, 2);
+
+        let counts: Vec<(String, i32)> = client
+            .query(
+                "SELECT seller_name, seller_nb_announces FROM seller ORDER BY seller_name",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![("alice".to_string(), 1), ("bob".to_string(), 0)]
+        );
+        // nothing changed since
+        assert_eq!(update_sellers_nb_announces_from_db(&client).await, 0);
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    fn announce(id: u32, name: &str) -> Game {
+        Game {
+            okkazeo_announce: OkkazeoAnnounce {
+                id,
+                name: name.to_string(),
+                extension: "Jeu".to_string(),
+                price: 10.0,
+                seller: Seller {
+                    name: "alice".to_string(),
+                    ..Default::default()
+                },
+                city: Some("Lyon (69000)".to_string()),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_catalogue_game_references() {
+        let Some((admin, mut client, dbname)) = scratch_database("game_references").await else {
+            return;
+        };
+        let entry = CatalogueEntry {
+            title: "Azul".to_string(),
+            key: "azul".to_string(),
+            references: HashMap::from([(
+                "philibert".to_string(),
+                Reference {
+                    name: "philibert".to_string(),
+                    price: 30.0,
+                    ..Default::default()
+                },
+            )]),
+            review: Review {
+                reviews: HashMap::from([(
+                    "bgg".to_string(),
+                    Reviewer {
+                        name: "bgg".to_string(),
+                        note: 8.0,
+                        number: 1000,
+                        ..Default::default()
+                    },
+                )]),
+                ..Default::default()
+            },
+            updated: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let game_id = upsert_catalogue_entry_into_db(&mut client, &entry)
+            .await
+            .unwrap();
+
+        let mut linked = announce(1, "Azul");
+        linked.catalogue_id = Some(game_id);
+        linked.references = entry.references.clone();
+        linked.review = entry.review.clone();
+        insert_announce_into_db(&mut client, &linked).await.unwrap();
+        let mut unlinked = announce(2, "Splendor");
+        unlinked.references = entry.references.clone();
+        insert_announce_into_db(&mut client, &unlinked)
+            .await
+            .unwrap();
+
+        // only the announce without catalogue game has its own copies
+        let copies: Vec<i32> = client
+            .query(
+                "SELECT ref_oa_id FROM reference WHERE ref_oa_id IS NOT NULL",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| row.get(0))
+            .collect();
+        assert_eq!(copies, vec![2]);
+
+        let game = select_game_with_id_from_db(&client, 1).await.unwrap();
+        assert_eq!(game.catalogue_id, Some(game_id));
+        assert!(game.references.contains_key("philibert"));
+        assert!(game.review.reviews.contains_key("bgg"));
+
+        // the note filter reads the reviews of the catalogue game
+        let state = State {
+            pagination: Pagination::default(),
+            filters: Filters {
+                note: Some(5.0),
+                ..Default::default()
+            },
+            sort: Sort::default(),
+        };
+        let games = select_games_from_db(&client, &state).await.unwrap();
+        let ids: Vec<u32> = games.games.iter().map(|g| g.okkazeo_announce.id).collect();
+        assert_eq!(ids, vec![1]);
+        assert!(games.games[0].references.contains_key("philibert"));
+
+        // one announce of the entry is enough to refresh it
+        let mut other = announce(3, "Azul");
+        other.catalogue_id = Some(game_id);
+        insert_announce_into_db(&mut client, &other).await.unwrap();
+        assert_eq!(
+            select_announces_to_reenrich_from_db(&client, None, Some("azul"))
+                .await
+                .unwrap(),
+            vec![1]
+        );
+        assert!(
+            select_announces_to_reenrich_from_db(&client, None, Some("splendor"))
+                .await
+                .unwrap()
+                .is_empty()
+        );
+        // an announce without entry is found by its barcode
+        let barcode = Gtin::try_from(4006381333931).ok();
+        let mut with_barcode = announce(4, "Splendor");
+        with_barcode.okkazeo_announce.barcode = barcode;
+        insert_announce_into_db(&mut client, &with_barcode)
+            .await
+            .unwrap();
+        let mut ids = select_announces_to_reenrich_from_db(&client, barcode, Some("azul"))
+            .await
+            .unwrap();
+        ids.sort();
+        assert_eq!(ids, vec![1, 4]);
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_catalogue_refresh_updates_deals() {
+        let Some((admin, mut client, dbname)) = scratch_database("catalogue_refresh").await else {
+            return;
+        };
+        let entry = |key: &str, price: f32| CatalogueEntry {
+            title: "Azul".to_string(),
+            key: key.to_string(),
+            bgg: Some(BggInfos {
+                id: 230802,
+                ..Default::default()
+            }),
+            references: HashMap::from([(
+                "philibert".to_string(),
+                Reference {
+                    name: "philibert".to_string(),
+                    price,
+                    available: Some(true),
+                    ..Default::default()
+                },
+            )]),
+            updated: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let game_id = upsert_catalogue_entry_into_db(&mut client, &entry("azul", 20.0))
+            .await
+            .unwrap();
+        let mut linked = announce(1, "Azul");
+        linked.catalogue_id = Some(game_id);
+        linked.references = entry("azul", 20.0).references;
+        linked.get_deal_advantage();
+        insert_announce_into_db(&mut client, &linked).await.unwrap();
+        assert_eq!(linked.deal.deal_percentage, -50);
+
+        // another title of the same bgg game refreshes the entry in place
+        let refreshed = upsert_catalogue_entry_into_db(&mut client, &entry("azul vf", 12.5))
+            .await
+            .unwrap();
+        assert_eq!(refreshed, game_id);
+        let game = select_game_with_id_from_db(&client, 1).await.unwrap();
+        assert_eq!(game.references["philibert"].price, 12.5);
+        assert_eq!(game.deal.deal_percentage, -20);
+        assert_eq!(game.deal.deal_price, -3);
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_listing_price_history() {
+        let Some((admin, mut client, dbname)) = scratch_database("price_history").await else {
+            return;
+        };
+        let reference = |price: f32| {
+            HashMap::from([(
+                "philibert".to_string(),
+                Reference {
+                    name: "philibert".to_string(),
+                    price,
+                    ..Default::default()
+                },
+            )])
+        };
+        let entry = CatalogueEntry {
+            title: "Azul".to_string(),
+            key: "azul".to_string(),
+            references: reference(30.0),
+            updated: chrono::Utc::now(),
+            ..Default::default()
+        };
+        let game_id = upsert_catalogue_entry_into_db(&mut client, &entry)
+            .await
+            .unwrap();
+        let mut linked = announce(1, "Azul");
+        linked.catalogue_id = Some(game_id);
+        insert_announce_into_db(&mut client, &linked).await.unwrap();
+        let mut unlinked = announce(2, "Splendor");
+        unlinked.references = reference(25.0);
+        insert_announce_into_db(&mut client, &unlinked)
+            .await
+            .unwrap();
+        client
+            .batch_execute(
+                "INSERT INTO announce_price_history (aph_oa_id, aph_price) VALUES (1, 8)",
+            )
+            .await
+            .unwrap();
+
+        let state = State {
+            pagination: Pagination::default(),
+            filters: Filters::default(),
+            sort: Sort::default(),
+        };
+        let games = select_games_from_db(&client, &state).await.unwrap();
+        assert_eq!(games.games.len(), 2);
+        for game in &games.games {
+            let id = game.okkazeo_announce.id as i32;
+            let single = select_announce_price_history_from_db(&client, id, game.catalogue_id)
+                .await
+                .unwrap();
+            let prices = |points: &[PricePoint]| points.iter().map(|p| p.price).collect::<Vec<_>>();
+            let (announce_prices, reseller_prices) = match id {
+                1 => (vec![10.0, 8.0], vec![30.0]),
+                _ => (vec![10.0], vec![25.0]),
+            };
+            for history in [&game.price_history, &single] {
+                assert_eq!(prices(&history.announce), announce_prices, "{}", id);
+                assert_eq!(
+                    prices(&history.references["philibert"]),
+                    reseller_prices,
+                    "{}",
+                    id
+                );
+            }
+        }
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_reject_alias() {
+        let Some((admin, client, dbname)) = scratch_database("reject_alias").await else {
+            return;
+        };
+        let learn = || async {
+            for (key, alias) in [
+                (alias_key("Les Colons de Catane"), "Catan"),
+                (alias_key("Catan"), "Les Colons de Catane"),
+            ] {
+                insert_alias_into_db(&client, &key, alias, "philibert")
+                    .await
+                    .unwrap();
+            }
+        };
+        learn().await;
+        let aliases = select_all_aliases_from_db(&client).await.unwrap();
+        assert_eq!(aliases.len(), 2);
+
+        let rejected = reject_alias(&client, aliases[0].id).await.unwrap().unwrap();
+        assert!(rejected.rejected);
+        assert!(reject_alias(&client, -1).await.unwrap().is_none());
+        // both ways, and the alias is not learned again
+        learn().await;
+        for title in ["Les Colons de Catane", "Catan"] {
+            let aliases = select_aliases_from_db(&client, &alias_key(title))
+                .await
+                .unwrap();
+            assert!(aliases.is_empty(), "{:?}", aliases);
+        }
+        let aliases = select_all_aliases_from_db(&client).await.unwrap();
+        assert!(aliases.iter().all(|alias| alias.rejected));
+
+        drop_database(admin, client, dbname).await;
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_reenrich_requests() {
+        let Some((admin, mut client, dbname)) = scratch_database("reenrich_requests").await else {
+            return;
+        };
+        let barcode = Gtin::new(4006381333931);
+        for (key, barcode) in [(Some("azul"), None), (None, barcode), (Some("azul"), None)] {
+            insert_reenrich_request_into_db(&client, key, barcode)
+                .await
+                .unwrap();
+        }
+        let requests = select_reenrich_requests_from_db(&client).await.unwrap();
+        assert_eq!(requests.len(), 3);
+        assert_eq!(requests[1].barcode, barcode);
+
+        // no announce to look up, the requests are consumed all the same
+        process_reenrich_requests(&mut client).await;
+        assert!(select_reenrich_requests_from_db(&client)
+            .await
+            .unwrap()
+            .is_empty());
+
+        drop_database(admin, client, dbname).await;
+    }
 }
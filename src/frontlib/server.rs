@@ -1,18 +1,30 @@
-use axum::extract::Query;
+use axum::extract::{Path, Query};
+use axum::http::{header, HeaderMap, StatusCode};
 use axum::response::Html;
-use axum::Extension;
-use axum::{extract::Form, routing::get, Router};
+use axum::{
+    extract::Form,
+    routing::{delete, get},
+    Router,
+};
+use axum::{Extension, Json};
+use deadpool_postgres::{Object, Pool};
 use prometheus::{register_int_counter_vec, IntCounter, IntCounterVec};
-use serde::Serialize;
-use std::sync::Arc;
+use serde::{Deserialize, Serialize};
 use tera::{Context, Tera};
-use tokio_postgres::Client;
 use tower_http::services::ServeDir;
 
 use lazy_static::lazy_static;
 use prometheus::register_int_counter;
 
-use crate::db::{connect_db, select_count_filtered_games_from_db, select_games_from_db};
+use crate::alias::{reject_alias, TitleAlias};
+use crate::catalogue::{catalogue_key, MatchOverride};
+use crate::db::{
+    delete_match_override_from_db, get_db_client, insert_match_override_into_db,
+    insert_reenrich_request_into_db, observe_db_error, select_all_aliases_from_db,
+    select_all_match_overrides_from_db, select_count_filtered_games_from_db, select_games_from_db,
+};
+use crate::game::GameKind;
+use crate::gtin::Gtin;
 
 use super::{Filters, FiltersForm, Pagination, Sort};
 
@@ -43,45 +55,45 @@ pub fn format_url_params(state: &State) -> String {
             .name
             .as_ref()
             .map_or(String::new(), |name| format!("&name={}", name)),
-        if state.filters.vendor.is_some() {
-            format!("&vendor={}", state.filters.vendor.as_ref().unwrap())
-        } else {
-            String::new()
-        },
-        if state.filters.pro.is_some() {
-            format!("&pro={}", state.filters.pro.as_ref().unwrap())
-        } else {
-            String::new()
-        },
-        if state.filters.exact_match.is_some() {
-            format!("&exact_match={}", state.filters.exact_match.as_ref().unwrap())
-        } else {
-            String::new()
-        },
+        state
+            .filters
+            .vendor
+            .as_ref()
+            .map_or(String::new(), |vendor| format!("&vendor={}", vendor)),
+        state
+            .filters
+            .pro
+            .as_ref()
+            .map_or(String::new(), |pro| format!("&pro={}", pro)),
+        state
+            .filters
+            .exact_match
+            .as_ref()
+            .map_or(String::new(), |exact_match| format!("&exact_match={}", exact_match)),
         state.filters.type_ext,
         state.filters.type_game_ext,
         state.filters.type_game,
         state.filters.type_misc,
-        if state.filters.delivery.is_some() {
-            format!("&delivery={}", state.filters.delivery.as_ref().unwrap())
-        } else {
-            String::new()
-        },
-        if state.filters.note.is_some() {
-            format!("&note={}", state.filters.note.as_ref().unwrap())
-        } else {
-            String::new()
-        },
-        if state.filters.max_price.is_some() {
-            format!("&max_price={}", state.filters.max_price.as_ref().unwrap())
-        } else {
-            String::new()
-        },
-        if state.filters.min_price.is_some() {
-            format!("&min_price={}", state.filters.min_price.as_ref().unwrap())
-        } else {
-            String::new()
-        },
+        state
+            .filters
+            .delivery
+            .as_ref()
+            .map_or(String::new(), |delivery| format!("&delivery={}", delivery)),
+        state
+            .filters
+            .note
+            .as_ref()
+            .map_or(String::new(), |note| format!("&note={}", note)),
+        state
+            .filters
+            .max_price
+            .as_ref()
+            .map_or(String::new(), |max_price| format!("&max_price={}", max_price)),
+        state
+            .filters
+            .min_price
+            .as_ref()
+            .map_or(String::new(), |min_price| format!("&min_price={}", min_price)),
         state.sort.sort,
     )
 }
@@ -90,10 +102,11 @@ pub async fn root(
     pagination: Option<Query<Pagination>>,
     sort: Option<Query<Sort>>,
     filters: Option<Query<Filters>>,
-    Extension(db_client): Extension<Arc<Client>>,
+    Extension(pool): Extension<Pool>,
     filters_form: Form<FiltersForm>,
-) -> Html<String> {
+) -> Result<Html<String>, StatusCode> {
     AXUM_ROOT_GET.inc();
+    let db_client = pooled_client(&pool).await?;
     let mut pagination_param = pagination.unwrap_or_default().0;
     let mut filters_param = filters.clone().unwrap_or_default().0;
     let sort_param = sort.clone().unwrap_or_default().0;
@@ -106,24 +119,9 @@ pub async fn root(
     log::debug!("FILTER FORM : {:?}", &filters_form);
 
     if filters_form.0.city_form.is_some() {
-        let note = filters_form
-            .0
-            .note_form
-            .unwrap()
-            .parse::<f32>()
-            .map_or_else(|_| None, Some);
-        let max_price = filters_form
-            .0
-            .max_price_form
-            .unwrap()
-            .parse::<i32>()
-            .map_or_else(|_| None, Some);
-        let min_price = filters_form
-            .0
-            .min_price_form
-            .unwrap()
-            .parse::<i32>()
-            .map_or_else(|_| None, Some);
+        let note = filters_form.0.note_form.unwrap().parse::<f32>().ok();
+        let max_price = filters_form.0.max_price_form.unwrap().parse::<i32>().ok();
+        let min_price = filters_form.0.min_price_form.unwrap().parse::<i32>().ok();
         let pro: Option<bool> = if filters_form.0.pro_form == Some("on".to_string()) {
             Some(true)
         } else {
@@ -144,11 +142,7 @@ pub async fn root(
         } else {
             None
         };
-        let city = if filters_form.0.city_form.as_ref().unwrap().is_empty() {
-            None
-        } else {
-            filters_form.0.city_form
-        };
+        let city = filters_form.0.city_form.filter(|city| !city.is_empty());
         let date = if filters_form.0.date_form.as_ref().unwrap().is_empty() {
             None
         } else {
@@ -200,6 +194,7 @@ pub async fn root(
         match select_count_filtered_games_from_db(&db_client, filters_param.clone()).await {
             Ok(val) => val as usize,
             Err(e) => {
+                observe_db_error(&e);
                 DB_ERRORS.with_label_values(&[&e.to_string()]).inc();
                 log::error!("[SERVER] error getting count filtered games : {}", e);
                 0
@@ -222,9 +217,10 @@ pub async fn root(
     let part_games = match select_games_from_db(&db_client, &state).await {
         Ok(g) => g,
         Err(e) => {
+            observe_db_error(&e);
             DB_ERRORS.with_label_values(&[&e.to_string()]).inc();
             log::error!("[SERVER] error getting games from db : {}", e);
-            return Html(String::new());
+            return Ok(Html(String::new()));
         }
     };
 
@@ -238,7 +234,7 @@ pub async fn root(
         Ok(t) => t,
         Err(e) => {
             log::error!("error tera loading template : {}", e);
-            return Html(String::new());
+            return Ok(Html(String::new()));
         }
     };
     let mut ctx = Context::new();
@@ -256,10 +252,12 @@ pub async fn root(
     ctx.insert("url_param_sort_price", &format_url_params(&state_clone));
     state_clone.sort.sort = String::from("percent");
     ctx.insert("url_param_sort_percent", &format_url_params(&state_clone));
+    state_clone.sort.sort = String::from("relevance");
+    ctx.insert("url_param_sort_relevance", &format_url_params(&state_clone));
 
     ctx.insert("games", &part_games.games);
 
-    let total_pages = (total_items + state.pagination.per_page - 1) / state.pagination.per_page;
+    let total_pages = total_items.div_ceil(state.pagination.per_page);
     ctx.insert("total_pages", &total_pages);
 
     // this is dumb but there is no way in tera to do an iteration
@@ -271,24 +269,258 @@ pub async fn root(
     ctx.insert("pages_vec", &pages_vec);
 
     match tera.render("frontpage.tera", &ctx) {
-        Ok(r) => Html(r),
+        Ok(r) => Ok(Html(r)),
         Err(e) => {
             log::error!("error tera rendering : {}", e);
-            Html(String::new())
+            Ok(Html(String::new()))
+        }
+    }
+}
+
+/// Token expected in the `Authorization: Bearer` header of the admin routes
+#[derive(Clone)]
+pub struct AdminToken(pub String);
+
+/// Override posted to the admin route, the game is given by its title and okkazeo type or by its EAN
+#[derive(Debug, Deserialize)]
+pub struct OverrideForm {
+    pub title: Option<String>,
+    pub kind: Option<String>,
+    /// EAN as printed on the box
+    pub barcode: Option<String>,
+    pub reseller: String,
+    /// None when the reseller does not sell the game
+    pub url: Option<String>,
+}
+
+/// Connection of the pool for a request, counted as a db error when none is available in time
+async fn pooled_client(pool: &Pool) -> Result<Object, StatusCode> {
+    get_db_client(pool).await.map_err(|_| {
+        DB_ERRORS.with_label_values(&["pool"]).inc();
+        StatusCode::SERVICE_UNAVAILABLE
+    })
+}
+
+fn is_admin(headers: &HeaderMap, token: &AdminToken) -> bool {
+    let bearer = headers
+        .get(header::AUTHORIZATION)
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .unwrap_or_default();
+    let (a, b) = (bearer.as_bytes(), token.0.as_bytes());
+    // compare all the bytes so the time taken does not tell how much of the token is right
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+pub async fn list_overrides(
+    headers: HeaderMap,
+    Extension(token): Extension<AdminToken>,
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<Vec<MatchOverride>>, StatusCode> {
+    if !is_admin(&headers, &token) {
+        AXUM_ADMIN.with_label_values(&["unauthorized"]).inc();
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    AXUM_ADMIN.with_label_values(&["list"]).inc();
+    let db_client = pooled_client(&pool).await?;
+    select_all_match_overrides_from_db(&db_client)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            observe_db_error(&e);
+            log::error!("error db, cannot select match overrides : {}", e);
+            DB_ERRORS
+                .with_label_values(&["select_match_overrides"])
+                .inc();
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Store the override and queue the lookup of the announces of its game for the backend
+pub async fn add_override(
+    headers: HeaderMap,
+    Extension(token): Extension<AdminToken>,
+    Extension(pool): Extension<Pool>,
+    Json(form): Json<OverrideForm>,
+) -> Result<(StatusCode, Json<i32>), StatusCode> {
+    if !is_admin(&headers, &token) {
+        AXUM_ADMIN.with_label_values(&["unauthorized"]).inc();
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    let key = form.title.as_deref().map(|title| {
+        catalogue_key(
+            title,
+            GameKind::from_okkazeo(form.kind.as_deref().unwrap_or_default()),
+        )
+    });
+    let barcode = match form.barcode.as_deref().map(str::parse::<Gtin>) {
+        Some(Ok(gtin)) => Some(gtin),
+        Some(Err(e)) => {
+            log::debug!("[SERVER] rejecting override barcode : {}", e);
+            return Err(StatusCode::BAD_REQUEST);
         }
+        None => None,
+    };
+    if key.is_none() && barcode.is_none() || form.reseller.is_empty() {
+        return Err(StatusCode::BAD_REQUEST);
     }
+
+    let match_override = MatchOverride {
+        id: 0,
+        key,
+        barcode,
+        reseller: form.reseller,
+        url: form.url.filter(|u| !u.is_empty()),
+    };
+    let db_client = pooled_client(&pool).await?;
+    let id = insert_match_override_into_db(&db_client, &match_override)
+        .await
+        .map_err(|e| {
+            observe_db_error(&e);
+            log::error!("error db, cannot insert match override : {}", e);
+            DB_ERRORS
+                .with_label_values(&["insert_match_override"])
+                .inc();
+            StatusCode::INTERNAL_SERVER_ERROR
+        })?;
+    AXUM_ADMIN.with_label_values(&["add"]).inc();
+    log::info!(
+        "[SERVER] added match override {} : {:?}",
+        id,
+        match_override
+    );
+
+    queue_reenrich(&db_client, &match_override).await;
+    Ok((StatusCode::ACCEPTED, Json(id)))
 }
 
-pub async fn run_server(bind_addr: String) {
-    let client = Arc::new(connect_db().await.unwrap());
-    log::info!("[SERVER] connected with DB");
+/// Ask the backend to look up again the announces of the game of
+/// `match_override`, the scraping is not done by the frontend
+async fn queue_reenrich(db_client: &Object, match_override: &MatchOverride) {
+    if let Err(e) = insert_reenrich_request_into_db(
+        db_client,
+        match_override.key.as_deref(),
+        match_override.barcode,
+    )
+    .await
+    {
+        observe_db_error(&e);
+        log::error!(
+            "error db, cannot queue the re-enrichment of {:?} : {}",
+            match_override,
+            e
+        );
+        DB_ERRORS.with_label_values(&["reenrich"]).inc();
+    }
+}
 
-    let app = Router::new()
-        .route("/", get(root).post(root))
+/// Remove the override and queue the lookup of the announces of its game for the backend
+pub async fn remove_override(
+    headers: HeaderMap,
+    Path(id): Path<i32>,
+    Extension(token): Extension<AdminToken>,
+    Extension(pool): Extension<Pool>,
+) -> StatusCode {
+    if !is_admin(&headers, &token) {
+        AXUM_ADMIN.with_label_values(&["unauthorized"]).inc();
+        return StatusCode::UNAUTHORIZED;
+    }
+    let db_client = match pooled_client(&pool).await {
+        Ok(client) => client,
+        Err(status) => return status,
+    };
+    match delete_match_override_from_db(&db_client, id).await {
+        Ok(Some(match_override)) => {
+            AXUM_ADMIN.with_label_values(&["remove"]).inc();
+            log::info!("[SERVER] removed match override {:?}", match_override);
+            queue_reenrich(&db_client, &match_override).await;
+            StatusCode::ACCEPTED
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            observe_db_error(&e);
+            log::error!("error db, cannot delete match override {} : {}", id, e);
+            DB_ERRORS
+                .with_label_values(&["delete_match_override"])
+                .inc();
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn list_aliases(
+    headers: HeaderMap,
+    Extension(token): Extension<AdminToken>,
+    Extension(pool): Extension<Pool>,
+) -> Result<Json<Vec<TitleAlias>>, StatusCode> {
+    if !is_admin(&headers, &token) {
+        AXUM_ADMIN.with_label_values(&["unauthorized"]).inc();
+        return Err(StatusCode::UNAUTHORIZED);
+    }
+    AXUM_ADMIN.with_label_values(&["list_aliases"]).inc();
+    let db_client = pooled_client(&pool).await?;
+    select_all_aliases_from_db(&db_client)
+        .await
+        .map(Json)
+        .map_err(|e| {
+            observe_db_error(&e);
+            log::error!("error db, cannot select aliases : {}", e);
+            DB_ERRORS.with_label_values(&["select_aliases"]).inc();
+            StatusCode::INTERNAL_SERVER_ERROR
+        })
+}
+
+/// Reject a learned alias both ways, it is not used nor learned again. The
+/// catalogue entries found through it are fixed when they are refreshed.
+pub async fn remove_alias(
+    headers: HeaderMap,
+    Path(id): Path<i32>,
+    Extension(token): Extension<AdminToken>,
+    Extension(pool): Extension<Pool>,
+) -> StatusCode {
+    if !is_admin(&headers, &token) {
+        AXUM_ADMIN.with_label_values(&["unauthorized"]).inc();
+        return StatusCode::UNAUTHORIZED;
+    }
+    let db_client = match pooled_client(&pool).await {
+        Ok(client) => client,
+        Err(status) => return status,
+    };
+    match reject_alias(&db_client, id).await {
+        Ok(Some(alias)) => {
+            AXUM_ADMIN.with_label_values(&["remove_alias"]).inc();
+            log::info!("[SERVER] rejected alias {:?}", alias);
+            StatusCode::NO_CONTENT
+        }
+        Ok(None) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            observe_db_error(&e);
+            log::error!("error db, cannot reject alias {} : {}", id, e);
+            DB_ERRORS.with_label_values(&["reject_alias"]).inc();
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+}
+
+pub async fn run_server(bind_addr: String, pool: Pool) {
+    let mut app = Router::new().route("/", get(root).post(root));
+    match std::env::var("ADMIN_TOKEN") {
+        Ok(token) if !token.is_empty() => {
+            app = app
+                .route("/admin/overrides", get(list_overrides).post(add_override))
+                .route("/admin/overrides/:id", delete(remove_override))
+                .route("/admin/aliases", get(list_aliases))
+                .route("/admin/aliases/:id", delete(remove_alias))
+                .layer(Extension(AdminToken(token)));
+        }
+        _ => log::info!("[SERVER] ADMIN_TOKEN not set, admin routes disabled"),
+    }
+
+    let app = app
         .nest_service("/img", ServeDir::new("img"))
         .nest_service("/assets", ServeDir::new("assets"))
         .nest_service("/css", ServeDir::new("css"))
-        .layer(Extension(client));
+        .layer(Extension(pool));
 
     log::info!("[SERVER] starting server on {}", bind_addr);
     let listener = tokio::net::TcpListener::bind(bind_addr).await.unwrap();
@@ -302,6 +534,12 @@ lazy_static! {
         "Number of get or post resquests to root route"
     )
     .unwrap();
+    static ref AXUM_ADMIN: IntCounterVec = register_int_counter_vec!(
+        "axum_admin",
+        "Number of requests to the admin routes",
+        &["action"]
+    )
+    .unwrap();
     static ref DB_ERRORS: IntCounterVec =
         register_int_counter_vec!("db_errors", "Number of error from db queries", &["error"])
             .unwrap();
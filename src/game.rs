@@ -1,18 +1,22 @@
-use crate::website::agorajeux::get_agorajeux_price_and_url_by_name;
-use crate::website::knapix::get_knapix_prices;
-use crate::website::ludifolie::get_ludifolie_price_and_url_by_name;
-use crate::website::ludocortex::get_ludocortex_price_and_url;
-use crate::website::philibert::get_philibert_price_and_url;
-use crate::website::ultrajeux::get_ultrajeux_price_and_url;
-use chrono::{DateTime, NaiveDateTime, Utc};
+use crate::website::{
+    search_resellers, MatchOverrides, ResellersSearch, SearchedGame, StandardResellerTrait,
+    RESELLERS, RESELLER_TIMEOUT,
+};
+use chrono::{DateTime, NaiveDate, Utc};
 use serde::{Deserialize, Serialize};
 use std::cmp::Ordering;
 use std::collections::HashMap;
 use std::error;
+use tokio_postgres::Client;
+
+use crate::catalogue::enrich_game;
+use crate::gtin::Gtin;
+use crate::rating::RATING_POLICY;
 
 use crate::website::okkazeo::{download_okkazeo_game_image, Row};
 
-use crate::website::bgg::get_bgg_note;
+use crate::website::bgg::{bgg_id_from_url, get_bgg_infos, get_bgg_note};
+use crate::website::trictrac::get_trictrac_note;
 
 #[derive(Debug, Default, Clone, Serialize)]
 pub struct Games {
@@ -22,14 +26,34 @@ pub struct Games {
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Reference {
     pub name: String,
+    /// List price, the crossed-out one during a sale
     pub price: f32,
+    /// Price actually asked during a sale
+    pub promo_price: Option<f32>,
+    /// Last day of the sale, when the website shows it
+    pub promo_end: Option<NaiveDate>,
     pub url: String,
+    /// None when the website does not tell if the game is in stock
+    pub available: Option<bool>,
+    /// Current price including the shipping fee of the reseller
+    pub landed_price: f32,
+    /// Found on a price comparator rather than on the shop website
+    pub aggregated: bool,
+    /// Confidence that the product is the game, None when the website gives
+    /// nothing to compare
+    pub match_score: Option<f32>,
+    /// Title of the product found by a barcode lookup which checked the barcode
+    /// of the product, when the name lookup misses it. Aliases are learned from
+    /// it. Not stored.
+    pub product_title: Option<String>,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Review {
     pub reviews: HashMap<String, Reviewer>,
     pub average_note: f32,
+    /// Between 0 and 1, how much the average note can be trusted
+    pub confidence: f32,
 }
 
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
@@ -40,6 +64,22 @@ pub struct Reviewer {
     pub number: u32,
 }
 
+/// Description of the game on boardgamegeek, None values are unknown to bgg
+#[derive(Debug, Default, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BggInfos {
+    pub id: u32,
+    pub year: Option<i32>,
+    pub min_players: Option<u32>,
+    pub max_players: Option<u32>,
+    /// Minutes
+    pub playing_time: Option<u32>,
+    pub min_age: Option<u32>,
+    /// Complexity voted by the users, from 1 (light) to 5 (heavy)
+    pub weight: Option<f32>,
+    pub categories: Vec<String>,
+    pub mechanics: Vec<String>,
+}
+
 #[derive(Debug, Default, Clone, Serialize, Deserialize)]
 pub struct Seller {
     pub name: String,
@@ -58,7 +98,7 @@ pub struct OkkazeoAnnounce {
     pub extension: String,
     pub shipping: HashMap<String, f32>,
     pub seller: Seller,
-    pub barcode: Option<u64>,
+    pub barcode: Option<Gtin>,
     pub city: Option<String>,
     pub last_modification_date: DateTime<Utc>,
 }
@@ -69,12 +109,145 @@ pub struct Game {
     pub references: HashMap<String, Reference>,
     pub review: Review,
     pub deal: Deal,
+    /// Resellers which timed out during the last lookup
+    pub timed_out_resellers: Vec<String>,
+    /// Catalogue entry the references and reviews come from
+    pub catalogue_id: Option<i32>,
+    pub bgg: Option<BggInfos>,
+    /// Previous prices, only read for the frontend listing
+    pub price_history: PriceHistory,
+}
+
+/// A price as seen on a date
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PricePoint {
+    pub price: f32,
+    pub promo_price: Option<f32>,
+    pub date: DateTime<Utc>,
+}
+
+/// Prices seen for an announce or a catalogue game, oldest first
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct PriceHistory {
+    /// Empty for a catalogue game
+    pub announce: Vec<PricePoint>,
+    /// By reseller name
+    pub references: HashMap<String, Vec<PricePoint>>,
+}
+
+impl PriceHistory {
+    /// Append a price of the announce, ignored when it did not change
+    pub fn push_announce_price(&mut self, point: PricePoint) {
+        push_price(&mut self.announce, point);
+    }
+
+    /// Append a price of a reseller, ignored when it did not change
+    pub fn push_reference_price(&mut self, reseller: &str, point: PricePoint) {
+        push_price(
+            self.references.entry(reseller.to_string()).or_default(),
+            point,
+        );
+    }
+}
+
+/// The same price seen again, through the announce and its catalogue game, adds nothing
+fn push_price(points: &mut Vec<PricePoint>, point: PricePoint) {
+    let unchanged = points
+        .last()
+        .is_some_and(|last| last.price == point.price && last.promo_price == point.promo_price);
+    if !unchanged {
+        points.push(point);
+    }
 }
 
 #[derive(Debug, Default, Clone, Serialize)]
 pub struct Deal {
     pub deal_price: i32,
     pub deal_percentage: i32,
+    /// Reference the okkazeo price is compared to
+    pub deal_reference: Option<String>,
+    /// The compared price is a sale price
+    pub deal_promo: bool,
+}
+
+impl Reference {
+    /// Price asked today, the sale price if any
+    pub fn current_price(&self) -> f32 {
+        self.promo_price.unwrap_or(self.price)
+    }
+}
+
+/// What an announce or a product title sells, from the okkazeo announce type
+/// or the markers of the title
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum GameKind {
+    /// Standalone game
+    Base,
+    Expansion,
+    /// Game sold with one or more of its expansions
+    BaseWithExpansion,
+    /// Accessories, goodies or a title without marker
+    #[default]
+    Unknown,
+}
+
+impl GameKind {
+    /// Read the okkazeo announce type ("Jeu", "Extension", "Jeu + extension")
+    pub fn from_okkazeo(kind: &str) -> GameKind {
+        match kind {
+            "Jeu" => GameKind::Base,
+            "Extension" => GameKind::Expansion,
+            "Jeu + extension" => GameKind::BaseWithExpansion,
+            _ => GameKind::Unknown,
+        }
+    }
+
+    /// False when a product of kind `other` is not worth comparing to this one.
+    /// A game sold with its expansions can still be compared to the base box,
+    /// the deal is then underestimated rather than faked.
+    pub fn accepts(&self, other: GameKind) -> bool {
+        match (self, other) {
+            (GameKind::Unknown, _) | (_, GameKind::Unknown) => true,
+            (GameKind::BaseWithExpansion, GameKind::Base) => true,
+            (searched, found) => *searched == found,
+        }
+    }
+}
+
+/// Which prices are compared to compute a deal
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+pub enum DealPrice {
+    /// Prices displayed by okkazeo and the resellers
+    #[default]
+    Shelf,
+    /// Prices including the cheapest delivery, opt-in with DEAL_PRICE=landed
+    Landed,
+}
+
+impl OkkazeoAnnounce {
+    pub fn kind(&self) -> GameKind {
+        GameKind::from_okkazeo(&self.extension)
+    }
+
+    pub fn searched(&self, aliases: &[String]) -> SearchedGame {
+        SearchedGame {
+            barcode: self.barcode,
+            aliases: aliases.to_vec(),
+            ..SearchedGame::new(&self.name, self.kind())
+        }
+    }
+
+    /// Price with the cheapest delivery. Hand delivery is only free when the
+    /// buyer lives nearby, it is used only if it is the single option
+    pub fn landed_price(&self) -> f32 {
+        let delivery = self
+            .shipping
+            .iter()
+            .filter(|(name, _)| name.as_str() != "hand_delivery")
+            .map(|(_, fee)| *fee)
+            .reduce(f32::min);
+        self.price + delivery.unwrap_or(0.0)
+    }
 }
 
 impl Ord for Game {
@@ -85,6 +258,8 @@ impl Ord for Game {
             .cmp(&self.okkazeo_announce.last_modification_date)
     }
 }
+// `<` compares the dates the other way round than `sort`, as it always did
+#[allow(clippy::non_canonical_partial_ord_impl)]
 impl PartialOrd for Game {
     fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
         Some(other.cmp(self))
@@ -102,92 +277,120 @@ impl Eq for Game {}
 
 impl Game {
     pub fn get_deal_advantage(&mut self) {
+        self.get_deal_advantage_with(*DEAL_PRICE)
+    }
+
+    pub fn get_deal_advantage_with(&mut self, deal_price: DealPrice) {
+        // a deal computed before a price change or a lost reference is no longer true
+        self.deal = Deal::default();
+
         // okkazeo is counted as a ref, so we need at least 2 refs
         if self.references.is_empty() {
             log::debug!("no references for {}", self.okkazeo_announce.name);
             return;
         }
 
-        let mut min_price = 0.0;
-        let mut first_ref = true;
-        for (_, reference) in self.references.iter() {
-            if first_ref {
-                min_price = reference.price;
-                first_ref = false;
-            }
+        let price_of = |reference: &Reference| match deal_price {
+            DealPrice::Shelf => reference.current_price(),
+            DealPrice::Landed => reference.landed_price,
+        };
+        let okkazeo_price = match deal_price {
+            DealPrice::Shelf => self.okkazeo_announce.price,
+            DealPrice::Landed => self.okkazeo_announce.landed_price(),
+        };
 
-            if min_price > reference.price {
-                min_price = reference.price;
+        // a shop without the game in stock is not a real alternative
+        let cheapest = self
+            .references
+            .values()
+            .filter(|r| r.available != Some(false))
+            .min_by(|a, b| price_of(a).total_cmp(&price_of(b)));
+        let cheapest = match cheapest {
+            Some(c) => c,
+            None => {
+                log::debug!("no reference in stock for {}", self.okkazeo_announce.name);
+                return;
             }
-        }
-        let percent: i32 = ((self.okkazeo_announce.price * 100.0) / min_price).round() as i32 - 100;
-        let economy = (self.okkazeo_announce.price - min_price).round() as i32;
+        };
+        let min_price = price_of(cheapest);
+        let percent: i32 = ((okkazeo_price * 100.0) / min_price).round() as i32 - 100;
+        let economy = (okkazeo_price - min_price).round() as i32;
 
         if economy == 0 || percent == 0 {
             return;
         }
         self.deal.deal_price = economy;
         self.deal.deal_percentage = percent;
+        self.deal.deal_reference = Some(cheapest.name.clone());
+        self.deal.deal_promo = cheapest.promo_price.is_some();
     }
 
-    pub async fn get_reviews(&mut self) {
-        match get_bgg_note(&self.okkazeo_announce.name).await {
-            Err(e) => log::error!("error getting bgg note : {}", e),
-            Ok(v) => {
-                if let Some(r) = v {
-                    self.review.reviews.insert("bgg".to_string(), r);
-                } else {
-                    log::debug!("cannot get bgg note for {}", self.okkazeo_announce.name);
-                }
-            }
+    /// Query concurrently bgg and the resellers for which we have no reference yet,
+    /// `aliases` are searched when the name of the announce finds nothing
+    pub async fn get_references_and_reviews(
+        &mut self,
+        overrides: &MatchOverrides,
+        aliases: &[String],
+    ) {
+        let resellers: Vec<&dyn StandardResellerTrait> = RESELLERS
+            .iter()
+            .map(|r| r.as_ref())
+            .filter(|r| !self.references.contains_key(r.name()))
+            .collect();
+
+        let searched = self.okkazeo_announce.searched(aliases);
+        let (search, (bgg, bgg_review), trictrac_review) = tokio::join!(
+            search_resellers(&resellers, &searched, overrides),
+            get_bgg(&searched, overrides.get("bgg")),
+            get_trictrac(&searched, overrides.get("trictrac"))
+        );
+
+        self.add_references(search);
+        if bgg.is_some() {
+            self.bgg = bgg;
+        }
+        if let Some(r) = bgg_review {
+            self.review.reviews.insert("bgg".to_string(), r);
+        }
+        if let Some(r) = trictrac_review {
+            self.review.reviews.insert("trictrac".to_string(), r);
         }
         self.review.compute_average_note();
     }
 
-    pub fn update_game(&mut self, row: Row) {
-        self.okkazeo_announce.price = row.prix_annonce;
-
-        let naive_date: NaiveDateTime =
-            match NaiveDateTime::parse_from_str(row.date.as_str(), "%Y-%m-%d %H:%M:%S") {
-                Ok(dt) => dt,
-                Err(e) => {
-                    log::error!("Failed to parse datetime \"{}\" : {}", row.date, e);
-                    return;
-                }
-            };
-        let datetime_utc = DateTime::from_naive_utc_and_offset(naive_date, Utc);
-        self.okkazeo_announce.last_modification_date = datetime_utc;
-
+    /// Query again the resellers which timed out during the previous lookup
+    pub async fn retry_timed_out_resellers(
+        &mut self,
+        overrides: &MatchOverrides,
+        aliases: &[String],
+    ) {
+        let resellers: Vec<&dyn StandardResellerTrait> = RESELLERS
+            .iter()
+            .map(|r| r.as_ref())
+            .filter(|r| self.timed_out_resellers.iter().any(|name| name == r.name()))
+            .collect();
+        log::debug!(
+            "retrying {} timed out resellers for {}",
+            resellers.len(),
+            self.okkazeo_announce.name
+        );
+
+        let searched = self.okkazeo_announce.searched(aliases);
+        let search = search_resellers(&resellers, &searched, overrides).await;
+        self.add_references(search);
         self.get_deal_advantage();
+    }
 
-        if let Some(v) = row.shop2shop {
-            self.okkazeo_announce
-                .shipping
-                .insert("shop2shop".to_string(), v);
-        }
-        if let Some(v) = row.colissimo {
-            self.okkazeo_announce
-                .shipping
-                .insert("colissimo".to_string(), v);
-        }
-
-        if let Some(v) = row.mondial_relay {
-            self.okkazeo_announce
-                .shipping
-                .insert("mondial_relay".to_string(), v);
-        }
-
-        if let Some(v) = row.relais_colis {
-            self.okkazeo_announce
-                .shipping
-                .insert("relais_colis".to_string(), v);
+    /// References already known are kept, inside a search the last reseller wins
+    fn add_references(&mut self, search: ResellersSearch) {
+        let mut found = HashMap::new();
+        for reference in search.references {
+            found.insert(reference.name.clone(), reference);
         }
-
-        if row.rmp {
-            self.okkazeo_announce
-                .shipping
-                .insert("hand_delivery".to_string(), 0.0);
+        for (name, reference) in found {
+            self.references.entry(name).or_insert(reference);
         }
+        self.timed_out_resellers = search.timed_out;
     }
 }
 
@@ -200,27 +403,135 @@ impl Games {
 }
 
 impl Review {
+    /// Aggregate the notes of the reviewers following `RATING_POLICY`
     pub fn compute_average_note(&mut self) {
-        let mut total_reviewer = 0;
-        let mut note = 0.0;
-        for val in self.reviews.values() {
-            total_reviewer += val.number;
-            note += val.note * val.number as f32
+        (self.average_note, self.confidence) = RATING_POLICY.aggregate(&self.reviews);
+    }
+}
+
+/// Description and rating of the game from the bgg API, the rating is
+/// scraped from the search page when the API cannot be used. The aliases of
+/// the game are tried in turn when its name is not found.
+/// `bgg_override` is the game page pinned by an admin, None for no match.
+async fn get_bgg(
+    game: &SearchedGame,
+    bgg_override: Option<&Option<String>>,
+) -> (Option<BggInfos>, Option<Reviewer>) {
+    let name = game.name.as_str();
+    let pinned_id = match bgg_override {
+        None => None,
+        Some(None) => {
+            log::debug!("{} is not on bgg, skipping lookup", name);
+            return (None, None);
+        }
+        Some(Some(url)) => {
+            let id = bgg_id_from_url(url);
+            if id.is_none() {
+                log::warn!("pinned bgg page {} has no game id, ignoring it", url);
+            }
+            id
+        }
+    };
+    // a pinned game is found whatever the name
+    let names = match pinned_id {
+        Some(_) => vec![name],
+        None => std::iter::once(name)
+            .chain(game.aliases.iter().map(String::as_str))
+            .collect(),
+    };
+
+    for searched in &names {
+        match tokio::time::timeout(
+            *RESELLER_TIMEOUT,
+            get_bgg_infos(searched, game.kind, pinned_id),
+        )
+        .await
+        {
+            Err(_) => {
+                log::warn!("bgg api lookup for {} timed out", searched);
+                return (None, None);
+            }
+            Ok(Err(e)) => {
+                log::error!("error getting bgg infos : {}", e);
+                for searched in &names {
+                    if let Some(review) = get_bgg_review(searched, pinned_id).await {
+                        return (None, Some(review));
+                    }
+                }
+                return (None, None);
+            }
+            Ok(Ok(None)) => log::debug!("cannot find {} on bgg api", searched),
+            Ok(Ok(Some((infos, review)))) => return (Some(infos), review),
         }
+    }
+    (None, None)
+}
 
-        if total_reviewer == 0 || note == 0.0 {
-            self.average_note = 0.0;
-        } else {
-            self.average_note = note / total_reviewer as f32;
+async fn get_bgg_review(name: &str, pinned_id: Option<u32>) -> Option<Reviewer> {
+    match tokio::time::timeout(*RESELLER_TIMEOUT, get_bgg_note(name, pinned_id)).await {
+        Err(_) => {
+            log::warn!("bgg lookup for {} timed out", name);
+            None
+        }
+        Ok(Err(e)) => {
+            log::error!("error getting bgg note : {}", e);
+            None
         }
+        Ok(Ok(None)) => {
+            log::debug!("cannot get bgg note for {}", name);
+            None
+        }
+        Ok(Ok(review)) => review,
     }
 }
 
-pub async fn get_game_infos(row: Row) -> Result<Box<Game>, Box<dyn error::Error + Send + Sync>> {
+/// Rating of the french community, `trictrac_override` is the game page
+/// pinned by an admin, None for no match
+async fn get_trictrac(
+    game: &SearchedGame,
+    trictrac_override: Option<&Option<String>>,
+) -> Option<Reviewer> {
+    let pinned_url = match trictrac_override {
+        None => None,
+        Some(None) => {
+            log::debug!("{} is not on trictrac, skipping lookup", game.name);
+            return None;
+        }
+        Some(Some(url)) => Some(url.as_str()),
+    };
+    match tokio::time::timeout(*RESELLER_TIMEOUT, get_trictrac_note(game, pinned_url)).await {
+        Err(_) => {
+            log::warn!("trictrac lookup for {} timed out", game.name);
+            None
+        }
+        Ok(Err(e)) => {
+            log::error!("error getting trictrac note : {}", e);
+            None
+        }
+        Ok(Ok(None)) => {
+            log::debug!("cannot get trictrac note for {}", game.name);
+            None
+        }
+        Ok(Ok(review)) => review,
+    }
+}
+
+pub async fn get_game_infos(
+    db_client: &mut Client,
+    row: Row,
+) -> Result<Box<Game>, Box<dyn error::Error + Send + Sync>> {
     log::debug!("Getting game infos, parsing row");
+    let last_modification_date = match row.last_modification_date() {
+        Ok(date) => date,
+        Err(e) => {
+            log::error!("Failed to parse datetime \"{}\" : {}", row.date, e);
+            return Err(Box::new(e));
+        }
+    };
     let mut game = Box::new(Game {
         okkazeo_announce: OkkazeoAnnounce {
             id: row.id,
+            last_modification_date,
             price: row.prix_annonce,
             url: row.url_announce,
             extension: row.kind,
@@ -228,7 +539,7 @@ pub async fn get_game_infos(row: Row) -> Result<Box<Game>, Box<dyn error::Error
             shipping: HashMap::new(),
             city: Some(match row.zipcode {
                 Some(z) => format!("{} ({})", row.city, z),
-                None => format!("{}", row.city),
+                None => row.city.to_string(),
             }),
             ..Default::default()
         },
@@ -236,17 +547,6 @@ pub async fn get_game_infos(row: Row) -> Result<Box<Game>, Box<dyn error::Error
         ..Default::default()
     });
 
-    let naive_date: NaiveDateTime =
-        match NaiveDateTime::parse_from_str(row.date.as_str(), "%Y-%m-%d %H:%M:%S") {
-            Ok(dt) => dt,
-            Err(e) => {
-                log::error!("Failed to parse datetime \"{}\" : {}", row.date, e);
-                return Err(Box::new(e));
-            }
-        };
-    let datetime_utc = DateTime::from_naive_utc_and_offset(naive_date, Utc);
-    game.okkazeo_announce.last_modification_date = datetime_utc;
-
     game.okkazeo_announce.seller = Seller {
         name: row.vendor,
         url: row.url_vendor,
@@ -301,119 +601,156 @@ pub async fn get_game_infos(row: Row) -> Result<Box<Game>, Box<dyn error::Error
     let image = download_okkazeo_game_image(&image_url).await?;
     game.okkazeo_announce.image = image;
 
-    if let Err(e) = get_knapix_prices(&mut game).await {
-        log::error!("Error gettin knapix prices : {:?}", e);
-    }
-
-    if game.references.get("philibert").is_none() {
-        match get_philibert_price_and_url(
-            &game.okkazeo_announce.name,
-            game.okkazeo_announce.barcode,
-        )
-        .await
-        {
-            Err(e) => log::error!("error getting philibert price : {}", e),
-            Ok(v) => {
-                if let Some((price, url)) = v {
-                    game.references.insert(
-                        "philibert".to_string(),
-                        Reference {
-                            name: "philibert".to_string(),
-                            price,
-                            url,
-                        },
-                    );
-                }
-            }
-        }
-    }
+    enrich_game(db_client, &mut game).await;
+    game.get_deal_advantage();
 
-    if game.references.get("agorajeux").is_none() {
-        match get_agorajeux_price_and_url_by_name(&game.okkazeo_announce.name).await {
-            Err(e) => log::error!("error getting agorajeux price : {}", e),
-            Ok(v) => {
-                if let Some((price, url)) = v {
-                    game.references.insert(
-                        "agorajeux".to_string(),
-                        Reference {
-                            name: "agorajeux".to_string(),
-                            price,
-                            url,
-                        },
-                    );
-                }
-            }
-        }
-    }
+    log::debug!("returning game {:?}", game);
+    Ok(game)
+}
 
-    if game.references.get("ludifolie").is_none() {
-        match get_ludifolie_price_and_url_by_name(&game.okkazeo_announce.name).await {
-            Err(e) => log::error!("error getting ludifolie price : {}", e),
-            Ok(v) => {
-                if let Some((price, url)) = v {
-                    game.references.insert(
-                        "ludifolie".to_string(),
-                        Reference {
-                            name: "ludifolie".to_string(),
-                            price,
-                            url,
-                        },
-                    );
-                }
-            }
+fn create_deal_price() -> DealPrice {
+    match std::env::var("DEAL_PRICE").as_deref() {
+        Ok("shelf") => DealPrice::Shelf,
+        Ok("landed") => DealPrice::Landed,
+        v => {
+            log::warn!(
+                "Cannot initialize deal price from environment ({:?}), fallback to default",
+                v
+            );
+            DealPrice::default()
         }
     }
+}
 
-    /*
-    if game.references.get("ultrajeux").is_none() {
-        match get_ultrajeux_price_and_url(
-            &game.okkazeo_announce.name,
-            game.okkazeo_announce.barcode,
-        )
-        .await
-        {
-            Err(e) => log::error!("error getting ultrajeux price : {}", e),
-            Ok(v) => {
-                if let Some((price, url)) = v {
-                    game.references.insert(
-                        "ultrajeux".to_string(),
-                        Reference {
-                            name: "ultrajeux".to_string(),
-                            price,
-                            url,
-                        },
-                    );
-                }
-            }
-        }
-    }*/
+use lazy_static::lazy_static;
+lazy_static! {
+    /// Prices compared by `Game::get_deal_advantage`, shelf prices unless DEAL_PRICE=landed
+    pub static ref DEAL_PRICE: DealPrice = create_deal_price();
+}
 
-    if game.references.get("ludocortex").is_none() {
-        match get_ludocortex_price_and_url(
-            &game.okkazeo_announce.name,
-            game.okkazeo_announce.barcode,
-        )
-        .await
-        {
-            Err(e) => log::error!("error getting ultrajeux price : {}", e),
-            Ok(v) => {
-                if let Some((price, url)) = v {
-                    game.references.insert(
-                        "ludocortex".to_string(),
-                        Reference {
-                            name: "ludocortex".to_string(),
-                            price,
-                            url,
-                        },
-                    );
-                }
-            }
-        }
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use chrono::{TimeZone, Utc};
+
+    use super::{DealPrice, Game, OkkazeoAnnounce, PriceHistory, PricePoint, Reference};
+
+    #[test]
+    fn test_deal_advantage_landed() {
+        let mut game = Game {
+            okkazeo_announce: OkkazeoAnnounce {
+                price: 30.0,
+                shipping: HashMap::from([
+                    ("colissimo".to_string(), 8.0),
+                    ("mondial_relay".to_string(), 5.0),
+                    ("hand_delivery".to_string(), 0.0),
+                ]),
+                ..Default::default()
+            },
+            references: HashMap::from([(
+                "philibert".to_string(),
+                Reference {
+                    name: "philibert".to_string(),
+                    price: 40.0,
+                    landed_price: 45.9,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(game.okkazeo_announce.landed_price(), 35.0);
+
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, -10);
+        assert_eq!(game.deal.deal_percentage, -25);
+
+        game.get_deal_advantage_with(DealPrice::Landed);
+        assert_eq!(game.deal.deal_price, -11);
+        assert_eq!(game.deal.deal_percentage, -24);
+        assert_eq!(game.deal.deal_reference.as_deref(), Some("philibert"));
+        assert!(!game.deal.deal_promo);
+
+        // a sale price is compared instead of the list price
+        game.references.insert(
+            "agorajeux".to_string(),
+            Reference {
+                name: "agorajeux".to_string(),
+                price: 40.0,
+                promo_price: Some(36.0),
+                landed_price: 36.0,
+                ..Default::default()
+            },
+        );
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, -6);
+        assert_eq!(game.deal.deal_reference.as_deref(), Some("agorajeux"));
+        assert!(game.deal.deal_promo);
     }
 
-    game.get_reviews().await;
-    game.get_deal_advantage();
+    #[test]
+    fn test_deal_advantage_reset() {
+        let mut game = Game {
+            okkazeo_announce: OkkazeoAnnounce {
+                price: 30.0,
+                ..Default::default()
+            },
+            references: HashMap::from([(
+                "philibert".to_string(),
+                Reference {
+                    name: "philibert".to_string(),
+                    price: 40.0,
+                    ..Default::default()
+                },
+            )]),
+            ..Default::default()
+        };
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, -10);
+
+        // the only reference is out of stock
+        game.references.get_mut("philibert").unwrap().available = Some(false);
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, 0);
+        assert_eq!(game.deal.deal_percentage, 0);
+        assert_eq!(game.deal.deal_reference, None);
+
+        // the announce is now at the reference price
+        game.references.get_mut("philibert").unwrap().available = None;
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, -10);
+        game.okkazeo_announce.price = 40.0;
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, 0);
+        assert_eq!(game.deal.deal_reference, None);
+
+        game.references.clear();
+        game.okkazeo_announce.price = 30.0;
+        game.get_deal_advantage_with(DealPrice::Shelf);
+        assert_eq!(game.deal.deal_price, 0);
+    }
 
-    log::debug!("returning game {:?}", game);
-    Ok(game)
+    #[test]
+    fn test_price_history() {
+        let point = |price: f32, promo_price: Option<f32>, day: u32| PricePoint {
+            price,
+            promo_price,
+            date: Utc.with_ymd_and_hms(2024, 3, day, 12, 0, 0).unwrap(),
+        };
+        let mut history = PriceHistory::default();
+        history.push_announce_price(point(30.0, None, 1));
+        history.push_announce_price(point(30.0, None, 2));
+        history.push_announce_price(point(25.0, None, 3));
+        assert_eq!(
+            history.announce,
+            vec![point(30.0, None, 1), point(25.0, None, 3)]
+        );
+
+        history.push_reference_price("philibert", point(40.0, None, 1));
+        history.push_reference_price("philibert", point(40.0, Some(35.0), 2));
+        history.push_reference_price("philibert", point(40.0, Some(35.0), 3));
+        history.push_reference_price("agorajeux", point(39.0, None, 1));
+        assert_eq!(history.references["philibert"].len(), 2);
+        assert_eq!(history.references["agorajeux"].len(), 1);
+    }
 }
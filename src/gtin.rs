@@ -0,0 +1,214 @@
+use std::{fmt, str::FromStr};
+
+use serde::{Deserialize, Serialize};
+
+/// Barcode of a product (EAN-8, UPC-A, EAN-13 or GTIN-14) with a valid check digit.
+/// Leading zeros are not significant, a UPC-A is the EAN-13 made of a 0
+/// followed by its 12 digits and "00123457" is the EAN-8 123457.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "u64", into = "u64")]
+pub struct Gtin(u64);
+
+/// Why a barcode was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GtinError {
+    /// Something else than digits, spaces or dashes
+    NotDigits,
+    /// Number of digits, none of the 8, 12, 13 or 14 of the GTIN formats
+    Length(usize),
+    Checksum,
+}
+
+impl fmt::Display for GtinError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GtinError::NotDigits => write!(f, "not a number"),
+            GtinError::Length(n) => write!(f, "{} digits", n),
+            GtinError::Checksum => write!(f, "wrong check digit"),
+        }
+    }
+}
+
+impl std::error::Error for GtinError {}
+
+const GTIN_MAX: u64 = 99_999_999_999_999;
+
+impl Gtin {
+    /// None for 0, too long codes and wrong check digits. The number of digits
+    /// of a code is only known from its printed form, see `from_str`.
+    pub fn new(code: u64) -> Option<Gtin> {
+        Gtin::try_from(code).ok()
+    }
+
+    pub fn value(&self) -> u64 {
+        self.0
+    }
+}
+
+/// The check digit is the last one, the others are weighted 3, 1, 3... from the right
+fn has_valid_check_digit(code: u64) -> bool {
+    let mut sum = code % 10;
+    let mut rest = code / 10;
+    let mut weight = 3;
+    while rest > 0 {
+        sum += (rest % 10) * weight;
+        rest /= 10;
+        weight = 4 - weight;
+    }
+    sum.is_multiple_of(10)
+}
+
+impl TryFrom<u64> for Gtin {
+    type Error = GtinError;
+
+    fn try_from(code: u64) -> Result<Self, Self::Error> {
+        if !(1..=GTIN_MAX).contains(&code) {
+            return Err(GtinError::Length(
+                code.checked_ilog10().map_or(1, |l| l + 1) as usize,
+            ));
+        }
+        if !has_valid_check_digit(code) {
+            return Err(GtinError::Checksum);
+        }
+        Ok(Gtin(code))
+    }
+}
+
+impl From<Gtin> for u64 {
+    fn from(gtin: Gtin) -> u64 {
+        gtin.0
+    }
+}
+
+impl FromStr for Gtin {
+    type Err = GtinError;
+
+    /// Read a printed barcode, "0 36000 29145-2" is the UPC-A 036000291452
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let digits: String = s
+            .trim()
+            .chars()
+            .filter(|c| *c != ' ' && *c != '-')
+            .collect();
+        if digits.is_empty() || !digits.chars().all(|c| c.is_ascii_digit()) {
+            return Err(GtinError::NotDigits);
+        }
+        if ![8, 12, 13, 14].contains(&digits.len()) {
+            return Err(GtinError::Length(digits.len()));
+        }
+        digits
+            .parse::<u64>()
+            .map_err(|_| GtinError::NotDigits)
+            .and_then(Gtin::try_from)
+    }
+}
+
+impl fmt::Display for Gtin {
+    /// EAN-8 when it fits, else the EAN-13 shown on the boxes
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            code if code < 100_000_000 => write!(f, "{:08}", code),
+            code if code < 10_000_000_000_000 => write!(f, "{:013}", code),
+            code => write!(f, "{:014}", code),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Gtin, GtinError};
+
+    #[test]
+    fn test_parse() {
+        struct Test {
+            code: &'static str,
+            result: Result<u64, GtinError>,
+        }
+        let tests = vec![
+            // EAN-13
+            Test {
+                code: "3760269590120",
+                result: Ok(3760269590120),
+            },
+            // EAN-8
+            Test {
+                code: "96385074",
+                result: Ok(96385074),
+            },
+            // EAN-8 starting with zeros
+            Test {
+                code: "00123457",
+                result: Ok(123457),
+            },
+            Test {
+                code: "123457",
+                result: Err(GtinError::Length(6)),
+            },
+            // UPC-A, the same code as its EAN-13
+            Test {
+                code: "036000291452",
+                result: Ok(36000291452),
+            },
+            Test {
+                code: "0036000291452",
+                result: Ok(36000291452),
+            },
+            Test {
+                code: "0 36000 29145-2",
+                result: Ok(36000291452),
+            },
+            Test {
+                code: "3760269590121",
+                result: Err(GtinError::Checksum),
+            },
+            Test {
+                code: "0",
+                result: Err(GtinError::Length(1)),
+            },
+            Test {
+                code: "",
+                result: Err(GtinError::NotDigits),
+            },
+            Test {
+                code: "978-2-07-036",
+                result: Err(GtinError::Length(9)),
+            },
+            Test {
+                code: "376026959012X",
+                result: Err(GtinError::NotDigits),
+            },
+        ];
+        for test in tests {
+            assert_eq!(
+                test.code.parse::<Gtin>().map(|g| g.value()),
+                test.result,
+                "{}",
+                test.code
+            );
+        }
+    }
+
+    #[test]
+    fn test_new() {
+        assert_eq!(
+            Gtin::new(3760269590120).map(|g| g.value()),
+            Some(3760269590120)
+        );
+        assert_eq!(Gtin::new(0), None);
+        assert_eq!(Gtin::new(18), None);
+        assert_eq!(Gtin::new(123457).map(|g| g.value()), Some(123457));
+        assert_eq!(Gtin::new(3760269590121), None);
+        assert_eq!(Gtin::new(123_456_789_012_345), None);
+    }
+
+    #[test]
+    fn test_display() {
+        assert_eq!(
+            Gtin::new(3760269590120).unwrap().to_string(),
+            "3760269590120"
+        );
+        assert_eq!(Gtin::new(96385074).unwrap().to_string(), "96385074");
+        assert_eq!(Gtin::new(123457).unwrap().to_string(), "00123457");
+        assert_eq!(Gtin::new(36000291452).unwrap().to_string(), "0036000291452");
+    }
+}
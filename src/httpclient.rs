@@ -1,6 +1,6 @@
 use governor::state::keyed::DefaultKeyedStateStore;
 use governor::{clock, DefaultKeyedRateLimiter, Quota, RateLimiter};
-use hyper::{HeaderMap, StatusCode};
+use hyper::{HeaderMap, Method, StatusCode};
 use nonzero_ext::nonzero;
 use rand::rngs::StdRng;
 use rand::{Rng, SeedableRng};
@@ -91,6 +91,28 @@ fn create_limiter() -> DefaultKeyedRateLimiter<String> {
 
 /// Execute a request using the shared http client
 pub async fn get<U: IntoUrl>(url: U) -> Result<Response, reqwest::Error> {
+    get_with_bearer(url, None).await
+}
+
+/// Execute a request using the shared http client, authenticated by `token`
+/// for the APIs requiring it
+pub async fn get_with_bearer<U: IntoUrl>(
+    url: U,
+    token: Option<&str>,
+) -> Result<Response, reqwest::Error> {
+    send(Method::GET, url, token).await
+}
+
+/// Execute a HEAD request using the shared http client, for the headers only
+pub async fn head<U: IntoUrl>(url: U) -> Result<Response, reqwest::Error> {
+    send(Method::HEAD, url, None).await
+}
+
+async fn send<U: IntoUrl>(
+    method: Method,
+    url: U,
+    token: Option<&str>,
+) -> Result<Response, reqwest::Error> {
     let url_r = url.into_url()?;
 
     let ratelimit_key = url_r.host_str().unwrap().to_string();
@@ -101,7 +123,11 @@ pub async fn get<U: IntoUrl>(url: U) -> Result<Response, reqwest::Error> {
     loop {
         let client = CLIENTS.get(rng.gen_range(0..CLIENTS.len())).unwrap();
         log::debug!("Sending request to {:?}", client);
-        match client.get(url_r.clone()).send().await {
+        let mut request = client.request(method.clone(), url_r.clone());
+        if let Some(token) = token {
+            request = request.bearer_auth(token);
+        }
+        match request.send().await {
             Ok(resp) => {
                 return Ok(resp);
             }
@@ -125,3 +151,35 @@ pub async fn get_doc<U: IntoUrl>(url: U) -> Result<(Html, StatusCode), reqwest::
     let document = Html::parse_document(&content);
     Ok((document, http_code))
 }
+
+/// Follow the redirections of `url` and return the final URL.
+/// The shared clients do not follow redirections, each hop goes through
+/// the rate limiter of its own host. Only the headers are requested, with a
+/// GET for the hosts refusing HEAD.
+pub async fn resolve_redirects<U: IntoUrl>(
+    url: U,
+    max_hops: usize,
+) -> Result<String, reqwest::Error> {
+    let mut current = url.into_url()?;
+    for _ in 0..max_hops {
+        let mut response = head(current.clone()).await?;
+        if response.status() == StatusCode::METHOD_NOT_ALLOWED {
+            response = get(current.clone()).await?;
+        }
+        if !response.status().is_redirection() {
+            break;
+        }
+        let location = match response
+            .headers()
+            .get(hyper::header::LOCATION)
+            .and_then(|l| l.to_str().ok())
+            .and_then(|l| current.join(l).ok())
+        {
+            Some(l) => l,
+            None => break,
+        };
+        log::trace!("{} redirects to {}", current, location);
+        current = location;
+    }
+    Ok(current.to_string())
+}
@@ -1,6 +1,11 @@
+pub mod alias;
+pub mod catalogue;
 pub mod db;
 pub mod frontlib;
 pub mod game;
+pub mod gtin;
 pub mod httpclient;
 pub mod metrics;
+pub mod migration;
+pub mod rating;
 pub mod website;
@@ -0,0 +1,360 @@
+use std::fmt;
+
+use tokio_postgres::Client;
+
+/// Change of the schema, applied once in the order of `version`
+pub struct Migration {
+    pub version: i32,
+    pub name: &'static str,
+    sql: &'static str,
+}
+
+/// Every migration, a new one is added at the end with the next version and never modified once released
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        version: 1,
+        name: "initial",
+        sql: include_str!("../migrations/0001_initial.sql"),
+    },
+    Migration {
+        version: 2,
+        name: "timed_out_resellers",
+        sql: include_str!("../migrations/0002_timed_out_resellers.sql"),
+    },
+    Migration {
+        version: 3,
+        name: "landed_price",
+        sql: include_str!("../migrations/0003_landed_price.sql"),
+    },
+    Migration {
+        version: 4,
+        name: "aggregated_reference",
+        sql: include_str!("../migrations/0004_aggregated_reference.sql"),
+    },
+    Migration {
+        version: 5,
+        name: "promo_price",
+        sql: include_str!("../migrations/0005_promo_price.sql"),
+    },
+    Migration {
+        version: 6,
+        name: "match_score",
+        sql: include_str!("../migrations/0006_match_score.sql"),
+    },
+    Migration {
+        version: 7,
+        name: "game_catalogue",
+        sql: include_str!("../migrations/0007_game_catalogue.sql"),
+    },
+    Migration {
+        version: 8,
+        name: "bgg_infos",
+        sql: include_str!("../migrations/0008_bgg_infos.sql"),
+    },
+    Migration {
+        version: 9,
+        name: "match_override",
+        sql: include_str!("../migrations/0009_match_override.sql"),
+    },
+    Migration {
+        version: 10,
+        name: "title_alias",
+        sql: include_str!("../migrations/0010_title_alias.sql"),
+    },
+    Migration {
+        version: 11,
+        name: "name_search",
+        sql: include_str!("../migrations/0011_name_search.sql"),
+    },
+    Migration {
+        version: 12,
+        name: "seller_name",
+        sql: include_str!("../migrations/0012_seller_name.sql"),
+    },
+    Migration {
+        version: 13,
+        name: "price_history",
+        sql: include_str!("../migrations/0013_price_history.sql"),
+    },
+    Migration {
+        version: 14,
+        name: "shipping_key",
+        sql: include_str!("../migrations/0014_shipping_key.sql"),
+    },
+    Migration {
+        version: 15,
+        name: "game_references",
+        sql: include_str!("../migrations/0015_game_references.sql"),
+    },
+    Migration {
+        version: 16,
+        name: "price_history_game_key",
+        sql: include_str!("../migrations/0016_price_history_game_key.sql"),
+    },
+    Migration {
+        version: 17,
+        name: "alias_rejected",
+        sql: include_str!("../migrations/0017_alias_rejected.sql"),
+    },
+    Migration {
+        version: 18,
+        name: "reenrich_request",
+        sql: include_str!("../migrations/0018_reenrich_request.sql"),
+    },
+];
+
+/// Backend and frontend starting together wait for each other instead of migrating twice
+const MIGRATION_LOCK: i64 = 0x6d65_6570_6c65;
+
+/// Why the database cannot be used with this build
+#[derive(Debug)]
+pub enum MigrationError {
+    Db(tokio_postgres::Error),
+    /// The database was migrated by a more recent build
+    TooRecent {
+        database: i32,
+        latest: i32,
+    },
+    /// The migration applied to the database is not the embedded one
+    Mismatch {
+        version: i32,
+        database: String,
+        embedded: String,
+    },
+}
+
+impl fmt::Display for MigrationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MigrationError::Db(e) => write!(f, "{}", e),
+            MigrationError::TooRecent { database, latest } => write!(
+                f,
+                "database schema version {} is more recent than the latest known {}",
+                database, latest
+            ),
+            MigrationError::Mismatch {
+                version,
+                database,
+                embedded,
+            } => write!(
+                f,
+                "migration {} is {} in the database but {} in this build",
+                version, database, embedded
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MigrationError {}
+
+impl From<tokio_postgres::Error> for MigrationError {
+    fn from(e: tokio_postgres::Error) -> Self {
+        MigrationError::Db(e)
+    }
+}
+
+/// Migrations to apply to a database where the `applied` (version, name) are
+fn pending_migrations(applied: &[(i32, String)]) -> Result<&'static [Migration], MigrationError> {
+    let latest = MIGRATIONS.last().map_or(0, |m| m.version);
+    for (version, name) in applied {
+        let migration = match MIGRATIONS.iter().find(|m| m.version == *version) {
+            Some(m) => m,
+            None => {
+                return Err(MigrationError::TooRecent {
+                    database: *version,
+                    latest,
+                })
+            }
+        };
+        if migration.name != name {
+            return Err(MigrationError::Mismatch {
+                version: *version,
+                database: name.clone(),
+                embedded: migration.name.to_string(),
+            });
+        }
+    }
+    let current = applied.iter().map(|(v, _)| *v).max().unwrap_or(0);
+    Ok(&MIGRATIONS[MIGRATIONS
+        .iter()
+        .position(|m| m.version > current)
+        .unwrap_or(MIGRATIONS.len())..])
+}
+
+/// Bring the schema to the latest version, each migration is recorded in
+/// `schema_version`. Fails when the database does not match this build.
+pub async fn migrate(db_client: &mut Client) -> Result<i32, MigrationError> {
+    let transaction = db_client.transaction().await?;
+    // waiting for the lock and migrating may take longer than a query is allowed
+    transaction
+        .batch_execute("SET LOCAL statement_timeout = 0")
+        .await?;
+    transaction
+        .execute("SELECT pg_advisory_xact_lock($1)", &[&MIGRATION_LOCK])
+        .await?;
+    transaction
+        .batch_execute(
+            r#"CREATE TABLE IF NOT EXISTS "schema_version" (
+                "sv_version" integer PRIMARY KEY,
+                "sv_name" text NOT NULL,
+                "sv_applied" timestamptz NOT NULL DEFAULT now()
+            )"#,
+        )
+        .await?;
+
+    let applied: Vec<(i32, String)> = transaction
+        .query("SELECT sv_version, sv_name FROM schema_version", &[])
+        .await?
+        .iter()
+        .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+        .collect::<Result<_, tokio_postgres::Error>>()?;
+    let pending = pending_migrations(&applied)?;
+
+    for migration in pending {
+        log::info!(
+            "applying migration {} {}",
+            migration.version,
+            migration.name
+        );
+        transaction.batch_execute(migration.sql).await?;
+        transaction
+            .execute(
+                "INSERT INTO schema_version (sv_version, sv_name) VALUES ($1, $2)",
+                &[&migration.version, &migration.name],
+            )
+            .await?;
+    }
+    transaction.commit().await?;
+
+    let version = MIGRATIONS.last().map_or(0, |m| m.version);
+    log::info!("database schema at version {}", version);
+    Ok(version)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{migrate, pending_migrations, MigrationError, MIGRATIONS};
+    use tokio_postgres::{Client, Config, NoTls};
+
+    #[test]
+    fn test_migrations_order() {
+        for (i, migration) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(migration.version, i as i32 + 1, "{}", migration.name);
+        }
+    }
+
+    #[test]
+    fn test_pending_migrations() {
+        let latest = MIGRATIONS.len() as i32;
+        let versions = |applied: &[(i32, String)]| {
+            pending_migrations(applied).map(|p| p.iter().map(|m| m.version).collect::<Vec<_>>())
+        };
+
+        assert_eq!(versions(&[]).unwrap(), (1..=latest).collect::<Vec<_>>());
+        assert_eq!(
+            versions(&[(1, "initial".to_string())]).unwrap(),
+            (2..=latest).collect::<Vec<_>>()
+        );
+        let all: Vec<(i32, String)> = MIGRATIONS
+            .iter()
+            .map(|m| (m.version, m.name.to_string()))
+            .collect();
+        assert!(versions(&all).unwrap().is_empty());
+
+        assert!(matches!(
+            versions(&[(latest + 1, "future".to_string())]),
+            Err(MigrationError::TooRecent { .. })
+        ));
+        assert!(matches!(
+            versions(&[(1, "other".to_string())]),
+            Err(MigrationError::Mismatch { version: 1, .. })
+        ));
+    }
+
+    async fn connect(config: &Config, dbname: &str) -> Client {
+        let (client, connection) = config.clone().dbname(dbname).connect(NoTls).await.unwrap();
+        tokio::spawn(connection);
+        client
+    }
+
+    async fn columns(client: &Client) -> Vec<(String, String, String, String)> {
+        client
+            .query(
+                "SELECT table_name::text, column_name::text, data_type::text, is_nullable::text \
+                FROM information_schema.columns WHERE table_schema = 'public' \
+                ORDER BY table_name, column_name",
+                &[],
+            )
+            .await
+            .unwrap()
+            .iter()
+            .map(|row| (row.get(0), row.get(1), row.get(2), row.get(3)))
+            .collect()
+    }
+
+    /// Needs DB_URL with the right to create databases, skipped without it
+    #[tokio::test]
+    async fn test_migrate_scrapy_baseline() {
+        let Ok(db_url) = std::env::var("DB_URL") else {
+            return;
+        };
+        let config: Config = db_url.parse().unwrap();
+        let admin = connect(&config, config.get_dbname().unwrap_or("postgres")).await;
+        let baseline_db = format!("migration_baseline_{}", std::process::id());
+        let fresh_db = format!("migration_fresh_{}", std::process::id());
+        for db in [&baseline_db, &fresh_db] {
+            admin
+                .batch_execute(&format!("CREATE DATABASE {}", db))
+                .await
+                .unwrap();
+        }
+
+        let mut baseline = connect(&config, &baseline_db).await;
+        baseline
+            .batch_execute(include_str!("../tests/schema/scrapy_baseline.sql"))
+            .await
+            .unwrap();
+        let latest = MIGRATIONS.len() as i32;
+        assert_eq!(migrate(&mut baseline).await.unwrap(), latest);
+        // a second start finds nothing to apply
+        assert_eq!(migrate(&mut baseline).await.unwrap(), latest);
+
+        let mut fresh = connect(&config, &fresh_db).await;
+        migrate(&mut fresh).await.unwrap();
+        assert_eq!(columns(&baseline).await, columns(&fresh).await);
+
+        let count = |query: &'static str| {
+            let baseline = &baseline;
+            async move {
+                baseline
+                    .query_one(query, &[])
+                    .await
+                    .unwrap()
+                    .get::<_, i64>(0)
+            }
+        };
+        assert_eq!(count("SELECT count(*) FROM okkazeo_announce").await, 3);
+        assert_eq!(
+            count("SELECT count(*) FROM okkazeo_announce WHERE oa_seller = 'alice'").await,
+            2
+        );
+        assert_eq!(count("SELECT count(*) FROM shipping").await, 2);
+        assert_eq!(
+            count("SELECT count(*) FROM announce_price_history").await,
+            3
+        );
+        assert_eq!(
+            count("SELECT count(*) FROM reference_price_history").await,
+            1
+        );
+
+        drop(baseline);
+        drop(fresh);
+        for db in [&baseline_db, &fresh_db] {
+            admin
+                .batch_execute(&format!("DROP DATABASE {} WITH (FORCE)", db))
+                .await
+                .unwrap();
+        }
+    }
+}
@@ -0,0 +1,293 @@
+use std::{collections::HashMap, env, fs};
+
+use serde::Deserialize;
+
+use crate::game::Reviewer;
+
+/// How the note of each source is read
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Average {
+    /// Average of the votes, as given by the source
+    Raw,
+    /// Average pulled towards `prior_note` as if `prior_votes` more votes were
+    /// given, a few enthusiastic votes do not make a great game
+    #[default]
+    Bayesian,
+}
+
+/// Share of a source in the aggregated note
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct SourcePolicy {
+    pub weight: f32,
+    /// The source is ignored below this number of votes
+    pub min_votes: u32,
+}
+
+impl Default for SourcePolicy {
+    fn default() -> Self {
+        SourcePolicy {
+            weight: 1.0,
+            min_votes: 1,
+        }
+    }
+}
+
+/// How the notes of the reviewers are aggregated. The sources are averaged
+/// with their weight whatever their number of votes, so that bgg does not
+/// drown the french sites.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RatingPolicy {
+    pub average: Average,
+    /// Note of a game nobody voted for, out of 10
+    pub prior_note: f32,
+    /// Weight of `prior_note` in a bayesian average, also the number of votes
+    /// of a source giving a confidence of 0.5
+    pub prior_votes: u32,
+    pub sources: HashMap<String, SourcePolicy>,
+    /// Policy of the sources missing from `sources`
+    pub default_source: SourcePolicy,
+}
+
+impl Default for RatingPolicy {
+    fn default() -> Self {
+        RatingPolicy {
+            average: Average::default(),
+            prior_note: 6.0,
+            prior_votes: 20,
+            sources: HashMap::new(),
+            default_source: SourcePolicy::default(),
+        }
+    }
+}
+
+impl RatingPolicy {
+    pub fn source(&self, name: &str) -> &SourcePolicy {
+        self.sources.get(name).unwrap_or(&self.default_source)
+    }
+
+    /// Note of a source with `votes` votes averaging `note`
+    pub fn source_note(&self, note: f32, votes: u32) -> f32 {
+        match self.average {
+            Average::Raw => note,
+            Average::Bayesian => {
+                let prior = self.prior_votes as f32;
+                (prior * self.prior_note + votes as f32 * note) / (prior + votes as f32)
+            }
+        }
+    }
+
+    /// Aggregated note out of 10 and its confidence between 0 and 1, (0, 0)
+    /// when no source has enough votes. Confidence grows with the votes of
+    /// each source and with the number of sources.
+    pub fn aggregate(&self, reviews: &HashMap<String, Reviewer>) -> (f32, f32) {
+        let mut weighted_notes = 0.0;
+        let mut weights = 0.0;
+        let mut doubt = 1.0;
+        for (name, reviewer) in reviews {
+            let source = self.source(name);
+            if reviewer.number == 0 || reviewer.number < source.min_votes || source.weight <= 0.0 {
+                continue;
+            }
+            weighted_notes += source.weight * self.source_note(reviewer.note, reviewer.number);
+            weights += source.weight;
+            let votes = reviewer.number as f32;
+            doubt *= self.prior_votes as f32 / (self.prior_votes as f32 + votes);
+        }
+        if weights == 0.0 {
+            return (0.0, 0.0);
+        }
+        (weighted_notes / weights, 1.0 - doubt)
+    }
+
+    /// SQL expression of the aggregated note of the reviewer rows `r` of an
+    /// announce, NULL when no source has enough votes
+    pub fn sql_average(&self) -> String {
+        let included = |min_votes: u32, weight: f32| {
+            format!(
+                "CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= {} THEN {} ELSE 0 END",
+                min_votes, weight
+            )
+        };
+        let mut sources: Vec<(&String, &SourcePolicy)> = self.sources.iter().collect();
+        sources.sort_by_key(|(name, _)| name.as_str());
+        let default_weight = included(self.default_source.min_votes, self.default_source.weight);
+        // a CASE needs at least one WHEN
+        let weight = if sources.is_empty() {
+            default_weight
+        } else {
+            format!(
+                "CASE {} ELSE {} END",
+                sources
+                    .iter()
+                    .map(|(name, source)| format!(
+                        "WHEN r.reviewer_name = '{}' THEN {}",
+                        name.replace('\'', "''"),
+                        included(source.min_votes, source.weight)
+                    ))
+                    .collect::<Vec<_>>()
+                    .join(" "),
+                default_weight
+            )
+        };
+        let note = match self.average {
+            Average::Raw => "r.reviewer_note".to_string(),
+            Average::Bayesian => format!(
+                "({} * {} + r.reviewer_number * r.reviewer_note) / NULLIF({} + r.reviewer_number, 0)",
+                self.prior_votes, self.prior_note, self.prior_votes
+            ),
+        };
+        format!("SUM(({}) * {}) / NULLIF(SUM({}), 0)", weight, note, weight)
+    }
+}
+
+fn load_rating_policy() -> RatingPolicy {
+    let filename = env::var("RATING_POLICY_FILE").unwrap_or("rating.json".to_string());
+    match fs::read_to_string(&filename)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse_rating_policy(&content).map_err(|e| e.to_string()))
+    {
+        Ok(policy) => {
+            log::debug!("loaded rating policy from {}", filename);
+            policy
+        }
+        Err(e) => {
+            log::error!(
+                "Cannot read rating policy from {}, fallback to default: {}",
+                filename,
+                e
+            );
+            RatingPolicy::default()
+        }
+    }
+}
+
+fn parse_rating_policy(content: &str) -> Result<RatingPolicy, serde_json::Error> {
+    serde_json::from_str(content)
+}
+
+use lazy_static::lazy_static;
+lazy_static! {
+    pub static ref RATING_POLICY: RatingPolicy = load_rating_policy();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, fs};
+
+    use super::{parse_rating_policy, Average, RatingPolicy, SourcePolicy};
+    use crate::game::Reviewer;
+
+    fn reviews(notes: &[(&str, f32, u32)]) -> HashMap<String, Reviewer> {
+        notes
+            .iter()
+            .map(|(name, note, number)| {
+                (
+                    name.to_string(),
+                    Reviewer {
+                        name: name.to_string(),
+                        note: *note,
+                        number: *number,
+                        ..Default::default()
+                    },
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_policy() {
+        let content =
+            fs::read_to_string("rating.json").expect("Should have been able to read the file");
+        let policy = parse_rating_policy(&content).unwrap();
+        assert_eq!(policy.average, Average::Bayesian);
+        assert!(policy.sources.contains_key("bgg"));
+        assert!(policy.sources.contains_key("trictrac"));
+
+        let policy = parse_rating_policy(r#"{"average": "raw"}"#).unwrap();
+        assert_eq!(policy.average, Average::Raw);
+        assert_eq!(policy.default_source, SourcePolicy::default());
+    }
+
+    #[test]
+    fn test_aggregate() {
+        let raw = RatingPolicy {
+            average: Average::Raw,
+            ..Default::default()
+        };
+        // bgg thousands of votes do not drown trictrac
+        let (note, confidence) =
+            raw.aggregate(&reviews(&[("bgg", 8.0, 20000), ("trictrac", 6.0, 40)]));
+        assert!((note - 7.0).abs() < 0.001);
+        assert!(confidence > 0.99);
+
+        let weighted = RatingPolicy {
+            average: Average::Raw,
+            sources: HashMap::from([
+                (
+                    "bgg".to_string(),
+                    SourcePolicy {
+                        weight: 3.0,
+                        min_votes: 1,
+                    },
+                ),
+                (
+                    "trictrac".to_string(),
+                    SourcePolicy {
+                        weight: 1.0,
+                        min_votes: 50,
+                    },
+                ),
+            ]),
+            ..Default::default()
+        };
+        let (note, _) = weighted.aggregate(&reviews(&[("bgg", 8.0, 100), ("trictrac", 4.0, 100)]));
+        assert!((note - 7.0).abs() < 0.001);
+        // not enough votes on trictrac
+        let (note, _) = weighted.aggregate(&reviews(&[("bgg", 8.0, 100), ("trictrac", 4.0, 10)]));
+        assert!((note - 8.0).abs() < 0.001);
+
+        // a few votes are pulled towards the prior
+        let bayesian = RatingPolicy::default();
+        let (note, confidence) = bayesian.aggregate(&reviews(&[("trictrac", 10.0, 5)]));
+        assert!((note - 6.8).abs() < 0.001);
+        assert!((confidence - 0.2).abs() < 0.001);
+
+        assert_eq!(bayesian.aggregate(&reviews(&[("bgg", 7.0, 0)])), (0.0, 0.0));
+        assert_eq!(bayesian.aggregate(&HashMap::new()), (0.0, 0.0));
+    }
+
+    #[test]
+    fn test_sql_average() {
+        let policy = RatingPolicy {
+            average: Average::Raw,
+            sources: HashMap::from([(
+                "o'k".to_string(),
+                SourcePolicy {
+                    weight: 0.5,
+                    min_votes: 3,
+                },
+            )]),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.sql_average(),
+            "SUM((CASE WHEN r.reviewer_name = 'o''k' THEN CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 3 THEN 0.5 ELSE 0 END ELSE CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 1 THEN 1 ELSE 0 END END) * r.reviewer_note) / NULLIF(SUM(CASE WHEN r.reviewer_name = 'o''k' THEN CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 3 THEN 0.5 ELSE 0 END ELSE CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 1 THEN 1 ELSE 0 END END), 0)"
+        );
+    }
+
+    #[test]
+    fn test_sql_average_without_sources() {
+        let policy = RatingPolicy {
+            average: Average::Raw,
+            sources: HashMap::new(),
+            ..Default::default()
+        };
+        assert_eq!(
+            policy.sql_average(),
+            "SUM((CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 1 THEN 1 ELSE 0 END) * r.reviewer_note) / NULLIF(SUM(CASE WHEN r.reviewer_number > 0 AND r.reviewer_number >= 1 THEN 1 ELSE 0 END), 0)"
+        );
+    }
+}
@@ -1,12 +1,197 @@
+use anyhow::bail;
+use regex::Regex;
 use scraper::{Html, Selector};
+use serde::Deserialize;
 
 use crate::{
-    game::Reviewer,
+    game::{BggInfos, GameKind, Reviewer},
     httpclient,
-    website::helper::{are_names_similar, clean_name},
+    website::helper::{are_names_similar, clean_name, match_game},
 };
 
-pub async fn get_bgg_note(name: &str) -> Result<Option<Reviewer>, anyhow::Error> {
+const BGG_API: &str = "https://boardgamegeek.com/xmlapi2";
+
+/// Find the game on the bgg XML API: its description and its rating.
+/// The search is skipped when an admin pinned the bgg id of the game.
+pub async fn get_bgg_infos(
+    name: &str,
+    kind: GameKind,
+    pinned_id: Option<u32>,
+) -> Result<Option<(BggInfos, Option<Reviewer>)>, anyhow::Error> {
+    if let Some(id) = pinned_id {
+        return get_bgg_thing(id).await;
+    }
+
+    let name = clean_name(name);
+    // bgg lists expansions apart from the games
+    let bgg_type = match kind {
+        GameKind::Expansion => "boardgameexpansion",
+        _ => "boardgame",
+    };
+    let search = format!("{}/search?query={}&type={}", BGG_API, name, bgg_type);
+    log::debug!("searching bgg api: {}", &name);
+    let id = match parse_bgg_search(&name, kind, &get_bgg_xml(&search).await?)? {
+        Some(id) => id,
+        None => {
+            BGG_API_STAT.with_label_values(&["not_found"]).inc();
+            return Ok(None);
+        }
+    };
+
+    get_bgg_thing(id).await
+}
+
+async fn get_bgg_thing(id: u32) -> Result<Option<(BggInfos, Option<Reviewer>)>, anyhow::Error> {
+    let thing = format!("{}/thing?id={}&stats=1", BGG_API, id);
+    let infos = parse_bgg_thing(&get_bgg_xml(&thing).await?)?;
+    BGG_API_STAT
+        .with_label_values(&[if infos.is_some() {
+            "success"
+        } else {
+            "not_found"
+        }])
+        .inc();
+    Ok(infos)
+}
+
+async fn get_bgg_xml(url: &str) -> Result<String, anyhow::Error> {
+    let response = httpclient::get_with_bearer(url, BGG_API_TOKEN.as_deref()).await?;
+    let status = response.status();
+    if !status.is_success() {
+        BGG_API_STAT.with_label_values(&["fail"]).inc();
+        bail!("bgg api answered {} to {}", status, url);
+    }
+    Ok(response.text().await?)
+}
+
+#[derive(Debug, Deserialize)]
+struct XmlItems {
+    #[serde(rename = "item", default)]
+    items: Vec<XmlItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct XmlItem {
+    #[serde(rename = "@id")]
+    id: u32,
+    #[serde(rename = "name", default)]
+    names: Vec<XmlValue>,
+    yearpublished: Option<XmlValue>,
+    minplayers: Option<XmlValue>,
+    maxplayers: Option<XmlValue>,
+    playingtime: Option<XmlValue>,
+    minage: Option<XmlValue>,
+    #[serde(rename = "link", default)]
+    links: Vec<XmlLink>,
+    statistics: Option<XmlStatistics>,
+}
+
+/// `<minplayers value="2" />`, bgg gives 0 or nothing when it does not know
+#[derive(Debug, Deserialize)]
+struct XmlValue {
+    #[serde(rename = "@value")]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct XmlLink {
+    #[serde(rename = "@type")]
+    kind: String,
+    #[serde(rename = "@value")]
+    value: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct XmlStatistics {
+    ratings: XmlRatings,
+}
+
+#[derive(Debug, Deserialize)]
+struct XmlRatings {
+    usersrated: XmlValue,
+    average: XmlValue,
+    averageweight: Option<XmlValue>,
+}
+
+/// Read a known value, bgg fills unknown ones with 0
+fn known<T: std::str::FromStr + Default + PartialEq>(value: &Option<XmlValue>) -> Option<T> {
+    value
+        .as_ref()
+        .and_then(|v| v.value.trim().parse::<T>().ok())
+        .filter(|v| *v != T::default())
+}
+
+/// Id of the best matching search result, the first one on a tie.
+/// A game appears once for each of its names matching the search.
+fn parse_bgg_search(name: &str, kind: GameKind, xml: &str) -> Result<Option<u32>, anyhow::Error> {
+    let items: XmlItems = quick_xml::de::from_str(xml)?;
+    let mut best: Option<(f32, u32)> = None;
+    for item in items.items {
+        for item_name in item.names {
+            let name_match = match_game(name, kind, &item_name.value);
+            let score = name_match.score;
+            log::trace!("bgg search {} vs {} : {}", name, item_name.value, score);
+            if name_match.is_match() && best.is_none_or(|(s, _)| score > s) {
+                best = Some((score, item.id));
+            }
+        }
+    }
+    Ok(best.map(|(_, id)| id))
+}
+
+fn parse_bgg_thing(xml: &str) -> Result<Option<(BggInfos, Option<Reviewer>)>, anyhow::Error> {
+    let items: XmlItems = quick_xml::de::from_str(xml)?;
+    let item = match items.items.into_iter().next() {
+        Some(item) => item,
+        None => return Ok(None),
+    };
+
+    let links = |kind: &str| -> Vec<String> {
+        item.links
+            .iter()
+            .filter(|l| l.kind == kind)
+            .map(|l| l.value.clone())
+            .collect()
+    };
+    let infos = BggInfos {
+        id: item.id,
+        year: known(&item.yearpublished),
+        min_players: known(&item.minplayers),
+        max_players: known(&item.maxplayers),
+        playing_time: known(&item.playingtime),
+        min_age: known(&item.minage),
+        weight: item
+            .statistics
+            .as_ref()
+            .and_then(|s| known(&s.ratings.averageweight)),
+        categories: links("boardgamecategory"),
+        mechanics: links("boardgamemechanic"),
+    };
+
+    let reviewer = item.statistics.as_ref().and_then(|s| {
+        let number = s.ratings.usersrated.value.parse::<u32>().ok()?;
+        let note = s.ratings.average.value.parse::<f32>().ok()?;
+        (number > 0).then(|| Reviewer {
+            name: "bgg".to_string(),
+            url: format!("https://boardgamegeek.com/boardgame/{}", item.id),
+            note,
+            number,
+        })
+    });
+    Ok(Some((infos, reviewer)))
+}
+
+/// Read the bgg id of a game page like https://boardgamegeek.com/boardgame/230802/azul
+pub fn bgg_id_from_url(url: &str) -> Option<u32> {
+    let re = Regex::new(r"/boardgame(?:expansion)?/(\d+)").unwrap();
+    re.captures(url)?[1].parse().ok()
+}
+
+/// Rating of the first search result, or of the game pinned by an admin
+pub async fn get_bgg_note(
+    name: &str,
+    pinned_id: Option<u32>,
+) -> Result<Option<Reviewer>, anyhow::Error> {
     let name = clean_name(name);
     let search = format!(
         "https://boardgamegeek.com/geeksearch.php?action=search&objecttype=boardgame&q={}",
@@ -14,25 +199,43 @@ pub async fn get_bgg_note(name: &str) -> Result<Option<Reviewer>, anyhow::Error>
     );
     log::debug!("getting bgg note: {}\n", &name);
     let (doc, _) = httpclient::get_doc(&search).await?;
-    Ok(parse_bgg_document(&name, search, &doc))
+    Ok(parse_bgg_document(&name, pinned_id, search, &doc))
 }
 
-fn parse_bgg_document(name: &str, search: String, document: &Html) -> Option<Reviewer> {
+fn parse_bgg_document(
+    name: &str,
+    pinned_id: Option<u32>,
+    search: String,
+    document: &Html,
+) -> Option<Reviewer> {
+    let row_selector = Selector::parse(r#"tr[id="row_"]"#).unwrap();
     let primary_selector = Selector::parse("a.primary").unwrap();
 
     // Sélecteur pour les éléments avec la classe 'collection_bggrating'
     let bggrating_selector = Selector::parse("td.collection_bggrating").unwrap();
 
-    let selected_name = if let Some(primary) = document.select(&primary_selector).next() {
-        primary.text().collect::<Vec<_>>().join("")
-    } else {
-        BGG_STAT.with_label_values(&["fail"]).inc();
-        return None;
-    };
+    let row = document.select(&row_selector).find(|row| match pinned_id {
+        None => true,
+        Some(id) => {
+            row.select(&primary_selector)
+                .next()
+                .and_then(|primary| primary.value().attr("href"))
+                .and_then(bgg_id_from_url)
+                == Some(id)
+        }
+    });
+    let (row, selected_name) =
+        match row.and_then(|row| Some((row, row.select(&primary_selector).next()?))) {
+            Some((row, primary)) => (row, primary.text().collect::<Vec<_>>().join("")),
+            None => {
+                BGG_STAT.with_label_values(&["fail"]).inc();
+                return None;
+            }
+        };
     log::trace!("selected_name: {} vs name {}", selected_name, name);
 
     let mut bggrating_values = Vec::new();
-    for bggrating in document.select(&bggrating_selector).skip(1).take(2) {
+    for bggrating in row.select(&bggrating_selector).skip(1).take(2) {
         let bggrating_value = bggrating.text().collect::<Vec<_>>().join("");
         let ratings = bggrating_value.trim();
 
@@ -40,7 +243,9 @@ fn parse_bgg_document(name: &str, search: String, document: &Html) -> Option<Rev
     }
     log::trace!("bggrating_values: {:#?}", bggrating_values);
 
-    if bggrating_values.len() == 2 && are_names_similar(name, &selected_name) {
+    if bggrating_values.len() == 2
+        && (pinned_id.is_some() || are_names_similar(name, &selected_name))
+    {
         let rating = bggrating_values[0].clone().parse::<f32>().unwrap_or(0.0);
         let review_cnt = bggrating_values[1].clone().parse::<u32>().unwrap_or(0);
         BGG_STAT.with_label_values(&["success"]).inc();
@@ -59,6 +264,14 @@ fn parse_bgg_document(name: &str, search: String, document: &Html) -> Option<Rev
 use lazy_static::lazy_static;
 use prometheus::{register_int_counter_vec, IntCounterVec};
 lazy_static! {
+    /// Token of the registered application, required by the bgg XML API
+    static ref BGG_API_TOKEN: Option<String> = std::env::var("BGG_API_TOKEN").ok();
+    static ref BGG_API_STAT: IntCounterVec = register_int_counter_vec!(
+        "bgg_api_stat",
+        "Stat about bgg XML API lookups (success, not_found, fail)",
+        &["result"]
+    )
+    .unwrap();
     static ref BGG_STAT: IntCounterVec = register_int_counter_vec!(
         "bgg_stat",
         "Stat about parsing/fetch success/fail for this website",
@@ -72,7 +285,11 @@ mod tests {
     use log::Level;
     use std::{env, fs};
 
-    use crate::website::{bgg::parse_bgg_document, helper::clean_name};
+    use crate::game::{BggInfos, GameKind};
+    use crate::website::{
+        bgg::{bgg_id_from_url, parse_bgg_document, parse_bgg_search, parse_bgg_thing},
+        helper::clean_name,
+    };
 
     struct Test {
         name: String,
@@ -133,9 +350,88 @@ mod tests {
             let html_doc =
                 fs::read_to_string(test.document).expect("Should have been able to read the file");
             let document = scraper::Html::parse_document(&html_doc);
-            let review = parse_bgg_document(&name, String::new(), &document).unwrap();
+            let review = parse_bgg_document(&name, None, String::new(), &document).unwrap();
             assert_eq!(review.note, test.note);
             assert_eq!(review.number, test.review_cnt);
         }
     }
+
+    #[test]
+    fn test_pinned() {
+        assert_eq!(
+            bgg_id_from_url("https://boardgamegeek.com/boardgame/21523/runebound-second-edition"),
+            Some(21523)
+        );
+        assert_eq!(bgg_id_from_url("https://boardgamegeek.com/"), None);
+
+        let html_doc = fs::read_to_string("tests/bgg/test6.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&html_doc);
+        // the second result, whatever its name
+        let review =
+            parse_bgg_document("Runebound", Some(21523), String::new(), &document).unwrap();
+        assert_eq!(review.note, 6.89);
+        assert_eq!(review.number, 8037);
+        assert!(parse_bgg_document("Runebound", Some(1), String::new(), &document).is_none());
+    }
+
+    #[test]
+    fn test_api_search() {
+        let tests = vec![
+            ("Azul", "tests/bgg/search1.xml", Some(230802)),
+            // found by its alternate name
+            ("Super Rhino", "tests/bgg/search3.xml", Some(91514)),
+            ("Azul", "tests/bgg/search2.xml", None),
+            ("Dixit", "tests/bgg/search1.xml", None),
+        ];
+        for (name, document, result) in tests.into_iter() {
+            let xml = fs::read_to_string(document).expect("Should have been able to read the file");
+            assert_eq!(
+                parse_bgg_search(&clean_name(name), GameKind::Base, &xml).unwrap(),
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn test_api_thing() {
+        let xml = fs::read_to_string("tests/bgg/thing1.xml")
+            .expect("Should have been able to read the file");
+        let (infos, reviewer) = parse_bgg_thing(&xml).unwrap().unwrap();
+        assert_eq!(
+            infos,
+            BggInfos {
+                id: 230802,
+                year: Some(2017),
+                min_players: Some(2),
+                max_players: Some(4),
+                playing_time: Some(45),
+                min_age: Some(8),
+                weight: Some(1.7632),
+                categories: vec!["Abstract Strategy".to_string(), "Renaissance".to_string()],
+                mechanics: vec![
+                    "Open Drafting".to_string(),
+                    "Pattern Building".to_string(),
+                    "Tile Placement".to_string()
+                ],
+            }
+        );
+        let reviewer = reviewer.unwrap();
+        assert_eq!(reviewer.note, 7.72845);
+        assert_eq!(reviewer.number, 119807);
+        assert_eq!(reviewer.url, "https://boardgamegeek.com/boardgame/230802");
+
+        // a game nobody described nor rated yet
+        let xml = fs::read_to_string("tests/bgg/thing2.xml")
+            .expect("Should have been able to read the file");
+        let (infos, reviewer) = parse_bgg_thing(&xml).unwrap().unwrap();
+        assert_eq!(
+            infos,
+            BggInfos {
+                id: 432850,
+                ..Default::default()
+            }
+        );
+        assert!(reviewer.is_none());
+    }
 }
@@ -0,0 +1,270 @@
+use async_trait::async_trait;
+use scraper::{Html, Selector};
+
+use crate::{
+    game::Reference,
+    gtin::Gtin,
+    httpclient,
+    website::{
+        health::check_page_structure,
+        helper::{
+            best_match, clean_name, parse_availability, parse_date, parse_price, split_prices,
+        },
+        Reseller, SearchedGame, StandardResellerTrait,
+    },
+};
+
+pub struct Espritjeu;
+
+/// Present on every search page, with or without result
+const ESPRITJEU_CONTAINER: &str = "#liste_produits";
+
+#[async_trait]
+impl StandardResellerTrait for Espritjeu {
+    fn name(&self) -> &str {
+        Reseller::Espritjeu.name()
+    }
+
+    async fn get_references_by_barcode(
+        &self,
+        barcode: Gtin,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let search = format!(
+            "https://www.espritjeu.com/dhtml/resultat_recherche.php?keywords={}",
+            barcode
+        );
+        log::debug!("search on espritjeu by barcode: {}", barcode);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, ESPRITJEU_CONTAINER)?;
+
+        Ok(parse_espritjeu_document_by_barcode(&document)
+            .into_iter()
+            .collect())
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let name = game.name.as_str();
+        let search = format!(
+            "https://www.espritjeu.com/dhtml/resultat_recherche.php?keywords={}",
+            clean_name(name)
+        );
+        log::debug!("search on espritjeu by name: {}", name);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, ESPRITJEU_CONTAINER)?;
+
+        Ok(self.parse_document(game, &document))
+    }
+
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference> {
+        parse_espritjeu_document(game, document)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Returns the title and the reference of every product of the result list
+fn parse_espritjeu_products(document: &Html) -> Vec<(String, Reference)> {
+    let product_selector = Selector::parse("#liste_produits .produit").unwrap();
+    let title_selector = Selector::parse("a.titre_produit").unwrap();
+    let price_selector = Selector::parse(".prix_produit .prix").unwrap();
+    let regular_price_selector = Selector::parse(".prix_produit .prix_barre").unwrap();
+    let promo_end_selector = Selector::parse(".prix_produit .fin_promo").unwrap();
+    let availability_selector = Selector::parse(".dispo").unwrap();
+
+    let mut products = vec![];
+    for product in document.select(&product_selector) {
+        let title = match product.select(&title_selector).next() {
+            Some(t) => t,
+            None => {
+                log::trace!("fail to select title");
+                continue;
+            }
+        };
+        let href = title.value().attr("href").unwrap_or_default();
+        let title_text = title.text().collect::<String>();
+
+        let price = match product
+            .select(&price_selector)
+            .next()
+            .and_then(|p| parse_price(&p.text().collect::<String>()))
+        {
+            Some(p) => p,
+            None => {
+                log::trace!("fail to select price for {}", title_text.trim());
+                continue;
+            }
+        };
+
+        // the crossed-out list price is only shown during a sale
+        let regular_price = product
+            .select(&regular_price_selector)
+            .next()
+            .and_then(|p| parse_price(&p.text().collect::<String>()));
+        let (price, promo_price) = split_prices(regular_price, price);
+        let promo_end = product
+            .select(&promo_end_selector)
+            .next()
+            .and_then(|p| parse_date(&p.text().collect::<String>()));
+
+        let available = product
+            .select(&availability_selector)
+            .next()
+            .and_then(|a| parse_availability(&a.text().collect::<String>()));
+
+        products.push((
+            title_text.trim().to_string(),
+            Reference {
+                promo_price,
+                promo_end,
+                available,
+                ..Reseller::Espritjeu.reference(price, href.to_string())
+            },
+        ));
+    }
+    products
+}
+
+/// Espritjeu does not show barcodes in the result list, a barcode search is
+/// only trusted when it gives a single product
+fn parse_espritjeu_document_by_barcode(document: &Html) -> Option<Reference> {
+    let mut products = parse_espritjeu_products(document);
+    if products.len() == 1 {
+        // the single product may be another game, no alias is learned from its title
+        let (_, reference) = products.remove(0);
+        ESPRITJEU_STAT.with_label_values(&["success"]).inc();
+        return Some(reference);
+    }
+    ESPRITJEU_STAT.with_label_values(&["fail"]).inc();
+    None
+}
+
+fn parse_espritjeu_document(game: &SearchedGame, document: &Html) -> Option<Reference> {
+    log::trace!("parsing espritjeu document for {}", game.name);
+    if let Some(reference) = best_match(game, parse_espritjeu_products(document)) {
+        ESPRITJEU_STAT.with_label_values(&["success"]).inc();
+        return Some(reference);
+    }
+    ESPRITJEU_STAT.with_label_values(&["fail"]).inc();
+    None
+}
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    static ref ESPRITJEU_STAT: IntCounterVec = register_int_counter_vec!(
+        "espritjeu_stat",
+        "Stat about parsing/fetch success/fail for this website",
+        &["result"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_espritjeu_document, parse_espritjeu_document_by_barcode, ESPRITJEU_CONTAINER,
+    };
+    use crate::{
+        game::GameKind,
+        website::{health::check_page_structure, SearchedGame},
+    };
+    use chrono::NaiveDate;
+    use log::Level;
+    use reqwest::StatusCode;
+    use std::{env, fs};
+
+    struct Test {
+        name: String,
+        price: f32,
+        promo_price: Option<f32>,
+        promo_end: Option<NaiveDate>,
+        href: String,
+        available: Option<bool>,
+        document: String,
+    }
+
+    #[test]
+    fn test_parsing() {
+        env::set_var("RUST_LOG", "boardgame_finder=trace");
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or(Level::Info.as_str()),
+        )
+        .try_init();
+
+        let tests = vec![
+            Test {
+                price: 39.9,
+                promo_price: None,
+                promo_end: None,
+                href: "https://www.espritjeu.com/azul.html".to_string(),
+                name: "Azul".to_string(),
+                available: Some(true),
+                document: "tests/espritjeu/test1.html".to_string(),
+            },
+            Test {
+                price: 44.9,
+                promo_price: Some(29.9),
+                promo_end: NaiveDate::from_ymd_opt(2026, 10, 31),
+                href: "https://www.espritjeu.com/les-aventuriers-du-rail-europe.html".to_string(),
+                name: "Les Aventuriers du Rail - Europe".to_string(),
+                available: Some(true),
+                document: "tests/espritjeu/test2.html".to_string(),
+            },
+            Test {
+                price: 24.9,
+                promo_price: None,
+                promo_end: None,
+                href: "https://www.espritjeu.com/tiny-epic-western.html".to_string(),
+                name: "Tiny Epic Western".to_string(),
+                available: Some(false),
+                document: "tests/espritjeu/test3.html".to_string(),
+            },
+        ];
+        for test in tests.into_iter() {
+            let doc =
+                fs::read_to_string(test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(check_page_structure(
+                "espritjeu",
+                StatusCode::OK,
+                &document,
+                ESPRITJEU_CONTAINER
+            )
+            .is_ok());
+            if let Some(reference) =
+                parse_espritjeu_document(&SearchedGame::new(&test.name, GameKind::Base), &document)
+            {
+                assert_eq!(reference.price, test.price);
+                assert_eq!(reference.promo_price, test.promo_price);
+                assert_eq!(reference.promo_end, test.promo_end);
+                assert_eq!(reference.url, test.href);
+                assert_eq!(reference.available, test.available);
+            } else {
+                panic!("fail to parse");
+            }
+        }
+    }
+
+    #[test]
+    fn test_parsing_barcode() {
+        let doc = fs::read_to_string("tests/espritjeu/test4.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&doc);
+        assert_eq!(
+            parse_espritjeu_document_by_barcode(&document).map(|r| (r.price, r.url)),
+            Some((
+                44.9,
+                "https://www.espritjeu.com/les-chateaux-de-bourgogne.html".to_string()
+            ))
+        );
+
+        // several products for a barcode, nothing can be trusted
+        let doc = fs::read_to_string("tests/espritjeu/test1.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&doc);
+        assert!(parse_espritjeu_document_by_barcode(&document).is_none());
+    }
+}
@@ -0,0 +1,236 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use anyhow::anyhow;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, register_int_gauge_vec, IntCounterVec, IntGaugeVec};
+use reqwest::StatusCode;
+use scraper::{Html, Selector};
+
+/// A search page without the container the parser expects, the website
+/// probably changed its HTML. Returned through `anyhow::Error` by resellers.
+#[derive(Debug)]
+pub struct UnrecognisedPage {
+    pub reseller: String,
+    pub selector: String,
+}
+
+impl fmt::Display for UnrecognisedPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} page structure unrecognised, {} is missing",
+            self.reseller, self.selector
+        )
+    }
+}
+
+impl std::error::Error for UnrecognisedPage {}
+
+/// Fails when the website did not answer the page (blocked, rate limited,
+/// redirected...), or with `UnrecognisedPage` when `container`, present on
+/// every search page of the website even without result, cannot be found in
+/// the `document` it answered
+pub fn check_page_structure(
+    reseller: &str,
+    status: StatusCode,
+    document: &Html,
+    container: &str,
+) -> Result<(), anyhow::Error> {
+    if !status.is_success() {
+        return Err(anyhow!("{} answered {}", reseller, status));
+    }
+    let selector = Selector::parse(container).unwrap();
+    if document.select(&selector).next().is_none() {
+        return Err(UnrecognisedPage {
+            reseller: reseller.to_string(),
+            selector: container.to_string(),
+        }
+        .into());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+struct BreakerState {
+    structural_failures: u32,
+    disabled_until: Option<Instant>,
+}
+
+/// Disable a reseller for `cooldown` after `threshold` consecutive lookups
+/// on an unrecognised page. Once the cool-down is over the reseller is tried
+/// again, a single new structural failure disables it again.
+pub struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    states: Mutex<HashMap<String, BreakerState>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(threshold: u32, cooldown: Duration) -> CircuitBreaker {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            states: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// True while the reseller is disabled
+    pub fn is_open(&self, reseller: &str) -> bool {
+        let mut states = self.states.lock().unwrap();
+        match states.get_mut(reseller) {
+            Some(state) => match state.disabled_until {
+                Some(until) if until > Instant::now() => true,
+                Some(_) => {
+                    log::info!("{} cool-down is over, trying it again", reseller);
+                    state.disabled_until = None;
+                    RESELLER_DISABLED.with_label_values(&[reseller]).set(0);
+                    false
+                }
+                None => false,
+            },
+            None => false,
+        }
+    }
+
+    /// The page was recognised, with or without a match
+    pub fn record_success(&self, reseller: &str) {
+        let mut states = self.states.lock().unwrap();
+        if let Some(state) = states.remove(reseller) {
+            if state.structural_failures > 0 {
+                log::info!("{} page structure recognised again", reseller);
+            }
+        }
+    }
+
+    /// Returns true when this failure disables the reseller
+    pub fn record_structural_failure(&self, reseller: &str) -> bool {
+        let mut states = self.states.lock().unwrap();
+        let state = states.entry(reseller.to_string()).or_default();
+        state.structural_failures += 1;
+        if state.structural_failures < self.threshold {
+            return false;
+        }
+
+        log::error!(
+            "{} page structure unrecognised {} times in a row, disabling it for {:?}",
+            reseller,
+            state.structural_failures,
+            self.cooldown
+        );
+        state.disabled_until = Some(Instant::now() + self.cooldown);
+        RESELLER_DISABLED.with_label_values(&[reseller]).set(1);
+        RESELLER_TRIPS.with_label_values(&[reseller]).inc();
+        true
+    }
+}
+
+fn create_breaker() -> CircuitBreaker {
+    let threshold = std::env::var("RESELLER_BREAKER_THRESHOLD")
+        .map_err(|e| e.to_string())
+        .and_then(|v| v.parse::<u32>().map_err(|e| e.to_string()))
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Cannot initialize reseller breaker threshold from environment, fallback to default: {}",
+                err
+            );
+            DEFAULT_BREAKER_THRESHOLD
+        });
+    let cooldown = std::env::var("RESELLER_BREAKER_COOLDOWN_SECS")
+        .map_err(|e| e.to_string())
+        .and_then(|v| v.parse::<u64>().map_err(|e| e.to_string()))
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Cannot initialize reseller breaker cool-down from environment, fallback to default: {}",
+                err
+            );
+            DEFAULT_BREAKER_COOLDOWN_SECS
+        });
+    CircuitBreaker::new(threshold, Duration::from_secs(cooldown))
+}
+
+/// DEFAULT_BREAKER_THRESHOLD is the default number of structural failures disabling a reseller
+const DEFAULT_BREAKER_THRESHOLD: u32 = 5;
+/// DEFAULT_BREAKER_COOLDOWN_SECS is the default time a reseller stays disabled
+const DEFAULT_BREAKER_COOLDOWN_SECS: u64 = 3600;
+
+lazy_static! {
+    pub static ref RESELLER_BREAKER: CircuitBreaker = create_breaker();
+    static ref RESELLER_DISABLED: IntGaugeVec = register_int_gauge_vec!(
+        "reseller_disabled",
+        "1 while a reseller is disabled after too many unrecognised pages",
+        &["reseller"]
+    )
+    .unwrap();
+    static ref RESELLER_TRIPS: IntCounterVec = register_int_counter_vec!(
+        "reseller_disabled_total",
+        "Number of times a reseller has been disabled after too many unrecognised pages",
+        &["reseller"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use reqwest::StatusCode;
+    use scraper::Html;
+
+    use super::{check_page_structure, CircuitBreaker, UnrecognisedPage};
+
+    #[test]
+    fn test_check_page_structure() {
+        let document = Html::parse_document(r#"<div id="products"></div>"#);
+        assert!(check_page_structure("test", StatusCode::OK, &document, "#products").is_ok());
+        let error = check_page_structure("test", StatusCode::OK, &document, "#js-product-list")
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<UnrecognisedPage>().unwrap().selector,
+            "#js-product-list"
+        );
+    }
+
+    #[test]
+    fn test_check_page_structure_status() {
+        // block pages and redirections are not a change of the HTML
+        let block_page = Html::parse_document("<h1>Too many requests</h1>");
+        for status in [
+            StatusCode::TOO_MANY_REQUESTS,
+            StatusCode::FORBIDDEN,
+            StatusCode::FOUND,
+        ] {
+            let error = check_page_structure("test", status, &block_page, "#products").unwrap_err();
+            assert!(
+                error.downcast_ref::<UnrecognisedPage>().is_none(),
+                "{}",
+                status
+            );
+            assert!(error.to_string().contains(status.as_str()), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(2, Duration::from_millis(50));
+
+        assert!(!breaker.record_structural_failure("a"));
+        // a recognised page resets the count
+        breaker.record_success("a");
+        assert!(!breaker.record_structural_failure("a"));
+        assert!(!breaker.is_open("a"));
+        assert!(breaker.record_structural_failure("a"));
+        assert!(breaker.is_open("a"));
+        assert!(!breaker.is_open("b"));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(!breaker.is_open("a"));
+        // still broken after the cool-down
+        assert!(breaker.record_structural_failure("a"));
+        assert!(breaker.is_open("a"));
+    }
+}
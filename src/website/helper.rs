@@ -1,9 +1,15 @@
 use std::collections::HashSet;
 
+use chrono::NaiveDate;
 use regex::Regex;
 use unidecode::unidecode;
 
-static TOKENS_UNWANTED: [&str; 23] = [
+use crate::{
+    game::{GameKind, Reference},
+    website::SearchedGame,
+};
+
+pub static TOKENS_UNWANTED: [&str; 23] = [
     "vf",
     "vo",
     "edition",
@@ -39,50 +45,292 @@ pub fn clean_name(name: &str) -> String {
     name_cleaned
 }
 
-pub fn are_names_similar(name1: &str, name2: &str) -> bool {
-    log::trace!("name1 : {}, name2 : {}", name1, name2);
-    let name1_clean = clean_name(name1);
-    let name2_clean = clean_name(name2);
-    log::trace!(
-        "name1_clean : {}, name2_clean : {}",
-        name1_clean,
-        name2_clean
-    );
-
-    let words_name1: HashSet<String> = name1_clean
-        .split_whitespace()
-        .map(|word| unidecode(word).to_lowercase())
+/// Parse a french formatted price like "1 039,90 €"
+pub fn parse_price(text: &str) -> Option<f32> {
+    let price: String = text
+        .chars()
+        .filter(|c| c.is_ascii_digit() || *c == ',' || *c == '.')
         .collect();
-    let words_name2: HashSet<String> = name2_clean
+    price.replace(',', ".").parse::<f32>().ok()
+}
+
+/// Returns the list price and the sale price from the crossed-out price
+/// (if shown) and the price asked
+pub fn split_prices(regular: Option<f32>, current: f32) -> (f32, Option<f32>) {
+    match regular {
+        Some(regular) if regular > current => (regular, Some(current)),
+        _ => (current, None),
+    }
+}
+
+/// Read the first french formatted date like "jusqu'au 31/10/2026"
+pub fn parse_date(text: &str) -> Option<NaiveDate> {
+    let re = Regex::new(r"(\d{1,2})/(\d{1,2})/(\d{4})").unwrap();
+    let captures = re.captures(text)?;
+    NaiveDate::from_ymd_opt(
+        captures[3].parse().ok()?,
+        captures[2].parse().ok()?,
+        captures[1].parse().ok()?,
+    )
+}
+
+/// Read a stock marker like "En stock" or "Rupture de stock", None if unknown
+pub fn parse_availability(text: &str) -> Option<bool> {
+    let text = unidecode(text).to_lowercase();
+    let unavailable = [
+        "rupture",
+        "hors-stock",
+        "hors stock",
+        "indisponible",
+        "epuise",
+    ];
+    if unavailable.iter().any(|marker| text.contains(marker)) {
+        return Some(false);
+    }
+    if text.contains("en stock") || text.contains("disponible") {
+        return Some(true);
+    }
+    None
+}
+
+/// Words telling the product is an expansion rather than a standalone game
+static EXTENSION_KEYWORDS: [&str; 4] = ["extension", "extensions", "expansion", "expansions"];
+
+/// Phrases of a title telling it is the standalone game, compared once
+/// lowercased and stripped of accents and punctuation
+static BASE_MARKERS: [&str; 5] = [
+    "jeu de base",
+    "boite de base",
+    "base game",
+    "core set",
+    "core box",
+];
+
+/// Lowest score for a product title to be accepted as the searched game
+pub const MIN_MATCH_SCORE: f32 = 0.8;
+/// Penalty of a meaningful word found in only one of the names, one word is
+/// enough to tell two games of a series apart so it costs more than the
+/// margin left by `MIN_MATCH_SCORE`
+const TOKEN_MISMATCH_PENALTY: f32 = 0.25;
+/// Penalty of a meaningless word (edition, vf...) found in only one of the
+/// names, the exact title is preferred to a title with packaging words
+const UNWANTED_TOKEN_PENALTY: f32 = 0.05;
+/// Penalty of a number found in only one of the names (edition, volume...)
+const NUMBER_MISMATCH_PENALTY: f32 = 0.3;
+/// Penalty of an expansion keyword found in only one of the names
+const EXTENSION_MISMATCH_PENALTY: f32 = 0.3;
+
+/// Why a product title differs from the searched name
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MatchReason {
+    /// Word of the searched name absent from the title
+    MissingToken(String),
+    /// Word of the title absent from the searched name
+    ExtraToken(String),
+    /// Number found in only one of the names
+    NumberMismatch(String),
+    /// Expansion keyword found in only one of the names
+    ExtensionKeyword(String),
+    /// The title sells another kind of product than the searched one
+    KindMismatch(GameKind),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct NameMatch {
+    /// 1.0 when the names only differ by meaningless words, 0.0 when nothing matches
+    pub score: f32,
+    pub reasons: Vec<MatchReason>,
+}
+
+impl NameMatch {
+    /// The title names the searched game
+    pub fn is_match(&self) -> bool {
+        self.score >= MIN_MATCH_SCORE
+    }
+}
+
+/// Lowercased words of a name, without accents nor surrounding punctuation
+fn words(name: &str) -> Vec<String> {
+    clean_name(name)
         .split_whitespace()
-        .map(|word| unidecode(word).to_lowercase())
-        .collect();
-    log::trace!(
-        "words_name1 : {:#?}, words_name2 : {:#?}",
-        words_name1,
-        words_name2
-    );
-
-    let difference = words_name1.symmetric_difference(&words_name2);
-    log::trace!("difference raw: {:#?}", difference);
-
-    let difference_tokens_unwanted: Vec<String> = difference
-        .map(|word| unidecode(word).to_lowercase())
-        .filter(|word| !TOKENS_UNWANTED.contains(&word.as_str()))
-        .collect();
+        .map(|word| {
+            unidecode(word)
+                .to_lowercase()
+                .trim_matches(|c: char| !c.is_alphanumeric())
+                .to_string()
+        })
+        .filter(|word| !word.is_empty())
+        .collect()
+}
+
+fn name_words(name: &str) -> HashSet<String> {
+    words(name).into_iter().collect()
+}
+
+/// Tell from its markers if a title is an expansion, a base game or both
+pub fn title_kind(title: &str) -> GameKind {
+    let words = words(title);
+    let phrase = format!(" {} ", words.join(" "));
+    let expansion = words
+        .iter()
+        .any(|word| EXTENSION_KEYWORDS.contains(&word.as_str()));
+    let base = BASE_MARKERS
+        .iter()
+        .any(|marker| phrase.contains(&format!(" {} ", marker)));
+
+    match (base || title.contains('+'), expansion) {
+        (true, true) => GameKind::BaseWithExpansion,
+        (false, true) => GameKind::Expansion,
+        _ if base => GameKind::Base,
+        _ => GameKind::Unknown,
+    }
+}
 
-    if !difference_tokens_unwanted.is_empty() {
-        for word in difference_tokens_unwanted {
-            log::trace!("Word not in tokens_unwanted: {}", word);
+/// Score how likely the product `title` is the game `searched`: jaccard index
+/// of their meaningful words, lowered by every word, number and expansion
+/// keyword found on one side only
+pub fn match_names(searched: &str, title: &str) -> NameMatch {
+    score_names(searched, title, true)
+}
+
+/// Score how likely the product `title` is the game `searched` sold as `kind`.
+/// Titles of another kind are rejected. When the kind of the announce is
+/// known, an expansion keyword missing from one of the names is not penalised.
+pub fn match_game(searched: &str, kind: GameKind, title: &str) -> NameMatch {
+    let searched_kind = match kind {
+        GameKind::Unknown => title_kind(searched),
+        kind => kind,
+    };
+    let found_kind = title_kind(title);
+    if !searched_kind.accepts(found_kind) {
+        log::trace!(
+            "{} is a {:?}, {} is a {:?}",
+            title,
+            found_kind,
+            searched,
+            searched_kind
+        );
+        return NameMatch {
+            score: 0.0,
+            reasons: vec![MatchReason::KindMismatch(found_kind)],
+        };
+    }
+    score_names(searched, title, kind == GameKind::Unknown)
+}
+
+fn score_names(searched: &str, title: &str, penalise_extension: bool) -> NameMatch {
+    let searched_words = name_words(searched);
+    let title_words = name_words(title);
+    let mut reasons = vec![];
+    let mut penalty = 0.0;
+
+    for word in searched_words.symmetric_difference(&title_words) {
+        let in_searched = searched_words.contains(word);
+        if EXTENSION_KEYWORDS.contains(&word.as_str()) {
+            if penalise_extension {
+                penalty += EXTENSION_MISMATCH_PENALTY;
+                reasons.push(MatchReason::ExtensionKeyword(word.clone()));
+            }
+        } else if word.chars().any(|c| c.is_ascii_digit()) {
+            penalty += NUMBER_MISMATCH_PENALTY;
+            reasons.push(MatchReason::NumberMismatch(word.clone()));
+        } else if TOKENS_UNWANTED.contains(&word.as_str()) {
+            penalty += UNWANTED_TOKEN_PENALTY;
+        } else if in_searched {
+            penalty += TOKEN_MISMATCH_PENALTY;
+            reasons.push(MatchReason::MissingToken(word.clone()));
+        } else {
+            penalty += TOKEN_MISMATCH_PENALTY;
+            reasons.push(MatchReason::ExtraToken(word.clone()));
         }
-        return false;
     }
-    true
+
+    let meaningful = |words: &HashSet<String>| -> HashSet<String> {
+        words
+            .iter()
+            .filter(|word| {
+                !TOKENS_UNWANTED.contains(&word.as_str())
+                    && !EXTENSION_KEYWORDS.contains(&word.as_str())
+                    && !word.chars().any(|c| c.is_ascii_digit())
+            })
+            .cloned()
+            .collect()
+    };
+    let searched_meaningful = meaningful(&searched_words);
+    let title_meaningful = meaningful(&title_words);
+    let union = searched_meaningful.union(&title_meaningful).count();
+    let jaccard = if union == 0 {
+        1.0
+    } else {
+        searched_meaningful.intersection(&title_meaningful).count() as f32 / union as f32
+    };
+
+    let name_match = NameMatch {
+        score: (jaccard - penalty).max(0.0),
+        reasons,
+    };
+    log::trace!("match of {} with {} : {:?}", searched, title, name_match);
+    name_match
+}
+
+pub fn are_names_similar(name1: &str, name2: &str) -> bool {
+    match_names(name1, name2).is_match()
+}
+
+/// True when both URLs show the same product, tracking parameters aside
+pub fn same_product_url(url1: &str, url2: &str) -> bool {
+    let product = |url: &str| -> String {
+        url.split(['?', '#'])
+            .next()
+            .unwrap_or_default()
+            .trim_end_matches('/')
+            .to_lowercase()
+    };
+    product(url1) == product(url2)
+}
+
+/// Pick the best scoring product of a result list, the first one on a tie.
+/// Its score is stored in the returned reference. A product pinned by an
+/// admin is picked whatever its title, if it is in the list.
+pub fn best_match(game: &SearchedGame, products: Vec<(String, Reference)>) -> Option<Reference> {
+    if let Some(pinned) = &game.pinned_url {
+        let reference = products
+            .into_iter()
+            .map(|(_, reference)| reference)
+            .find(|reference| same_product_url(pinned, &reference.url));
+        if reference.is_none() {
+            log::warn!("pinned product {} not found for {}", pinned, game.name);
+        }
+        return reference.map(|reference| Reference {
+            match_score: Some(1.0),
+            ..reference
+        });
+    }
+
+    let mut best: Option<(f32, Reference)> = None;
+    for (title, reference) in products {
+        let name_match = match_game(&game.name, game.kind, &title);
+        let score = name_match.score;
+        if name_match.is_match() && best.as_ref().is_none_or(|(s, _)| score > *s) {
+            best = Some((score, reference));
+        }
+    }
+    best.map(|(score, reference)| Reference {
+        match_score: Some(score),
+        ..reference
+    })
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::website::helper::are_names_similar;
+    use chrono::NaiveDate;
+
+    use crate::game::{GameKind, Reference};
+    use crate::website::helper::{
+        are_names_similar, best_match, match_game, match_names, parse_availability, parse_date,
+        parse_price, split_prices, title_kind, MatchReason, MIN_MATCH_SCORE,
+    };
+    use crate::website::SearchedGame;
 
     struct Test<'a> {
         name1: &'a str,
@@ -140,7 +388,224 @@ mod tests {
             },
         ];
         for test in tests.into_iter() {
-            assert_eq!(are_names_similar(&test.name1, &test.name2), test.result);
+            assert_eq!(are_names_similar(test.name1, test.name2), test.result);
         }
     }
+
+    #[test]
+    fn test_match_names() {
+        let duel = match_names("Tiny Epic Western", "Tiny Epic Western: The Duel");
+        assert!((duel.score - 0.45).abs() < 1e-6);
+        assert_eq!(
+            duel.reasons,
+            vec![MatchReason::ExtraToken("duel".to_string())]
+        );
+
+        let sequel = match_names("Dixit", "Dixit 2");
+        assert_eq!(sequel.score, 0.7);
+        assert_eq!(
+            sequel.reasons,
+            vec![MatchReason::NumberMismatch("2".to_string())]
+        );
+
+        let extension = match_names("Dominion", "Dominion Intrigue Extension");
+        assert!(extension
+            .reasons
+            .contains(&MatchReason::ExtensionKeyword("extension".to_string())));
+        assert!(extension
+            .reasons
+            .contains(&MatchReason::ExtraToken("intrigue".to_string())));
+        assert_eq!(extension.score, 0.0);
+
+        let missing = match_names("Les Aventuriers du Rail Europe", "Les Aventuriers du Rail");
+        assert_eq!(
+            missing.reasons,
+            vec![MatchReason::MissingToken("europe".to_string())]
+        );
+        assert!(!missing.is_match());
+
+        // packaging words lower the score without rejecting the title
+        let edition = match_names("Azul", "Azul Edition VF");
+        assert!(edition.reasons.is_empty());
+        assert!((edition.score - 0.9).abs() < 1e-6);
+        assert!(edition.is_match());
+    }
+
+    #[test]
+    fn test_best_match() {
+        let reference = |price| Reference {
+            price,
+            ..Default::default()
+        };
+        let products = vec![
+            ("Azul - Pavillon d'Eté".to_string(), reference(37.9)),
+            ("Azul".to_string(), reference(35.9)),
+            ("Azul : Edition".to_string(), reference(34.9)),
+        ];
+        let best = best_match(&SearchedGame::new("Azul", GameKind::Unknown), products).unwrap();
+        assert_eq!(best.price, 35.9);
+        assert_eq!(best.match_score, Some(1.0));
+
+        // both titles are accepted, the closest one wins whatever the order
+        let products = vec![
+            ("Azul - Edition VF".to_string(), reference(29.9)),
+            ("Azul".to_string(), reference(35.9)),
+        ];
+        let best = best_match(&SearchedGame::new("Azul", GameKind::Unknown), products).unwrap();
+        assert_eq!(best.price, 35.9);
+        assert_eq!(best.match_score, Some(1.0));
+        let best = best_match(
+            &SearchedGame::new("Azul", GameKind::Unknown),
+            vec![("Azul - Edition VF".to_string(), reference(29.9))],
+        )
+        .unwrap();
+        assert!((best.match_score.unwrap() - 0.9).abs() < 1e-6);
+
+        assert!(best_match(
+            &SearchedGame::new("Azul", GameKind::Unknown),
+            vec![("Azul Duel".to_string(), reference(20.0))]
+        )
+        .is_none());
+
+        // one more word is another game of the series
+        assert!(best_match(
+            &SearchedGame::new("Les Aventuriers du Rail Europe", GameKind::Unknown),
+            vec![("Les Aventuriers du Rail".to_string(), reference(39.9))]
+        )
+        .is_none());
+        assert!(best_match(
+            &SearchedGame::new("Les Aventuriers du Rail", GameKind::Unknown),
+            vec![(
+                "Les Aventuriers du Rail Europe".to_string(),
+                reference(42.9)
+            )]
+        )
+        .is_none());
+
+        // the expansion announce is not priced against the base box
+        let products = vec![
+            ("Catan - Jeu de base".to_string(), reference(45.0)),
+            ("Catan - Extension Marins".to_string(), reference(35.0)),
+        ];
+        let best = best_match(
+            &SearchedGame::new("Catan Marins", GameKind::Expansion),
+            products.clone(),
+        )
+        .unwrap();
+        assert_eq!(best.price, 35.0);
+        assert!(best_match(
+            &SearchedGame::new("Catan", GameKind::Expansion),
+            products.clone()
+        )
+        .is_none());
+        assert_eq!(
+            best_match(&SearchedGame::new("Catan", GameKind::Base), products)
+                .unwrap()
+                .price,
+            45.0
+        );
+    }
+
+    #[test]
+    fn test_title_kind() {
+        assert_eq!(title_kind("Azul"), GameKind::Unknown);
+        assert_eq!(
+            title_kind("Dominion : Intrigue (Extension)"),
+            GameKind::Expansion
+        );
+        assert_eq!(title_kind("Catan - Jeu de base"), GameKind::Base);
+        assert_eq!(
+            title_kind("Catan + Extension 5-6 joueurs"),
+            GameKind::BaseWithExpansion
+        );
+        // a deck building game is not an expansion
+        assert_eq!(title_kind("Dale of Merchants Deck"), GameKind::Unknown);
+    }
+
+    #[test]
+    fn test_match_game() {
+        // the announce type tells what the keyword would
+        assert_eq!(
+            match_game(
+                "Dominion Intrigue",
+                GameKind::Expansion,
+                "Dominion Intrigue - Extension"
+            )
+            .score,
+            1.0
+        );
+        assert!(
+            match_names("Dominion Intrigue", "Dominion Intrigue - Extension").score
+                < MIN_MATCH_SCORE
+        );
+
+        assert_eq!(
+            match_game("Azul", GameKind::Expansion, "Azul - Jeu de base").reasons,
+            vec![MatchReason::KindMismatch(GameKind::Base)]
+        );
+        assert_eq!(
+            match_game("Azul", GameKind::Base, "Azul extension").reasons,
+            vec![MatchReason::KindMismatch(GameKind::Expansion)]
+        );
+        // without announce type the kind comes from the searched name
+        assert_eq!(
+            match_game(
+                "Catan Extension Marins",
+                GameKind::Unknown,
+                "Catan Jeu de base"
+            )
+            .score,
+            0.0
+        );
+        // a game with its expansions can be compared to the base box
+        assert_eq!(
+            match_game("Catan", GameKind::BaseWithExpansion, "Catan").score,
+            1.0
+        );
+    }
+
+    #[test]
+    fn test_parse_price() {
+        assert_eq!(parse_price("39,90\u{a0}€"), Some(39.9));
+        assert_eq!(parse_price(" 1 039,90 € "), Some(1039.9));
+        assert_eq!(parse_price("24.5€"), Some(24.5));
+        assert_eq!(parse_price("Prix sur demande"), None);
+    }
+
+    #[test]
+    fn test_parse_availability() {
+        assert_eq!(
+            parse_availability("en stock, généralement expédié sous 24h"),
+            Some(true)
+        );
+        assert_eq!(
+            parse_availability("hors-stock, généralement expédié sous 5 jours"),
+            Some(false)
+        );
+        assert_eq!(parse_availability("Rupture de stock"), Some(false));
+        assert_eq!(parse_availability("Indisponible"), Some(false));
+        assert_eq!(parse_availability("Précommande"), None);
+    }
+
+    #[test]
+    fn test_split_prices() {
+        assert_eq!(split_prices(Some(24.9), 22.41), (24.9, Some(22.41)));
+        assert_eq!(split_prices(None, 22.41), (22.41, None));
+        // same price shown twice is not a sale
+        assert_eq!(split_prices(Some(22.41), 22.41), (22.41, None));
+    }
+
+    #[test]
+    fn test_parse_date() {
+        assert_eq!(
+            parse_date("Offre valable jusqu'au 31/10/2026"),
+            NaiveDate::from_ymd_opt(2026, 10, 31)
+        );
+        assert_eq!(
+            parse_date("Fin le 5/1/2027"),
+            NaiveDate::from_ymd_opt(2027, 1, 5)
+        );
+        assert_eq!(parse_date("31/02/2026"), None);
+        assert_eq!(parse_date("bientôt"), None);
+    }
 }
@@ -1,62 +1,327 @@
-use std::error;
+use std::time::Duration;
 
-use scraper::Selector;
+use async_trait::async_trait;
+use futures_util::{stream, StreamExt};
+use scraper::{Html, Selector};
+use unidecode::unidecode;
 
 use crate::{
-    game::{Game, Reference},
+    game::Reference,
     httpclient,
-    website::helper::clean_name,
+    website::{
+        health::check_page_structure,
+        helper::{clean_name, match_game, parse_price},
+        Reseller, SearchedGame, StandardResellerTrait,
+    },
 };
 
-pub async fn get_knapix_prices(game: &mut Game) -> Result<(), Box<dyn error::Error>> {
-    let name = clean_name(&game.okkazeo_announce.name).replace(' ', "+");
-    let search = format!(
-        "https://www.knapix.com/comparateur.php?nom_jeu={}&checkbox-exact=on&affiner=",
-        name
-    );
+/// Knapix is a price comparator, a single search gives the prices of several shops
+pub struct Knapix;
 
-    log::debug!("searching knapix {}", search);
-    let (document, _) = httpclient::get_doc(search).await?;
+/// Present on every comparator page, with or without shop
+const KNAPIX_CONTAINER: &str = "table.comparateur";
 
-    // choper <tr data-href="/r/127347999"> pou rla redirection vers le site
-    let row_selector = Selector::parse("tr[data-href]")?;
-    let img_selector = Selector::parse("img[alt]")?;
-    let price_selector = Selector::parse(".prix")?;
+#[async_trait]
+impl StandardResellerTrait for Knapix {
+    fn name(&self) -> &str {
+        Reseller::Knapix.name()
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let name = game.name.as_str();
+        let name_clean = clean_name(name).replace(' ', "+");
+        let search = format!(
+            "https://www.knapix.com/comparateur.php?nom_jeu={}&checkbox-exact=on&affiner=",
+            name_clean
+        );
+
+        log::debug!("searching knapix {}", search);
+        let references = {
+            let (document, status) = httpclient::get_doc(search).await?;
+            check_page_structure(self.name(), status, &document, KNAPIX_CONTAINER)?;
+            self.parse_document(game, &document)
+        };
+
+        Ok(stream::iter(references)
+            .map(resolve_knapix_url)
+            .buffered(KNAPIX_REDIRECT_CONCURRENCY)
+            .collect()
+            .await)
+    }
+
+    /// Cheapest matching row of each shop, only one reference by shop is kept
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference> {
+        let mut references: Vec<Reference> = vec![];
+        for (title, reference) in parse_knapix_document(document) {
+            let name_match = match_game(&game.name, game.kind, &title);
+            if !name_match.is_match() {
+                log::trace!("knapix row {} is not {}", title, game.name);
+                continue;
+            }
+            let reference = Reference {
+                match_score: Some(name_match.score),
+                ..reference
+            };
+            match references.iter_mut().find(|r| r.name == reference.name) {
+                Some(known) if known.price <= reference.price => {}
+                Some(known) => *known = reference,
+                None => references.push(reference),
+            }
+        }
+        references
+    }
+}
+
+/// Replace the knapix `/r/...` link by the shop URL it redirects to.
+/// The knapix link is kept when the redirection cannot be resolved in time.
+async fn resolve_knapix_url(reference: Reference) -> Reference {
+    match tokio::time::timeout(
+        KNAPIX_REDIRECT_TIMEOUT,
+        httpclient::resolve_redirects(&reference.url, KNAPIX_REDIRECT_MAX_HOPS),
+    )
+    .await
+    {
+        Ok(Ok(url)) => Reference { url, ..reference },
+        Ok(Err(e)) => {
+            log::warn!("cannot resolve knapix link {} : {}", reference.url, e);
+            reference
+        }
+        Err(_) => {
+            log::debug!("knapix link {} not resolved in time", reference.url);
+            reference
+        }
+    }
+}
 
+/// Shop names used by knapix which differ from the keys of `Game.references`,
+/// compared once lowercased and stripped of accents and punctuation
+static KNAPIX_SHOP_NAMES: [(&str, &str); 7] = [
+    ("philibertnet", "philibert"),
+    ("espritjeux", "espritjeu"),
+    ("ultrajeuxcom", "ultrajeux"),
+    ("agorajeuxcom", "agorajeux"),
+    ("amazonfr", "amazon"),
+    ("fnaccom", "fnac"),
+    ("cdiscountcom", "cdiscount"),
+];
+
+/// Turn the shop name shown by knapix into the key used for its references
+fn normalize_knapix_shop(name: &str) -> String {
+    let name: String = unidecode(name)
+        .to_lowercase()
+        .chars()
+        .filter(|c| c.is_ascii_alphanumeric())
+        .collect();
+    KNAPIX_SHOP_NAMES
+        .iter()
+        .find(|(alias, _)| *alias == name)
+        .map_or(name, |(_, shop)| shop.to_string())
+}
+
+/// Title of each row with its reference, the title of the page for the rows
+/// which do not name their product
+fn parse_knapix_document(document: &Html) -> Vec<(String, Reference)> {
+    // each row redirects to the shop through <tr data-href="/r/127347999">
+    let row_selector = Selector::parse("tr[data-href]").unwrap();
+    let img_selector = Selector::parse("img[alt]").unwrap();
+    let price_selector = Selector::parse(".prix").unwrap();
+    let title_selector = Selector::parse(".produit").unwrap();
+    let page_title = document
+        .select(&Selector::parse("h1").unwrap())
+        .next()
+        .map(|h1| h1.text().collect::<String>().trim().to_string())
+        .unwrap_or_default();
+
+    let mut references = vec![];
     for row in document.select(&row_selector) {
         let url = format!(
             "{}{}",
             "https://www.knapix.com",
             row.value().attr("data-href").unwrap_or_default()
         );
-        let img_element = row.select(&img_selector).next();
-        if let Some(img) = img_element {
-            let alt_value = img.value().attr("alt").unwrap_or_default().to_lowercase();
-
-            let price_element = row.select(&price_selector).next();
-            if let Some(price) = price_element {
-                let price_text = price.text().collect::<String>();
-                let price = price_text
-                    .trim()
-                    .replace(" €", "")
-                    .replace(',', ".")
-                    .parse::<f32>()?;
-                match alt_value.as_str() {
-                    "agorajeux" | "philibert" | "ultrajeux" => {
-                        game.references.insert(
-                            String::from(alt_value.as_str()),
-                            Reference {
-                                name: String::from(alt_value.as_str()),
-                                price,
-                                url,
-                            },
-                        );
-                    }
-                    _ => {}
-                }
+        let shop = match row
+            .select(&img_selector)
+            .next()
+            .and_then(|img| img.value().attr("alt"))
+            .map(normalize_knapix_shop)
+        {
+            Some(s) if !s.is_empty() => s,
+            _ => {
+                log::trace!("fail to select shop of knapix row {}", url);
+                continue;
             }
+        };
+
+        let price_text = match row.select(&price_selector).next() {
+            Some(p) => p.text().collect::<String>(),
+            None => {
+                log::trace!("fail to select price of knapix row {}", url);
+                continue;
+            }
+        };
+        let price = match parse_price(&price_text) {
+            Some(p) => p,
+            None => {
+                log::error!("cannot parse knapix price {}", price_text.trim());
+                KNAPIX_STAT.with_label_values(&["fail"]).inc();
+                continue;
+            }
+        };
+
+        let title = row
+            .select(&title_selector)
+            .next()
+            .map(|t| t.text().collect::<String>().trim().to_string())
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| page_title.clone());
+
+        references.push((
+            title,
+            Reference {
+                name: shop,
+                price,
+                url,
+                aggregated: true,
+                ..Default::default()
+            },
+        ));
+    }
+
+    if references.is_empty() {
+        KNAPIX_STAT.with_label_values(&["fail"]).inc();
+    } else {
+        KNAPIX_STAT.with_label_values(&["success"]).inc();
+    }
+    references
+}
+
+/// Deadline to resolve a knapix link, all of them wait for the knapix rate limiter
+const KNAPIX_REDIRECT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Links resolved at the same time, the others wait instead of piling up on the limiter
+const KNAPIX_REDIRECT_CONCURRENCY: usize = 2;
+/// Affiliation platforms can add a few hops before the shop
+const KNAPIX_REDIRECT_MAX_HOPS: usize = 5;
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    static ref KNAPIX_STAT: IntCounterVec = register_int_counter_vec!(
+        "knapix_stat",
+        "Stat about parsing/fetch success/fail for this website",
+        &["result"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{normalize_knapix_shop, parse_knapix_document, Knapix, KNAPIX_CONTAINER};
+    use crate::game::GameKind;
+    use crate::website::{health::check_page_structure, SearchedGame, StandardResellerTrait};
+    use reqwest::StatusCode;
+    use std::fs;
+
+    struct Test {
+        document: String,
+        result: Vec<(String, f32, String)>,
+    }
+
+    #[test]
+    fn test_normalize_shop() {
+        assert_eq!(normalize_knapix_shop("Philibert"), "philibert");
+        assert_eq!(normalize_knapix_shop("Philibert.net"), "philibert");
+        assert_eq!(normalize_knapix_shop("Esprit Jeux"), "espritjeu");
+        assert_eq!(normalize_knapix_shop("Le Passe-Temps"), "lepassetemps");
+        assert_eq!(normalize_knapix_shop("La Boîte à Jeux"), "laboiteajeux");
+    }
+
+    #[test]
+    fn test_parsing() {
+        let tests = vec![
+            Test {
+                document: "tests/knapix/test1.html".to_string(),
+                result: vec![
+                    (
+                        "philibert".to_string(),
+                        33.9,
+                        "https://www.knapix.com/r/127347999".to_string(),
+                    ),
+                    (
+                        "lepassetemps".to_string(),
+                        34.5,
+                        "https://www.knapix.com/r/127348120".to_string(),
+                    ),
+                    (
+                        "espritjeu".to_string(),
+                        35.9,
+                        "https://www.knapix.com/r/127348455".to_string(),
+                    ),
+                    (
+                        "ultrajeux".to_string(),
+                        36.9,
+                        "https://www.knapix.com/r/127348502".to_string(),
+                    ),
+                    (
+                        "amazon".to_string(),
+                        1039.9,
+                        "https://www.knapix.com/r/127349001".to_string(),
+                    ),
+                ],
+            },
+            // no shop sells the game
+            Test {
+                document: "tests/knapix/test2.html".to_string(),
+                result: vec![],
+            },
+        ];
+        for test in tests.into_iter() {
+            let doc =
+                fs::read_to_string(test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(
+                check_page_structure("knapix", StatusCode::OK, &document, KNAPIX_CONTAINER).is_ok()
+            );
+            let references = parse_knapix_document(&document);
+            assert!(references.iter().all(|(_, r)| r.aggregated));
+            // the rows do not name their product, they are about the game of the page
+            assert!(references.iter().all(|(title, _)| title == "Azul"));
+            assert_eq!(
+                references
+                    .into_iter()
+                    .map(|(_, r)| (r.name, r.price, r.url))
+                    .collect::<Vec<_>>(),
+                test.result
+            );
         }
     }
 
-    Ok(())
+    #[test]
+    fn test_parse_document_matches_rows() {
+        let doc = fs::read_to_string("tests/knapix/test3.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&doc);
+
+        let references =
+            Knapix.parse_document(&SearchedGame::new("Azul", GameKind::Base), &document);
+        // the extension row is dropped, the cheapest row of each shop is kept
+        assert_eq!(
+            references
+                .iter()
+                .map(|r| (r.name.as_str(), r.price, r.url.as_str()))
+                .collect::<Vec<_>>(),
+            vec![
+                ("philibert", 33.9, "https://www.knapix.com/r/127347999"),
+                ("espritjeu", 34.0, "https://www.knapix.com/r/127348460"),
+            ]
+        );
+        assert!(references.iter().all(|r| r.match_score.is_some()));
+
+        // a page about the base game gives no price for an extension
+        let extension = SearchedGame::new("Azul Extension Joker", GameKind::Expansion);
+        let references = Knapix.parse_document(&extension, &document);
+        assert!(references.is_empty(), "{:?}", references);
+    }
 }
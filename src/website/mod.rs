@@ -1,32 +1,611 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
 use async_trait::async_trait;
+use futures_util::future::join_all;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
 use scraper::Html;
 
-pub mod agorajeux;
+use crate::game::{GameKind, Reference};
+use crate::gtin::Gtin;
+use health::{CircuitBreaker, UnrecognisedPage, RESELLER_BREAKER};
+use helper::{match_game, same_product_url};
+
 pub mod bgg;
+pub mod espritjeu;
+pub mod health;
 pub mod helper;
 pub mod knapix;
-pub mod ludifolie;
-pub mod ludocortex;
 pub mod okkazeo;
 pub mod philibert;
+pub mod prestashop;
+pub mod shipping;
+pub mod trictrac;
 pub mod ultrajeux;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Reseller {
     Philibert,
-    Agorajeux,
     Espritjeu,
-    Ludifolie,
-    Ludocortex,
     Ultrajeux,
+    Knapix,
+}
+
+impl Reseller {
+    /// Name used as key in `Game.references` and for the icon in assets/
+    pub fn name(&self) -> &'static str {
+        match self {
+            Reseller::Philibert => "philibert",
+            Reseller::Espritjeu => "espritjeu",
+            Reseller::Ultrajeux => "ultrajeux",
+            Reseller::Knapix => "knapix",
+        }
+    }
+
+    pub fn reference(&self, price: f32, url: String) -> Reference {
+        Reference {
+            name: self.name().to_string(),
+            price,
+            url,
+            ..Default::default()
+        }
+    }
+}
+
+/// Game looked up on a reseller
+#[derive(Debug, Clone, Default)]
+pub struct SearchedGame {
+    pub name: String,
+    /// Products of another kind than the announce are not references
+    pub kind: GameKind,
+    pub barcode: Option<Gtin>,
+    /// Other names of the game, searched when the name finds nothing
+    pub aliases: Vec<String>,
+    /// Product chosen by an admin, picked instead of the best matching name
+    pub pinned_url: Option<String>,
 }
-pub struct StandardReseller<Reseller> {
-    pub reseller: Reseller,
+
+impl SearchedGame {
+    pub fn new(name: &str, kind: GameKind) -> SearchedGame {
+        SearchedGame {
+            name: name.to_string(),
+            kind,
+            ..Default::default()
+        }
+    }
 }
 
+/// Manual corrections of the matcher by reseller name: the URL of the right
+/// product, or None when the reseller does not sell the game
+pub type MatchOverrides = HashMap<String, Option<String>>;
+
+/// A website we can query to get the price of a new copy of a game.
+/// Most of them are shops returning at most one reference, comparators
+/// like knapix can return references for several shops at once.
 #[async_trait]
-pub trait StandardResellerTrait {
-    async fn get_price_and_url_by_name();
-    async fn get_price_and_url_by_barcode();
-    async fn get_price_and_url();
-    fn parse_document(name: &str, document: &Html) -> Option<(f32, String)>;
+pub trait StandardResellerTrait: Send + Sync {
+    /// Key in `Game.references` of the references found by this website
+    fn name(&self) -> &str;
+
+    /// Deadline for a whole lookup (barcode then name), rate limiter wait included
+    fn timeout(&self) -> Duration {
+        *RESELLER_TIMEOUT
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error>;
+
+    /// Websites without barcode search just don't find anything
+    async fn get_references_by_barcode(
+        &self,
+        _barcode: Gtin,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        Ok(Vec::new())
+    }
+
+    /// Barcode lookup first as it is more reliable, then fallback on the name
+    /// and on the aliases. A product found by barcode is ignored when an admin
+    /// pinned another one. Its title is kept to learn an alias from only when
+    /// the name does not match it and the name lookup misses it.
+    async fn get_references(&self, game: &SearchedGame) -> Result<Vec<Reference>, anyhow::Error> {
+        if let Some(barcode) = game.barcode {
+            let references: Vec<Reference> = self
+                .get_references_by_barcode(barcode)
+                .await?
+                .into_iter()
+                .filter(|reference| {
+                    game.pinned_url
+                        .as_ref()
+                        .is_none_or(|pinned| same_product_url(pinned, &reference.url))
+                })
+                .collect();
+            if !references.is_empty() {
+                let unmatched_title = references.iter().any(|reference| {
+                    reference
+                        .product_title
+                        .as_deref()
+                        .is_some_and(|title| !match_game(&game.name, game.kind, title).is_match())
+                });
+                // an alias is only worth it when the name cannot find the product,
+                // a failed name lookup is no proof of that
+                let found_by_name = unmatched_title
+                    && self
+                        .get_references_by_name(game)
+                        .await
+                        .map_or(true, |references| !references.is_empty());
+                // a barcode identifies the game for sure
+                return Ok(references
+                    .into_iter()
+                    .map(|reference| Reference {
+                        match_score: reference.match_score.or(Some(1.0)),
+                        product_title: reference.product_title.filter(|_| !found_by_name),
+                        ..reference
+                    })
+                    .collect());
+            }
+        }
+        let references = self.get_references_by_name(game).await?;
+        if !references.is_empty() {
+            return Ok(references);
+        }
+        for alias in &game.aliases {
+            let aliased = SearchedGame {
+                name: alias.clone(),
+                ..game.clone()
+            };
+            let references = self.get_references_by_name(&aliased).await?;
+            if !references.is_empty() {
+                log::debug!("{} found {} as {}", self.name(), game.name, alias);
+                RESELLER_LOOKUP
+                    .with_label_values(&[self.name(), "alias"])
+                    .inc();
+                return Ok(references);
+            }
+        }
+        Ok(references)
+    }
+
+    /// Extract the references matching `game` from a search result page
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference>;
+}
+
+/// Outcome of querying several resellers for the same game
+#[derive(Debug, Default)]
+pub struct ResellersSearch {
+    pub references: Vec<Reference>,
+    /// Resellers which did not answer before their deadline, worth retrying later
+    pub timed_out: Vec<String>,
+}
+
+/// Query all `resellers` concurrently. Each lookup has its own deadline and is
+/// cancelled when it expires, requests still go through the per host limiter of
+/// `httpclient`. Resellers disabled by `RESELLER_BREAKER` are skipped.
+/// References are returned in the order of `resellers`, with their landed
+/// price computed from the shipping rules. `overrides` pin the product of a
+/// shop, the shops overridden with no match are not queried and their
+/// references found by a comparator are dropped.
+pub async fn search_resellers(
+    resellers: &[&dyn StandardResellerTrait],
+    game: &SearchedGame,
+    overrides: &MatchOverrides,
+) -> ResellersSearch {
+    search_resellers_with(resellers, game, overrides, &RESELLER_BREAKER).await
+}
+
+async fn search_resellers_with(
+    resellers: &[&dyn StandardResellerTrait],
+    game: &SearchedGame,
+    overrides: &MatchOverrides,
+    breaker: &CircuitBreaker,
+) -> ResellersSearch {
+    let name = game.name.as_str();
+    let lookups = resellers
+        .iter()
+        .filter(|reseller| {
+            if let Some(None) = overrides.get(reseller.name()) {
+                log::debug!(
+                    "{} does not sell {}, skipping lookup",
+                    reseller.name(),
+                    name
+                );
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller.name(), "overridden"])
+                    .inc();
+                return false;
+            }
+            if breaker.is_open(reseller.name()) {
+                log::debug!(
+                    "{} is disabled, skipping lookup for {}",
+                    reseller.name(),
+                    name
+                );
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller.name(), "disabled"])
+                    .inc();
+                return false;
+            }
+            true
+        })
+        .map(|reseller| async move {
+            let game = SearchedGame {
+                pinned_url: overrides.get(reseller.name()).cloned().flatten(),
+                ..game.clone()
+            };
+            let result =
+                tokio::time::timeout(reseller.timeout(), reseller.get_references(&game)).await;
+            (reseller.name(), result)
+        });
+
+    let mut search = ResellersSearch::default();
+    for (reseller, result) in join_all(lookups).await {
+        match result {
+            Err(_) => {
+                log::warn!("{} lookup for {} timed out", reseller, name);
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller, "timeout"])
+                    .inc();
+                search.timed_out.push(reseller.to_string());
+            }
+            Ok(Err(e)) if e.downcast_ref::<UnrecognisedPage>().is_some() => {
+                log::error!("error getting {} price : {}", reseller, e);
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller, "unrecognised"])
+                    .inc();
+                breaker.record_structural_failure(reseller);
+            }
+            Ok(Err(e)) => {
+                log::error!("error getting {} price : {}", reseller, e);
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller, "error"])
+                    .inc();
+            }
+            Ok(Ok(references)) => {
+                breaker.record_success(reseller);
+                let outcome = if references.is_empty() {
+                    "no_match"
+                } else {
+                    "found"
+                };
+                RESELLER_LOOKUP
+                    .with_label_values(&[reseller, outcome])
+                    .inc();
+                search.references.extend(
+                    references
+                        .into_iter()
+                        .filter(|reference| match overrides.get(&reference.name) {
+                            None => true,
+                            Some(None) => false,
+                            Some(Some(pinned)) => same_product_url(pinned, &reference.url),
+                        })
+                        .map(|reference| Reference {
+                            landed_price: shipping::landed_price(
+                                &reference.name,
+                                reference.current_price(),
+                            ),
+                            ..reference
+                        }),
+                );
+            }
+        }
+    }
+    search
+}
+
+fn create_reseller_timeout() -> Duration {
+    let secs = std::env::var("RESELLER_TIMEOUT_SECS")
+        .map_err(|e| e.to_string())
+        .and_then(|v| v.parse::<u64>().map_err(|e| e.to_string()))
+        .unwrap_or_else(|err| {
+            log::warn!(
+                "Cannot initialize reseller timeout from environment, fallback to default: {}",
+                err
+            );
+            DEFAULT_RESELLER_TIMEOUT_SECS
+        });
+    Duration::from_secs(secs)
+}
+
+/// DEFAULT_RESELLER_TIMEOUT_SECS is the default deadline of a reseller lookup if not specified
+const DEFAULT_RESELLER_TIMEOUT_SECS: u64 = 60;
+
+lazy_static! {
+    pub static ref RESELLER_TIMEOUT: Duration = create_reseller_timeout();
+    static ref RESELLER_LOOKUP: IntCounterVec = register_int_counter_vec!(
+        "reseller_lookup",
+        "Number of reseller lookups by outcome (found, no_match, unrecognised, error, timeout, disabled)",
+        &["reseller", "result"]
+    )
+    .unwrap();
+
+    /// Websites queried when enriching a game. Knapix comes first so that a
+    /// reference found directly on a shop replaces the one given by knapix.
+    /// PrestaShop like shops are described in PRESTASHOP_FILE.
+    pub static ref RESELLERS: Vec<Box<dyn StandardResellerTrait>> = {
+        let mut resellers: Vec<Box<dyn StandardResellerTrait>> = vec![
+            Box::new(knapix::Knapix),
+            Box::new(philibert::Philibert),
+            Box::new(ultrajeux::Ultrajeux),
+            Box::new(espritjeu::Espritjeu),
+        ];
+        for reseller in prestashop::load_prestashop_resellers() {
+            resellers.push(Box::new(reseller));
+        }
+        resellers
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use async_trait::async_trait;
+    use reqwest::StatusCode;
+    use scraper::Html;
+
+    use super::{
+        health::{check_page_structure, CircuitBreaker},
+        search_resellers_with, MatchOverrides, Reseller, SearchedGame, StandardResellerTrait,
+    };
+    use crate::game::{GameKind, Reference};
+    use crate::gtin::Gtin;
+
+    struct Test {
+        reseller: Reseller,
+        delay: Duration,
+    }
+
+    /// Breaker of a single test, the tests must not disable the shops of each other
+    fn breaker() -> CircuitBreaker {
+        CircuitBreaker::new(5, Duration::from_secs(60))
+    }
+
+    #[async_trait]
+    impl StandardResellerTrait for Test {
+        fn name(&self) -> &str {
+            self.reseller.name()
+        }
+
+        fn timeout(&self) -> Duration {
+            Duration::from_millis(200)
+        }
+
+        async fn get_references_by_name(
+            &self,
+            game: &SearchedGame,
+        ) -> Result<Vec<Reference>, anyhow::Error> {
+            tokio::time::sleep(self.delay).await;
+            Ok(self.parse_document(game, &Html::new_document()))
+        }
+
+        fn parse_document(&self, _game: &SearchedGame, _document: &Html) -> Vec<Reference> {
+            vec![self.reseller.reference(10.0, String::new())]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_resellers() {
+        let slow = Test {
+            reseller: Reseller::Philibert,
+            delay: Duration::from_secs(5),
+        };
+        let fast = Test {
+            reseller: Reseller::Ultrajeux,
+            delay: Duration::from_millis(10),
+        };
+        let other_fast = Test {
+            reseller: Reseller::Espritjeu,
+            delay: Duration::from_millis(100),
+        };
+
+        let azul = SearchedGame::new("Azul", GameKind::Base);
+        let start = std::time::Instant::now();
+        let search = search_resellers_with(
+            &[&slow, &fast, &other_fast],
+            &azul,
+            &MatchOverrides::new(),
+            &breaker(),
+        )
+        .await;
+
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(search.timed_out, vec!["philibert".to_string()]);
+        let names: Vec<&str> = search.references.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["ultrajeux", "espritjeu"]);
+    }
+
+    #[tokio::test]
+    async fn test_search_resellers_overrides() {
+        let ultrajeux = Test {
+            reseller: Reseller::Ultrajeux,
+            delay: Duration::from_millis(10),
+        };
+        let espritjeu = Test {
+            reseller: Reseller::Espritjeu,
+            delay: Duration::from_millis(10),
+        };
+        let philibert = Test {
+            reseller: Reseller::Philibert,
+            delay: Duration::from_millis(10),
+        };
+        let overrides = MatchOverrides::from([
+            ("ultrajeux".to_string(), None),
+            (
+                "espritjeu".to_string(),
+                Some("https://www.espritjeu.com/azul.html".to_string()),
+            ),
+        ]);
+
+        let search = search_resellers_with(
+            &[&ultrajeux, &espritjeu, &philibert],
+            &SearchedGame::new("Azul", GameKind::Base),
+            &overrides,
+            &breaker(),
+        )
+        .await;
+
+        // ultrajeux is not looked up, espritjeu found another product than the pinned one
+        assert!(search.timed_out.is_empty());
+        let names: Vec<&str> = search.references.iter().map(|r| r.name.as_str()).collect();
+        assert_eq!(names, vec!["philibert"]);
+    }
+
+    /// A shop which only knows the english title
+    struct English;
+
+    #[async_trait]
+    impl StandardResellerTrait for English {
+        fn name(&self) -> &str {
+            Reseller::Philibert.name()
+        }
+
+        async fn get_references_by_name(
+            &self,
+            game: &SearchedGame,
+        ) -> Result<Vec<Reference>, anyhow::Error> {
+            Ok(self.parse_document(game, &Html::new_document()))
+        }
+
+        fn parse_document(&self, game: &SearchedGame, _document: &Html) -> Vec<Reference> {
+            if game.name == "Ticket to Ride" {
+                vec![Reseller::Philibert.reference(42.0, String::new())]
+            } else {
+                vec![]
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_references_aliases() {
+        let mut game = SearchedGame::new("Les Aventuriers du Rail", GameKind::Base);
+        assert!(English.get_references(&game).await.unwrap().is_empty());
+
+        game.aliases = vec!["Zug um Zug".to_string(), "Ticket to Ride".to_string()];
+        let references = English.get_references(&game).await.unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].price, 42.0);
+    }
+
+    /// A shop whose barcode search finds "Catan", and its name search too when `knows_name`
+    struct Catan {
+        knows_name: bool,
+    }
+
+    #[async_trait]
+    impl StandardResellerTrait for Catan {
+        fn name(&self) -> &str {
+            Reseller::Philibert.name()
+        }
+
+        async fn get_references_by_barcode(
+            &self,
+            _barcode: Gtin,
+        ) -> Result<Vec<Reference>, anyhow::Error> {
+            Ok(vec![Reference {
+                product_title: Some("Catan".to_string()),
+                ..Reseller::Philibert.reference(40.0, String::new())
+            }])
+        }
+
+        async fn get_references_by_name(
+            &self,
+            game: &SearchedGame,
+        ) -> Result<Vec<Reference>, anyhow::Error> {
+            Ok(self.parse_document(game, &Html::new_document()))
+        }
+
+        fn parse_document(&self, _game: &SearchedGame, _document: &Html) -> Vec<Reference> {
+            if self.knows_name {
+                vec![Reseller::Philibert.reference(40.0, String::new())]
+            } else {
+                vec![]
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_get_references_barcode_title() {
+        let game = SearchedGame {
+            barcode: Gtin::new(4006381333931),
+            ..SearchedGame::new("Les Colons de Catane", GameKind::Base)
+        };
+        let references = Catan { knows_name: false }
+            .get_references(&game)
+            .await
+            .unwrap();
+        assert_eq!(references[0].product_title.as_deref(), Some("Catan"));
+
+        // the name finds the product, no alias to learn
+        let references = Catan { knows_name: true }
+            .get_references(&game)
+            .await
+            .unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0].product_title, None);
+    }
+
+    /// A shop which changed its HTML, or which blocks us when `status` is not 200
+    struct Broken {
+        lookups: AtomicU32,
+        status: StatusCode,
+    }
+
+    #[async_trait]
+    impl StandardResellerTrait for Broken {
+        fn name(&self) -> &str {
+            Reseller::Knapix.name()
+        }
+
+        async fn get_references_by_name(
+            &self,
+            game: &SearchedGame,
+        ) -> Result<Vec<Reference>, anyhow::Error> {
+            self.lookups.fetch_add(1, Ordering::SeqCst);
+            let document = Html::new_document();
+            check_page_structure(self.name(), self.status, &document, "table.comparateur")?;
+            Ok(self.parse_document(game, &document))
+        }
+
+        fn parse_document(&self, _game: &SearchedGame, _document: &Html) -> Vec<Reference> {
+            vec![]
+        }
+    }
+
+    #[tokio::test]
+    async fn test_search_resellers_disables_broken_reseller() {
+        let broken = Broken {
+            lookups: AtomicU32::new(0),
+            status: StatusCode::OK,
+        };
+        let azul = SearchedGame::new("Azul", GameKind::Base);
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        for _ in 0..3 {
+            let search =
+                search_resellers_with(&[&broken], &azul, &MatchOverrides::new(), &breaker).await;
+            assert!(search.references.is_empty());
+        }
+        assert_eq!(broken.lookups.load(Ordering::SeqCst), 3);
+        assert!(breaker.is_open("knapix"));
+
+        search_resellers_with(&[&broken], &azul, &MatchOverrides::new(), &breaker).await;
+        assert_eq!(broken.lookups.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_search_resellers_keeps_rate_limited_reseller() {
+        let blocked = Broken {
+            lookups: AtomicU32::new(0),
+            status: StatusCode::TOO_MANY_REQUESTS,
+        };
+        let azul = SearchedGame::new("Azul", GameKind::Base);
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        for _ in 0..4 {
+            search_resellers_with(&[&blocked], &azul, &MatchOverrides::new(), &breaker).await;
+        }
+        assert_eq!(blocked.lookups.load(Ordering::SeqCst), 4);
+        assert!(!breaker.is_open("knapix"));
+    }
 }
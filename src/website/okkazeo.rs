@@ -4,11 +4,12 @@ use std::{
     path::Path,
 };
 
+use chrono::{DateTime, NaiveDateTime, Utc};
 use image::io::Reader as ImageReader;
 use regex::Regex;
 use serde::Deserialize;
 
-use crate::httpclient;
+use crate::{gtin::Gtin, httpclient};
 
 pub async fn download_okkazeo_game_image(
     url: &str,
@@ -57,7 +58,8 @@ pub struct Row {
     #[serde(rename = "Titre")]
     pub name: String,
     #[serde(rename = "EAN")]
-    pub ean: Option<u64>,
+    #[serde(deserialize_with = "deserialize_gtin")]
+    pub ean: Option<Gtin>,
     #[serde(rename = "Type")]
     pub kind: String,
     #[serde(rename = "Date")]
@@ -90,6 +92,14 @@ pub struct Row {
     pub shop2shop: Option<f32>,
 }
 
+impl Row {
+    /// Date of the last change of the announce, given in UTC by okkazeo
+    pub fn last_modification_date(&self) -> Result<DateTime<Utc>, chrono::ParseError> {
+        NaiveDateTime::parse_from_str(&self.date, "%Y-%m-%d %H:%M:%S")
+            .map(|date| DateTime::from_naive_utc_and_offset(date, Utc))
+    }
+}
+
 fn deserialize_bool<'de, D>(deserializer: D) -> Result<bool, D::Error>
 where
     D: serde::Deserializer<'de>,
@@ -103,6 +113,23 @@ where
     Ok(false)
 }
 
+/// Missing and invalid barcodes are both None, an invalid one must not fail the whole row
+fn deserialize_gtin<'de, D>(deserializer: D) -> Result<Option<Gtin>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    let opt: Option<String> = Option::deserialize(deserializer)?;
+    Ok(opt
+        .filter(|v| !v.trim().is_empty() && v.trim() != "0")
+        .and_then(|v| match v.parse::<Gtin>() {
+            Ok(gtin) => Some(gtin),
+            Err(e) => {
+                log::debug!("ignoring okkazeo barcode {} : {}", v, e);
+                None
+            }
+        }))
+}
+
 pub async fn get_okkazeo_csv(url: String) -> Result<Vec<Row>, anyhow::Error> {
     log::debug!("getting csv file");
     let content = httpclient::get(url).await?.bytes().await?;
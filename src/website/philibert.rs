@@ -1,107 +1,150 @@
-use scraper::Selector;
+use async_trait::async_trait;
+use scraper::{Html, Selector};
 
 use crate::{
+    game::Reference,
+    gtin::Gtin,
     httpclient,
-    website::helper::{are_names_similar, clean_name},
+    website::{
+        health::check_page_structure,
+        helper::{best_match, clean_name, parse_availability, parse_price, split_prices},
+        Reseller, SearchedGame, StandardResellerTrait,
+    },
 };
 
-pub async fn get_philibert_price_and_url_by_barcode(
-    barcode: u64,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    let search = format!(
-        "https://www.philibertnet.com/fr/recherche?search_query={}&submit_search=",
-        barcode
-    );
-    log::debug!("search on philibert by barcode: {}", &barcode);
-    let (document, _) = httpclient::get_doc(&search).await?;
+pub struct Philibert;
 
-    let product_list_selector = Selector::parse(".product_list.grid .ajax_block_product").unwrap();
-    let price_selector = Selector::parse(".price").unwrap();
-    let title_selector = Selector::parse("p.s_title_block a").unwrap();
+/// Present on every search page, with or without result
+const PHILIBERT_CONTAINER: &str = "#center_column";
 
-    for product in document.select(&product_list_selector) {
-        let price_element = product.select(&price_selector).next();
-        if let Some(price) = price_element {
-            let price_text = price.text().collect::<String>();
-            let price_text = price_text
-                .trim()
-                .replace(" €", "")
-                .replace(',', ".")
-                .parse::<f32>()
-                .unwrap_or(0.0);
-
-            let title_element = product.select(&title_selector).next();
-            if let Some(title) = title_element {
-                let href_attr = title.value().attr("href").unwrap_or_default();
-
-                if href_attr
-                    .split('?')
-                    .next()
-                    .unwrap()
-                    .contains(&barcode.to_string())
-                {
-                    PHILIBERT_STAT.with_label_values(&["success"]).inc();
-                    return Ok(Some((price_text, href_attr.to_string())));
-                }
-            }
-        }
+#[async_trait]
+impl StandardResellerTrait for Philibert {
+    fn name(&self) -> &str {
+        Reseller::Philibert.name()
     }
-    PHILIBERT_STAT.with_label_values(&["fail"]).inc();
-    Ok(None)
-}
 
-pub async fn get_philibert_price_and_url_by_name(
-    name: &str,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    let search = format!(
-        "https://www.philibertnet.com/fr/recherche?search_query={}&submit_search=",
-        clean_name(name)
-    );
-    log::debug!("search on philibert by name: {}", &name);
-    let (document, _) = httpclient::get_doc(&search).await?;
+    async fn get_references_by_barcode(
+        &self,
+        barcode: Gtin,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let search = format!(
+            "https://www.philibertnet.com/fr/recherche?search_query={}&submit_search=",
+            barcode
+        );
+        log::debug!("search on philibert by barcode: {}", &barcode);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, PHILIBERT_CONTAINER)?;
 
+        Ok(parse_philibert_document_by_barcode(barcode, &document)
+            .into_iter()
+            .collect())
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let name = game.name.as_str();
+        let search = format!(
+            "https://www.philibertnet.com/fr/recherche?search_query={}&submit_search=",
+            clean_name(name)
+        );
+        log::debug!("search on philibert by name: {}", &name);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, PHILIBERT_CONTAINER)?;
+
+        Ok(self.parse_document(game, &document))
+    }
+
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference> {
+        parse_philibert_document(game, document)
+            .into_iter()
+            .collect()
+    }
+}
+
+/// Returns the title and the reference of every product of the result list
+fn parse_philibert_products(document: &Html) -> Vec<(String, Reference)> {
     let product_list_selector = Selector::parse(".product_list.grid .ajax_block_product").unwrap();
     let price_selector = Selector::parse(".price").unwrap();
+    let regular_price_selector = Selector::parse(".old-price").unwrap();
     let title_selector = Selector::parse("p.s_title_block a").unwrap();
+    let availability_selector = Selector::parse(".availability").unwrap();
 
+    let mut products = vec![];
     for product in document.select(&product_list_selector) {
-        let price_element = product.select(&price_selector).next();
-        if let Some(price) = price_element {
-            let price_text = price.text().collect::<String>();
-            let price_text = price_text
-                .trim()
-                .replace(" €", "")
-                .replace(',', ".")
-                .parse::<f32>()
-                .unwrap_or(0.0);
-
-            let title_element = product.select(&title_selector).next();
-            if let Some(title) = title_element {
-                let title_text = title.text().collect::<String>();
-                let title_text = title_text.trim();
-                let href_attr = title.value().attr("href").unwrap_or_default();
-
-                if are_names_similar(title_text, name) {
-                    PHILIBERT_STAT.with_label_values(&["success"]).inc();
-                    return Ok(Some((price_text, href_attr.to_string())));
-                }
+        let title = match product.select(&title_selector).next() {
+            Some(t) => t,
+            None => {
+                log::trace!("fail to select title");
+                continue;
+            }
+        };
+        let title_text = title.text().collect::<String>();
+        let href = title.value().attr("href").unwrap_or_default();
+
+        let price = match product
+            .select(&price_selector)
+            .next()
+            .and_then(|p| parse_price(&p.text().collect::<String>()))
+        {
+            Some(p) => p,
+            None => {
+                log::trace!("fail to select price for {}", title_text.trim());
+                continue;
             }
+        };
+        // the crossed-out list price is only shown during a sale
+        let regular_price = product
+            .select(&regular_price_selector)
+            .next()
+            .and_then(|p| parse_price(&p.text().collect::<String>()));
+        let (price, promo_price) = split_prices(regular_price, price);
+
+        let available = product
+            .select(&availability_selector)
+            .next()
+            .and_then(|a| parse_availability(&a.text().collect::<String>()));
+
+        products.push((
+            title_text.trim().to_string(),
+            Reference {
+                promo_price,
+                available,
+                ..Reseller::Philibert.reference(price, href.to_string())
+            },
+        ));
+    }
+    products
+}
+
+fn parse_philibert_document_by_barcode(barcode: Gtin, document: &Html) -> Option<Reference> {
+    for (title, reference) in parse_philibert_products(document) {
+        if reference
+            .url
+            .split('?')
+            .next()
+            .unwrap()
+            .contains(&barcode.to_string())
+        {
+            PHILIBERT_STAT.with_label_values(&["success"]).inc();
+            return Some(Reference {
+                product_title: Some(title),
+                ..reference
+            });
         }
     }
     PHILIBERT_STAT.with_label_values(&["fail"]).inc();
-    Ok(None)
+    None
 }
 
-pub async fn get_philibert_price_and_url(
-    name: &str,
-    barcode: Option<u64>,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    if barcode.is_some() {
-        if let Some((a, b)) = get_philibert_price_and_url_by_barcode(barcode.unwrap()).await? {
-            return Ok(Some((a, b)));
-        }
+fn parse_philibert_document(game: &SearchedGame, document: &Html) -> Option<Reference> {
+    if let Some(reference) = best_match(game, parse_philibert_products(document)) {
+        PHILIBERT_STAT.with_label_values(&["success"]).inc();
+        return Some(reference);
     }
-    get_philibert_price_and_url_by_name(name).await
+    PHILIBERT_STAT.with_label_values(&["fail"]).inc();
+    None
 }
 
 use lazy_static::lazy_static;
@@ -114,3 +157,91 @@ lazy_static! {
     )
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_philibert_document, parse_philibert_document_by_barcode, PHILIBERT_CONTAINER,
+    };
+    use crate::{
+        game::GameKind,
+        gtin::Gtin,
+        website::{health::check_page_structure, SearchedGame},
+    };
+    use reqwest::StatusCode;
+    use std::fs;
+
+    struct Test {
+        name: String,
+        kind: GameKind,
+        result: Option<(f32, Option<f32>, String, Option<bool>)>,
+        document: String,
+    }
+
+    #[test]
+    fn test_parsing() {
+        let tests = vec![
+            // on sale, the crossed-out price is the regular one
+            Test {
+                name: "Azul".to_string(),
+                kind: GameKind::Base,
+                result: Some((
+                    39.9,
+                    Some(29.9),
+                    "https://www.philibertnet.com/fr/plan-b-games/72371-azul-3760269590878.html"
+                        .to_string(),
+                    Some(true),
+                )),
+                document: "tests/philibert/test1.html".to_string(),
+            },
+            Test {
+                name: "Dominion".to_string(),
+                kind: GameKind::Base,
+                result: Some((
+                    44.9,
+                    None,
+                    "https://www.philibertnet.com/fr/ystari/17035-dominion-3760146860114.html"
+                        .to_string(),
+                    Some(false),
+                )),
+                document: "tests/philibert/test2.html".to_string(),
+            },
+            // a product without a readable price is skipped rather than free
+            Test {
+                name: "Les Aventuriers du Rail".to_string(),
+                kind: GameKind::Base,
+                result: None,
+                document: "tests/philibert/test2.html".to_string(),
+            },
+        ];
+        for test in tests.into_iter() {
+            let doc =
+                fs::read_to_string(test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(check_page_structure(
+                "philibert",
+                StatusCode::OK,
+                &document,
+                PHILIBERT_CONTAINER
+            )
+            .is_ok());
+            assert_eq!(
+                parse_philibert_document(&SearchedGame::new(&test.name, test.kind), &document)
+                    .map(|r| (r.price, r.promo_price, r.url, r.available)),
+                test.result
+            );
+        }
+    }
+
+    #[test]
+    fn test_parsing_barcode() {
+        let doc = fs::read_to_string("tests/philibert/test1.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&doc);
+        let reference =
+            parse_philibert_document_by_barcode(Gtin::new(3760269590878).unwrap(), &document)
+                .unwrap();
+        assert_eq!((reference.price, reference.promo_price), (39.9, Some(29.9)));
+        assert_eq!(reference.product_title.as_deref(), Some("Azul"));
+    }
+}
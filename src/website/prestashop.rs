@@ -0,0 +1,514 @@
+use std::{collections::HashMap, env, fs, sync::Mutex};
+
+use anyhow::anyhow;
+use async_trait::async_trait;
+use reqwest::StatusCode;
+use scraper::{Html, Selector};
+use serde::Deserialize;
+
+use crate::{
+    game::Reference,
+    gtin::Gtin,
+    httpclient,
+    website::{
+        health::check_page_structure,
+        helper::{
+            best_match, clean_name, parse_availability, parse_date, parse_price, split_prices,
+        },
+        SearchedGame, StandardResellerTrait,
+    },
+};
+
+/// How a shop answers a search by barcode
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BarcodeSearch {
+    /// The search does not know barcodes
+    #[default]
+    Unsupported,
+    /// The barcode appears in the url of the matching product
+    Href,
+}
+
+/// Description of a PrestaShop like shop, as written in the config file
+#[derive(Debug, Clone, Deserialize)]
+pub struct PrestashopConfig {
+    /// Key in `Game.references` and name of the icon in assets/
+    pub name: String,
+    /// Search page, `{}` is replaced by the searched name or barcode
+    pub search_url: String,
+    /// Present on every search page, with or without result. Its absence
+    /// means the shop changed its HTML.
+    #[serde(default)]
+    pub container_selector: Option<String>,
+    pub product_selector: String,
+    pub title_selector: String,
+    pub href_selector: String,
+    /// Price asked, the sale price during a sale
+    pub price_selector: String,
+    /// Crossed-out list price, only shown during a sale
+    #[serde(default)]
+    pub regular_price_selector: Option<String>,
+    /// Text giving the last day of a sale
+    #[serde(default)]
+    pub promo_end_selector: Option<String>,
+    #[serde(default)]
+    pub availability_selector: Option<String>,
+    /// Strings removed from the price text before parsing it
+    #[serde(default)]
+    pub price_cleanup: Vec<String>,
+    /// Remove every special character from the searched name instead of only '&'
+    #[serde(default)]
+    pub clean_name: bool,
+    #[serde(default)]
+    pub barcode: BarcodeSearch,
+}
+
+/// Reseller scraped with the selectors of its `PrestashopConfig`
+pub struct Prestashop {
+    config: PrestashopConfig,
+    stat: IntCounterVec,
+    product_selector: Selector,
+    title_selector: Selector,
+    href_selector: Selector,
+    price_selector: Selector,
+    regular_price_selector: Option<Selector>,
+    promo_end_selector: Option<Selector>,
+    availability_selector: Option<Selector>,
+}
+
+impl Prestashop {
+    pub fn new(config: PrestashopConfig) -> Result<Prestashop, String> {
+        let parse = |selector: &str| {
+            Selector::parse(selector)
+                .map_err(|e| format!("invalid selector {} for {}: {:?}", selector, config.name, e))
+        };
+        if let Some(container) = &config.container_selector {
+            parse(container)?;
+        }
+        Ok(Prestashop {
+            stat: reseller_stat(&config.name)?,
+            product_selector: parse(&config.product_selector)?,
+            title_selector: parse(&config.title_selector)?,
+            href_selector: parse(&config.href_selector)?,
+            price_selector: parse(&config.price_selector)?,
+            regular_price_selector: config
+                .regular_price_selector
+                .as_deref()
+                .map(parse)
+                .transpose()?,
+            promo_end_selector: config
+                .promo_end_selector
+                .as_deref()
+                .map(parse)
+                .transpose()?,
+            availability_selector: config
+                .availability_selector
+                .as_deref()
+                .map(parse)
+                .transpose()?,
+            config,
+        })
+    }
+
+    fn search_url(&self, search: &str) -> String {
+        self.config.search_url.replace("{}", search)
+    }
+
+    fn check_page_structure(
+        &self,
+        status: StatusCode,
+        document: &Html,
+    ) -> Result<(), anyhow::Error> {
+        match &self.config.container_selector {
+            Some(container) => check_page_structure(&self.config.name, status, document, container),
+            None if !status.is_success() => {
+                Err(anyhow!("{} answered {}", self.config.name, status))
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn parse_price(&self, text: &str) -> Option<f32> {
+        let text = self
+            .config
+            .price_cleanup
+            .iter()
+            .fold(text.to_string(), |text, noise| {
+                text.replace(noise.as_str(), "")
+            });
+        parse_price(&text)
+    }
+
+    /// Returns the title and the reference of every product of the result list
+    fn parse_products(&self, document: &Html) -> Vec<(String, Reference)> {
+        let mut products = vec![];
+        for product in document.select(&self.product_selector) {
+            let href = match product
+                .select(&self.href_selector)
+                .next()
+                .and_then(|h| h.value().attr("href"))
+            {
+                Some(h) => h,
+                None => {
+                    log::trace!("{}: fail to select href", self.config.name);
+                    continue;
+                }
+            };
+            let title = match product.select(&self.title_selector).next() {
+                Some(t) => t.text().collect::<String>().trim().to_string(),
+                None => {
+                    log::trace!("{}: fail to select title", self.config.name);
+                    continue;
+                }
+            };
+            let price = match product
+                .select(&self.price_selector)
+                .next()
+                .and_then(|p| self.parse_price(&p.text().collect::<String>()))
+            {
+                Some(p) => p,
+                None => {
+                    log::trace!("{}: fail to select price for {}", self.config.name, title);
+                    continue;
+                }
+            };
+            let text_of = |selector: &Option<Selector>| {
+                selector
+                    .as_ref()
+                    .and_then(|selector| product.select(selector).next())
+                    .map(|element| element.text().collect::<String>())
+            };
+            let regular_price =
+                text_of(&self.regular_price_selector).and_then(|text| self.parse_price(&text));
+            let (price, promo_price) = split_prices(regular_price, price);
+            let promo_end = promo_price
+                .and(text_of(&self.promo_end_selector))
+                .and_then(|text| parse_date(&text));
+            let available =
+                text_of(&self.availability_selector).and_then(|text| parse_availability(&text));
+
+            products.push((
+                title,
+                Reference {
+                    name: self.config.name.clone(),
+                    price,
+                    promo_price,
+                    promo_end,
+                    url: href.to_string(),
+                    available,
+                    ..Default::default()
+                },
+            ));
+        }
+        products
+    }
+
+    fn parse_document_by_barcode(&self, barcode: Gtin, document: &Html) -> Option<Reference> {
+        let products = self.parse_products(document);
+        let product = match self.config.barcode {
+            BarcodeSearch::Unsupported => None,
+            BarcodeSearch::Href => products
+                .into_iter()
+                .find(|(_, reference)| reference.url.contains(&barcode.to_string())),
+        };
+        let reference = product.map(|(title, reference)| Reference {
+            product_title: Some(title),
+            ..reference
+        });
+        self.count(reference.is_some());
+        reference
+    }
+
+    fn parse_document_by_name(&self, game: &SearchedGame, document: &Html) -> Option<Reference> {
+        log::trace!("parsing {} document for {}", self.config.name, game.name);
+        let reference = best_match(game, self.parse_products(document));
+        self.count(reference.is_some());
+        reference
+    }
+
+    fn count(&self, success: bool) {
+        self.stat
+            .with_label_values(&[if success { "success" } else { "fail" }])
+            .inc();
+    }
+}
+
+#[async_trait]
+impl StandardResellerTrait for Prestashop {
+    fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    async fn get_references_by_barcode(
+        &self,
+        barcode: Gtin,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        if self.config.barcode == BarcodeSearch::Unsupported {
+            return Ok(vec![]);
+        }
+        log::debug!("search on {} by barcode: {}", self.config.name, barcode);
+        let (document, status) =
+            httpclient::get_doc(&self.search_url(&barcode.to_string())).await?;
+        self.check_page_structure(status, &document)?;
+
+        Ok(self
+            .parse_document_by_barcode(barcode, &document)
+            .into_iter()
+            .collect())
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let name = game.name.as_str();
+        let name_clean = if self.config.clean_name {
+            clean_name(name)
+        } else {
+            name.replace('&', " ")
+        };
+        log::debug!(
+            "search on {}: {} , cleaned_name : {}",
+            self.config.name,
+            name,
+            name_clean
+        );
+        let (document, status) = httpclient::get_doc(&self.search_url(&name_clean)).await?;
+        self.check_page_structure(status, &document)?;
+
+        Ok(self.parse_document(game, &document))
+    }
+
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference> {
+        self.parse_document_by_name(game, document)
+            .into_iter()
+            .collect()
+    }
+}
+
+fn parse_prestashop_configs(content: &str) -> Result<Vec<PrestashopConfig>, serde_json::Error> {
+    serde_json::from_str(content)
+}
+
+/// Build the resellers described in PRESTASHOP_FILE, invalid entries are skipped
+pub fn load_prestashop_resellers() -> Vec<Prestashop> {
+    let filename = env::var("PRESTASHOP_FILE").unwrap_or("prestashop.json".to_string());
+    let configs = match fs::read_to_string(&filename)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse_prestashop_configs(&content).map_err(|e| e.to_string()))
+    {
+        Ok(configs) => configs,
+        Err(e) => {
+            log::error!("Cannot read prestashop resellers from {}: {}", filename, e);
+            return vec![];
+        }
+    };
+
+    configs
+        .into_iter()
+        .filter_map(|config| match Prestashop::new(config) {
+            Ok(reseller) => Some(reseller),
+            Err(e) => {
+                log::error!("{}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// `<name>_stat` counter of a shop, the one each shop had before being described in the config
+fn reseller_stat(name: &str) -> Result<IntCounterVec, String> {
+    let mut stats = PRESTASHOP_STATS.lock().unwrap();
+    if let Some(stat) = stats.get(name) {
+        return Ok(stat.clone());
+    }
+    let stat = register_int_counter_vec!(
+        format!("{}_stat", name),
+        "Stat about parsing/fetch success/fail for this website",
+        &["result"]
+    )
+    .map_err(|e| format!("cannot register the stat of {}: {}", name, e))?;
+    stats.insert(name.to_string(), stat.clone());
+    Ok(stat)
+}
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    /// Counters already registered, the resellers may be built more than once
+    static ref PRESTASHOP_STATS: Mutex<HashMap<String, IntCounterVec>> = Mutex::new(HashMap::new());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_prestashop_configs, Prestashop};
+    use crate::{game::GameKind, gtin::Gtin, website::SearchedGame};
+    use log::Level;
+    use reqwest::StatusCode;
+    use std::{env, fs};
+
+    #[derive(Debug, PartialEq)]
+    struct Expected {
+        price: f32,
+        promo_price: Option<f32>,
+        url: String,
+        available: Option<bool>,
+    }
+
+    struct Test {
+        reseller: &'static str,
+        document: &'static str,
+        name: Option<&'static str>,
+        barcode: Option<u64>,
+        result: Option<Expected>,
+    }
+
+    #[test]
+    fn test_parsing() {
+        env::set_var("RUST_LOG", "boardgame_finder=trace");
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or(Level::Info.as_str()),
+        )
+        .try_init();
+
+        let content = fs::read_to_string("prestashop.json")
+            .expect("Should have been able to read the config");
+        let resellers: Vec<Prestashop> = parse_prestashop_configs(&content)
+            .unwrap()
+            .into_iter()
+            .map(|config| Prestashop::new(config).unwrap())
+            .collect();
+
+        let tests = vec![
+            Test {
+                reseller: "agorajeux",
+                document: "tests/agorajeux/test1.html",
+                name: Some("Break In - Tour Eiffel"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 26.5,
+                    promo_price: None,
+                    url: "https://www.agorajeux.com/fr/jeux-d-enquetes/14624-break-in-tour-eiffel.html".to_string(),
+                    available: Some(false),
+                }),
+            },
+            Test {
+                reseller: "agorajeux",
+                document: "tests/agorajeux/test2.html",
+                name: Some("My Little Scythe - Le Gâteau Dans Le Ciel"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 24.9,
+                    promo_price: Some(22.41),
+                    url: "https://www.agorajeux.com/fr/les-jeux-pour-toute-la-famille/11063-my-little-scythe-le-gateau-dans-le-ciel.html".to_string(),
+                    available: Some(false),
+                }),
+            },
+            Test {
+                reseller: "agorajeux",
+                document: "tests/agorajeux/test3.html",
+                name: Some("Quarto! Mini"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 23.9,
+                    promo_price: None,
+                    url: "https://www.agorajeux.com/fr/jeux-gigamic/3047-quarto-mini.html".to_string(),
+                    available: Some(true),
+                }),
+            },
+            Test {
+                reseller: "agorajeux",
+                document: "tests/agorajeux/test4.html",
+                name: Some("Death Note Le Jeu D'enquête"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 28.0,
+                    promo_price: Some(24.9),
+                    url: "https://www.agorajeux.com/fr/jeux-d-enquetes/13407-death-note-le-jeu-d-enquete.html".to_string(),
+                    available: Some(false),
+                }),
+            },
+            Test {
+                reseller: "ludifolie",
+                document: "tests/ludifolie/test1.html",
+                name: Some("Azul"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 35.9,
+                    promo_price: None,
+                    url: "https://www.ludifolie.com/jeux-de-strategie/1984-azul.html".to_string(),
+                    available: Some(true),
+                }),
+            },
+            Test {
+                reseller: "ludifolie",
+                document: "tests/ludifolie/test2.html",
+                name: Some("Dominion"),
+                barcode: None,
+                result: None,
+            },
+            Test {
+                reseller: "ludocortex",
+                document: "tests/ludocortex/test1.html",
+                name: Some("Les Châteaux de Bourgogne"),
+                barcode: None,
+                result: Some(Expected {
+                    price: 44.9,
+                    promo_price: Some(40.41),
+                    url: "https://www.ludocortex.fr/jeux-experts/8812-3770002176276-les-chateaux-de-bourgogne.html".to_string(),
+                    available: Some(true),
+                }),
+            },
+            Test {
+                reseller: "ludocortex",
+                document: "tests/ludocortex/test1.html",
+                name: None,
+                barcode: Some(4015566603332),
+                result: Some(Expected {
+                    price: 49.9,
+                    promo_price: None,
+                    url: "https://www.ludocortex.fr/jeux-experts/9120-4015566603332-les-chateaux-de-bourgogne-edition-20-ans.html".to_string(),
+                    available: Some(false),
+                }),
+            },
+        ];
+
+        for reseller in &resellers {
+            assert!(
+                tests.iter().any(|t| t.reseller == reseller.config.name),
+                "no test for {}",
+                reseller.config.name
+            );
+        }
+        for test in tests {
+            let reseller = resellers
+                .iter()
+                .find(|r| r.config.name == test.reseller)
+                .expect("reseller missing from prestashop.json");
+            let doc =
+                fs::read_to_string(test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            reseller
+                .check_page_structure(StatusCode::OK, &document)
+                .unwrap();
+            let reference = match (test.barcode.and_then(Gtin::new), test.name) {
+                (Some(barcode), _) => reseller.parse_document_by_barcode(barcode, &document),
+                (None, Some(name)) => reseller
+                    .parse_document_by_name(&SearchedGame::new(name, GameKind::Unknown), &document),
+                (None, None) => panic!("test {} searches nothing", test.document),
+            };
+            assert_eq!(
+                reference.map(|r| Expected {
+                    price: r.price,
+                    promo_price: r.promo_price,
+                    url: r.url,
+                    available: r.available,
+                }),
+                test.result,
+                "{}",
+                test.document
+            );
+        }
+    }
+}
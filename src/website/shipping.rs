@@ -0,0 +1,98 @@
+use std::{collections::HashMap, env, fs};
+
+use lazy_static::lazy_static;
+use serde::Deserialize;
+
+/// How a reseller charges the delivery of a single game
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ShippingRule {
+    /// Same fee whatever the price
+    Flat { fee: f32 },
+    /// Fee charged below `threshold`, delivery is free above
+    FreeAbove { fee: f32, threshold: f32 },
+    /// No delivery, the game has to be picked up in the shop
+    Pickup,
+}
+
+impl ShippingRule {
+    pub fn landed_price(&self, price: f32) -> f32 {
+        match self {
+            ShippingRule::Flat { fee } => price + fee,
+            ShippingRule::FreeAbove { fee, threshold } => {
+                if price >= *threshold {
+                    price
+                } else {
+                    price + fee
+                }
+            }
+            ShippingRule::Pickup => price,
+        }
+    }
+}
+
+/// Price paid to get the game from `reseller`, the shelf price if we have no rule for it
+pub fn landed_price(reseller: &str, price: f32) -> f32 {
+    match SHIPPING_RULES.get(reseller) {
+        Some(rule) => rule.landed_price(price),
+        None => price,
+    }
+}
+
+fn load_shipping_rules() -> HashMap<String, ShippingRule> {
+    let filename = env::var("SHIPPING_RULES_FILE").unwrap_or("shipping.json".to_string());
+    match fs::read_to_string(&filename)
+        .map_err(|e| e.to_string())
+        .and_then(|content| parse_shipping_rules(&content).map_err(|e| e.to_string()))
+    {
+        Ok(rules) => {
+            log::debug!("loaded {} shipping rules from {}", rules.len(), filename);
+            rules
+        }
+        Err(e) => {
+            log::error!(
+                "Cannot read shipping rules from {}, shelf prices are used as landed prices: {}",
+                filename,
+                e
+            );
+            HashMap::new()
+        }
+    }
+}
+
+fn parse_shipping_rules(content: &str) -> Result<HashMap<String, ShippingRule>, serde_json::Error> {
+    serde_json::from_str(content)
+}
+
+lazy_static! {
+    static ref SHIPPING_RULES: HashMap<String, ShippingRule> = load_shipping_rules();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_shipping_rules, ShippingRule};
+
+    #[test]
+    fn test_landed_price() {
+        let rules = parse_shipping_rules(
+            r#"{
+                "philibert": { "kind": "free_above", "fee": 6.9, "threshold": 60.0 },
+                "agorajeux": { "kind": "flat", "fee": 5.5 },
+                "ludocortex": { "kind": "pickup" }
+            }"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            rules["philibert"],
+            ShippingRule::FreeAbove {
+                fee: 6.9,
+                threshold: 60.0
+            }
+        );
+        assert_eq!(rules["philibert"].landed_price(30.0), 36.9);
+        assert_eq!(rules["philibert"].landed_price(60.0), 60.0);
+        assert_eq!(rules["agorajeux"].landed_price(70.0), 75.5);
+        assert_eq!(rules["ludocortex"].landed_price(20.0), 20.0);
+    }
+}
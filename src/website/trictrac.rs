@@ -0,0 +1,259 @@
+use scraper::{Html, Selector};
+
+use crate::{
+    game::Reviewer,
+    httpclient,
+    website::{
+        health::check_page_structure,
+        helper::{clean_name, match_game},
+        SearchedGame,
+    },
+};
+
+const TRICTRAC_URL: &str = "https://www.trictrac.net";
+/// Present on every search page, with or without result
+const TRICTRAC_SEARCH_CONTAINER: &str = "div.search-results";
+/// Present on every game page, rated or not
+const TRICTRAC_GAME_CONTAINER: &str = "div.game-header";
+
+/// Rating of the french community, found by searching the name of the game
+/// then its aliases. `pinned_url` is the game page chosen by an admin.
+pub async fn get_trictrac_note(
+    game: &SearchedGame,
+    pinned_url: Option<&str>,
+) -> Result<Option<Reviewer>, anyhow::Error> {
+    let url = match pinned_url {
+        Some(url) => url.to_string(),
+        None => match search_trictrac_game(game).await? {
+            Some(url) => url,
+            None => {
+                TRICTRAC_STAT.with_label_values(&["not_found"]).inc();
+                return Ok(None);
+            }
+        },
+    };
+
+    log::debug!("getting trictrac note: {}", url);
+    let (document, status) = httpclient::get_doc(&url).await?;
+    check_page_structure("trictrac", status, &document, TRICTRAC_GAME_CONTAINER)?;
+    let reviewer = parse_trictrac_game(&url, &document);
+    TRICTRAC_STAT
+        .with_label_values(&[if reviewer.is_some() {
+            "success"
+        } else {
+            "not_rated"
+        }])
+        .inc();
+    Ok(reviewer)
+}
+
+/// Page of the game whose title matches best the name or one of the aliases
+async fn search_trictrac_game(game: &SearchedGame) -> Result<Option<String>, anyhow::Error> {
+    for name in std::iter::once(&game.name).chain(&game.aliases) {
+        let search = format!(
+            "{}/recherche?search={}",
+            TRICTRAC_URL,
+            clean_name(name).trim().replace(' ', "+")
+        );
+        log::debug!("searching trictrac {}", search);
+        let url = {
+            let (document, status) = httpclient::get_doc(&search).await?;
+            check_page_structure("trictrac", status, &document, TRICTRAC_SEARCH_CONTAINER)?;
+            parse_trictrac_search(&SearchedGame::new(name, game.kind), &document)
+        };
+        if url.is_some() {
+            return Ok(url);
+        }
+    }
+    Ok(None)
+}
+
+/// Game pages of the search results, with the best matching title
+fn parse_trictrac_search(game: &SearchedGame, document: &Html) -> Option<String> {
+    let result_selector = Selector::parse("a.search-result[href]").unwrap();
+    let title_selector = Selector::parse(".search-result-title").unwrap();
+
+    let mut best: Option<(f32, String)> = None;
+    for result in document.select(&result_selector) {
+        let href = result.value().attr("href").unwrap_or_default();
+        // results also list articles, videos and forum threads
+        if !href.contains("/jeu-de-societe/") {
+            continue;
+        }
+        let title = match result.select(&title_selector).next() {
+            Some(t) => t.text().collect::<String>(),
+            None => continue,
+        };
+        let name_match = match_game(&game.name, game.kind, title.trim());
+        let score = name_match.score;
+        log::trace!(
+            "trictrac result {} scored {} for {}",
+            title.trim(),
+            score,
+            game.name
+        );
+        if name_match.is_match() && best.as_ref().is_none_or(|(s, _)| score > *s) {
+            best = Some((score, href.to_string()));
+        }
+    }
+    best.map(|(_, href)| match href.starts_with("http") {
+        true => href,
+        false => format!("{}{}", TRICTRAC_URL, href),
+    })
+}
+
+/// Community rating of a game page, scaled to 10 like the bgg one.
+/// None when nobody rated the game.
+fn parse_trictrac_game(url: &str, document: &Html) -> Option<Reviewer> {
+    let value_selector = Selector::parse(".game-rating .rating-value").unwrap();
+    let scale_selector = Selector::parse(".game-rating .rating-scale").unwrap();
+    let count_selector = Selector::parse(".game-rating .rating-count").unwrap();
+
+    let text = |selector: &Selector| {
+        document
+            .select(selector)
+            .next()
+            .map(|e| e.text().collect::<String>().trim().to_string())
+    };
+
+    let note = text(&value_selector)?
+        .replace(',', ".")
+        .parse::<f32>()
+        .ok()?;
+    // "/5" on the newest pages, "/10" on the older ones
+    let scale = text(&scale_selector)
+        .and_then(|s| s.trim_start_matches('/').trim().parse::<f32>().ok())
+        .unwrap_or(10.0);
+    let number = text(&count_selector)?
+        .chars()
+        .filter(|c| c.is_ascii_digit())
+        .collect::<String>()
+        .parse::<u32>()
+        .ok()?;
+    if number == 0 || scale <= 0.0 {
+        return None;
+    }
+
+    Some(Reviewer {
+        name: "trictrac".to_string(),
+        url: url.to_string(),
+        note: note * 10.0 / scale,
+        number,
+    })
+}
+
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+lazy_static! {
+    static ref TRICTRAC_STAT: IntCounterVec = register_int_counter_vec!(
+        "trictrac_stat",
+        "Stat about trictrac lookups (success, not_found, not_rated)",
+        &["result"]
+    )
+    .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::StatusCode;
+    use std::fs;
+
+    use super::{
+        parse_trictrac_game, parse_trictrac_search, TRICTRAC_GAME_CONTAINER,
+        TRICTRAC_SEARCH_CONTAINER,
+    };
+    use crate::{
+        game::GameKind,
+        website::{health::check_page_structure, SearchedGame},
+    };
+
+    struct Test {
+        name: String,
+        kind: GameKind,
+        document: String,
+        result: Option<String>,
+    }
+
+    #[test]
+    fn test_parsing_search() {
+        let tests = vec![
+            Test {
+                name: "Azul".to_string(),
+                kind: GameKind::Base,
+                document: "tests/trictrac/search1.html".to_string(),
+                result: Some("https://www.trictrac.net/jeu-de-societe/azul".to_string()),
+            },
+            // the expansion is listed first
+            Test {
+                name: "Les Aventuriers du Rail".to_string(),
+                kind: GameKind::Base,
+                document: "tests/trictrac/search2.html".to_string(),
+                result: Some(
+                    "https://www.trictrac.net/jeu-de-societe/les-aventuriers-du-rail".to_string(),
+                ),
+            },
+            Test {
+                name: "Les Aventuriers du Rail Europe 1912".to_string(),
+                kind: GameKind::Expansion,
+                document: "tests/trictrac/search2.html".to_string(),
+                result: Some(
+                    "https://www.trictrac.net/jeu-de-societe/les-aventuriers-du-rail-europe-1912"
+                        .to_string(),
+                ),
+            },
+            // only articles and another game
+            Test {
+                name: "Wingspan".to_string(),
+                kind: GameKind::Base,
+                document: "tests/trictrac/search3.html".to_string(),
+                result: None,
+            },
+        ];
+        for test in tests {
+            let doc =
+                fs::read_to_string(&test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(check_page_structure(
+                "trictrac",
+                StatusCode::OK,
+                &document,
+                TRICTRAC_SEARCH_CONTAINER
+            )
+            .is_ok());
+            assert_eq!(
+                parse_trictrac_search(&SearchedGame::new(&test.name, test.kind), &document),
+                test.result,
+                "{}",
+                test.name
+            );
+        }
+    }
+
+    #[test]
+    fn test_parsing_game() {
+        let tests = vec![
+            ("tests/trictrac/game1.html", Some((8.4, 1234))),
+            // rated on 5
+            ("tests/trictrac/game2.html", Some((8.6, 57))),
+            // nobody rated it yet
+            ("tests/trictrac/game3.html", None),
+        ];
+        for (document, result) in tests {
+            let doc = fs::read_to_string(document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(check_page_structure(
+                "trictrac",
+                StatusCode::OK,
+                &document,
+                TRICTRAC_GAME_CONTAINER
+            )
+            .is_ok());
+            let reviewer =
+                parse_trictrac_game("https://www.trictrac.net/jeu-de-societe/x", &document);
+            assert_eq!(
+                reviewer.map(|r| ((r.note * 100.0).round() / 100.0, r.number)),
+                result
+            );
+        }
+    }
+}
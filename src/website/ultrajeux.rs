@@ -1,64 +1,145 @@
-use regex::Regex;
-
-use crate::website::helper::clean_name;
-
-pub async fn get_ultrajeux_price_and_url_by_barcode(
-    barcode: u64,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    let search = format!(
-        "https://www.ultrajeux.com/search3.php?text={}&submit=Ok",
-        barcode
-    );
-    log::debug!("search on ultrajeux by barcode: {}", barcode);
-    let content = httpclient::get(&search).await?.bytes().await?;
-    let re = Regex::new(r#"produit_prix.*?class="prix.*?([\d,]+) "#).unwrap();
-    let content_str: &str = &String::from_utf8_lossy(&content);
-    for capture in re.captures_iter(content_str) {
-        if let Some(value) = capture.get(1) {
-            let number: f32 = value.as_str().replace(',', ".").parse().unwrap();
-            ULTRAJEUX_STAT.with_label_values(&["success"]).inc();
-            return Ok(Some((number, search)));
-        }
+use async_trait::async_trait;
+use scraper::{Html, Selector};
+
+use crate::{
+    game::Reference,
+    gtin::Gtin,
+    httpclient,
+    website::{
+        health::check_page_structure,
+        helper::{best_match, clean_name, parse_availability, parse_price},
+        Reseller, SearchedGame, StandardResellerTrait,
+    },
+};
+
+pub struct Ultrajeux;
+
+/// Present on every search page, with or without result
+const ULTRAJEUX_CONTAINER: &str = "#contenu .nb_resultat";
+
+#[async_trait]
+impl StandardResellerTrait for Ultrajeux {
+    fn name(&self) -> &str {
+        Reseller::Ultrajeux.name()
+    }
+
+    async fn get_references_by_barcode(
+        &self,
+        barcode: Gtin,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let search = format!(
+            "https://www.ultrajeux.com/search3.php?text={}&submit=Ok",
+            barcode
+        );
+        log::debug!("search on ultrajeux by barcode: {}", barcode);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, ULTRAJEUX_CONTAINER)?;
+
+        Ok(parse_ultrajeux_document_by_barcode(&document)
+            .into_iter()
+            .collect())
+    }
+
+    async fn get_references_by_name(
+        &self,
+        game: &SearchedGame,
+    ) -> Result<Vec<Reference>, anyhow::Error> {
+        let name = game.name.as_str();
+        let search = format!(
+            "https://www.ultrajeux.com/search3.php?text={}&submit=Ok",
+            clean_name(name)
+        );
+        log::debug!("search on ultrajeux by name: {}", name);
+        let (document, status) = httpclient::get_doc(&search).await?;
+        check_page_structure(self.name(), status, &document, ULTRAJEUX_CONTAINER)?;
+
+        Ok(self.parse_document(game, &document))
+    }
+
+    fn parse_document(&self, game: &SearchedGame, document: &Html) -> Vec<Reference> {
+        parse_ultrajeux_document(game, document)
+            .into_iter()
+            .collect()
     }
-    ULTRAJEUX_STAT.with_label_values(&["fail"]).inc();
-    Ok(None)
 }
 
-pub async fn get_ultrajeux_price_and_url_by_name(
-    name: &str,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    let search = format!(
-        "https://www.ultrajeux.com/search3.php?text={}&submit=Ok",
-        clean_name(name)
-    );
-    log::debug!("search on ultrajeux by name: {}", name);
-    let content = httpclient::get(&search).await?.bytes().await?;
-    let re = Regex::new(r#"produit_prix.*?class="prix.*?([\d,]+) "#).unwrap();
-    let content_str: &str = &String::from_utf8_lossy(&content);
-    for capture in re.captures_iter(content_str) {
-        if let Some(value) = capture.get(1) {
-            let number: f32 = value.as_str().replace(',', ".").parse().unwrap();
-            ULTRAJEUX_STAT.with_label_values(&["success"]).inc();
-            return Ok(Some((number, search)));
-        }
+/// Returns the title and the reference of every product of the result list
+fn parse_ultrajeux_products(document: &Html) -> Vec<(String, Reference)> {
+    let product_selector = Selector::parse("div.bloc_produit").unwrap();
+    let title_selector = Selector::parse("p.titre a").unwrap();
+    let price_selector = Selector::parse(".produit_prix .prix").unwrap();
+    let availability_selector = Selector::parse(".produit_prix .stock").unwrap();
+
+    let mut products = vec![];
+    for product in document.select(&product_selector) {
+        let title = match product.select(&title_selector).next() {
+            Some(t) => t,
+            None => {
+                log::trace!("fail to select title");
+                continue;
+            }
+        };
+        let title_text = title.text().collect::<String>();
+        let href = title.value().attr("href").unwrap_or_default();
+        // links of the result list are relative to the website root
+        let href = if href.starts_with('/') {
+            format!("https://www.ultrajeux.com{}", href)
+        } else {
+            href.to_string()
+        };
+
+        let price = match product
+            .select(&price_selector)
+            .next()
+            .and_then(|p| parse_price(&p.text().collect::<String>()))
+        {
+            Some(p) => p,
+            None => {
+                log::trace!("fail to select price for {}", title_text.trim());
+                continue;
+            }
+        };
+
+        let available = product
+            .select(&availability_selector)
+            .next()
+            .and_then(|a| parse_availability(&a.text().collect::<String>()));
+
+        products.push((
+            title_text.trim().to_string(),
+            Reference {
+                available,
+                ..Reseller::Ultrajeux.reference(price, href)
+            },
+        ));
+    }
+    products
+}
+
+/// Barcodes are not shown in the result list, a barcode search is only
+/// trusted when it gives a single product
+fn parse_ultrajeux_document_by_barcode(document: &Html) -> Option<Reference> {
+    let mut products = parse_ultrajeux_products(document);
+    if products.len() == 1 {
+        // the single product may be another game, no alias is learned from its title
+        let (_, reference) = products.remove(0);
+        ULTRAJEUX_STAT.with_label_values(&["success"]).inc();
+        return Some(reference);
     }
     ULTRAJEUX_STAT.with_label_values(&["fail"]).inc();
-    Ok(None)
+    None
 }
 
-pub async fn get_ultrajeux_price_and_url(
-    name: &str,
-    barcode: Option<u64>,
-) -> Result<Option<(f32, String)>, anyhow::Error> {
-    if barcode.is_some() {
-        if let Some((a, b)) = get_ultrajeux_price_and_url_by_barcode(barcode.unwrap()).await? {
-            return Ok(Some((a, b)));
-        }
+fn parse_ultrajeux_document(game: &SearchedGame, document: &Html) -> Option<Reference> {
+    log::trace!("parsing ultrajeux document for {}", game.name);
+    if let Some(reference) = best_match(game, parse_ultrajeux_products(document)) {
+        ULTRAJEUX_STAT.with_label_values(&["success"]).inc();
+        return Some(reference);
     }
-    get_ultrajeux_price_and_url_by_name(name).await
+    ULTRAJEUX_STAT.with_label_values(&["fail"]).inc();
+    None
 }
 
-use crate::httpclient;
 use lazy_static::lazy_static;
 use prometheus::{register_int_counter_vec, IntCounterVec};
 lazy_static! {
@@ -69,3 +150,108 @@ lazy_static! {
     )
     .unwrap();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        parse_ultrajeux_document, parse_ultrajeux_document_by_barcode, ULTRAJEUX_CONTAINER,
+    };
+    use crate::{
+        game::GameKind,
+        website::{health::check_page_structure, SearchedGame},
+    };
+    use log::Level;
+    use reqwest::StatusCode;
+    use std::{env, fs};
+
+    struct Test {
+        name: String,
+        kind: GameKind,
+        result: Option<(f32, String, Option<bool>)>,
+        document: String,
+    }
+
+    #[test]
+    fn test_parsing() {
+        env::set_var("RUST_LOG", "boardgame_finder=trace");
+        let _ = env_logger::Builder::from_env(
+            env_logger::Env::default().default_filter_or(Level::Info.as_str()),
+        )
+        .try_init();
+
+        let tests = vec![
+            Test {
+                name: "Azul".to_string(),
+                kind: GameKind::Base,
+                result: Some((
+                    36.9,
+                    "https://www.ultrajeux.com/jeu-de-societe-azul-34561.html".to_string(),
+                    Some(true),
+                )),
+                document: "tests/ultrajeux/test1.html".to_string(),
+            },
+            Test {
+                name: "Break In - Tour Eiffel".to_string(),
+                kind: GameKind::Base,
+                result: Some((
+                    26.95,
+                    "https://www.ultrajeux.com/jeu-de-societe-break-in-tour-eiffel-41208.html"
+                        .to_string(),
+                    Some(false),
+                )),
+                document: "tests/ultrajeux/test2.html".to_string(),
+            },
+            // only extensions in the result list, the first price must not be used
+            Test {
+                name: "Dominion".to_string(),
+                kind: GameKind::Base,
+                result: None,
+                document: "tests/ultrajeux/test3.html".to_string(),
+            },
+            // the announce type tells it is an expansion, the title does not
+            Test {
+                name: "Dominion Intrigue".to_string(),
+                kind: GameKind::Expansion,
+                result: Some((
+                    32.4,
+                    "https://www.ultrajeux.com/jeu-de-societe-dominion-intrigue-22101.html"
+                        .to_string(),
+                    Some(true),
+                )),
+                document: "tests/ultrajeux/test3.html".to_string(),
+            },
+        ];
+        for test in tests.into_iter() {
+            let doc =
+                fs::read_to_string(test.document).expect("Should have been able to read the file");
+            let document = scraper::Html::parse_document(&doc);
+            assert!(check_page_structure(
+                "ultrajeux",
+                StatusCode::OK,
+                &document,
+                ULTRAJEUX_CONTAINER
+            )
+            .is_ok());
+            assert_eq!(
+                parse_ultrajeux_document(&SearchedGame::new(&test.name, test.kind), &document)
+                    .map(|r| (r.price, r.url, r.available)),
+                test.result
+            );
+        }
+    }
+
+    #[test]
+    fn test_parsing_barcode() {
+        let doc = fs::read_to_string("tests/ultrajeux/test4.html")
+            .expect("Should have been able to read the file");
+        let document = scraper::Html::parse_document(&doc);
+        assert_eq!(
+            parse_ultrajeux_document_by_barcode(&document).map(|r| (r.price, r.url, r.available)),
+            Some((
+                31.5,
+                "https://www.ultrajeux.com/jeu-de-societe-quarto-mini-12877.html".to_string(),
+                Some(true)
+            ))
+        );
+    }
+}
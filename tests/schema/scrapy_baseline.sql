@@ -0,0 +1,82 @@
+-- Tables created by the scrapy.sql of the first releases, before the migrations.
+-- seller_name is declared UNIQUE here: the original script referenced it from
+-- oa_seller without any unique constraint, which postgres refuses, and the
+-- databases in use were fixed by hand that way.
+
+CREATE EXTENSION IF NOT EXISTS unaccent;
+
+CREATE TABLE "seller" (
+  "seller_id" SERIAL PRIMARY KEY,
+  "seller_name" text UNIQUE,
+  "seller_url" text,
+  "seller_nb_announces" integer,
+  "seller_is_pro" boolean
+);
+
+CREATE TABLE "okkazeo_announce" (
+  "oa_id" integer UNIQUE NOT NULL,
+  "oa_last_modification_date" timestamptz NOT NULL,
+  "oa_name" text NOT NULL,
+  "oa_image" text NOT NULL,
+  "oa_price" real NOT NULL,
+  "oa_url" text NOT NULL,
+  "oa_extension" text,
+  "oa_seller" text REFERENCES seller("seller_name"),
+  "oa_barcode" bigint,
+  "oa_city" text,
+  "oa_nbr_player" integer
+);
+
+CREATE TABLE "deal" (
+  "deal_id" SERIAL PRIMARY KEY,
+  "deal_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
+  "deal_price" integer,
+  "deal_percentage" integer
+);
+
+CREATE TABLE "reference" (
+  "ref_id" SERIAL PRIMARY KEY,
+  "ref_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
+  "ref_name" text,
+  "ref_price" real,
+  "ref_url" text,
+  "ref_available" boolean
+);
+
+CREATE TABLE "reviewer" (
+  "reviewer_id" SERIAL PRIMARY KEY,
+  "reviewer_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
+  "reviewer_name" text,
+  "reviewer_url" text,
+  "reviewer_note" real,
+  "reviewer_number" integer
+);
+
+CREATE TABLE "shipping" (
+  "ship_id" SERIAL PRIMARY KEY,
+  "ship_oa_id" integer REFERENCES okkazeo_announce("oa_id") ON DELETE CASCADE,
+  "ship_shipper" text,
+  "ship_price" real
+);
+
+CREATE INDEX idx_deal_oa_id ON deal (deal_oa_id);
+CREATE INDEX idx_oa_id ON okkazeo_announce (oa_id);
+CREATE INDEX idx_reference_oa_id ON reference (ref_oa_id);
+CREATE INDEX idx_reviewer_oa_id ON reviewer (reviewer_oa_id);
+CREATE INDEX idx_ship_oa_id ON shipping (ship_oa_id);
+CREATE INDEX idx_seller ON seller (seller_id);
+
+INSERT INTO seller (seller_name, seller_url, seller_nb_announces, seller_is_pro)
+  VALUES ('alice', 'https://www.okkazeo.com/alice', 2, false),
+         ('bob', 'https://www.okkazeo.com/bob', 1, true);
+INSERT INTO okkazeo_announce (oa_id, oa_last_modification_date, oa_name, oa_image, oa_price, oa_url, oa_seller, oa_barcode)
+  VALUES (1, now(), 'Azul', 'azul.jpg', 20, 'https://www.okkazeo.com/1', 'alice', 3760269590090),
+         (2, now(), 'Splendor', 'splendor.jpg', 15, 'https://www.okkazeo.com/2', 'alice', NULL),
+         (3, now(), 'Dixit', 'dixit.jpg', 12, 'https://www.okkazeo.com/3', 'bob', 0);
+INSERT INTO deal (deal_oa_id, deal_price, deal_percentage) VALUES (1, 10, 33);
+INSERT INTO reference (ref_oa_id, ref_name, ref_price, ref_url, ref_available)
+  VALUES (1, 'philibert', 30, 'https://www.philibert.com/azul', true);
+INSERT INTO reviewer (reviewer_oa_id, reviewer_name, reviewer_url, reviewer_note, reviewer_number)
+  VALUES (1, 'bgg', 'https://boardgamegeek.com/azul', 7.8, 1000);
+INSERT INTO shipping (ship_oa_id, ship_shipper, ship_price)
+  VALUES (1, 'colissimo', 5), (1, 'colissimo', 6), (2, 'hand', 0);